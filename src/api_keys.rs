@@ -0,0 +1,292 @@
+//! Personal API keys for scripts. A key is sent as `Authorization: Bearer
+//! qk_...` and only opens the routes its scopes cover. Keys are stored as
+//! SHA-256 hashes; the `qk_` prefix and the next characters identify a key
+//! in listings without revealing it.
+//!
+//! The routes keys can use also accept a session token. Handlers take a
+//! [`Caller`] and check that the ids they act on belong to it.
+
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use mongodb::Database;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use crate::error::ApiError;
+use crate::helpers::authenticate_api_key;
+use crate::jwt_utils::Session;
+use crate::model::{ApiKey, ApiScope};
+use crate::tokens::hash_token;
+
+const KEY_PREFIX: &str = "qk_";
+const PREFIX_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 40;
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 6] = [
+        ApiScope::QuizGenerate,
+        ApiScope::QuizRead,
+        ApiScope::QuizWrite,
+        ApiScope::DeckGenerate,
+        ApiScope::DeckRead,
+        ApiScope::DeckWrite,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiScope::QuizGenerate => "quiz:generate",
+            ApiScope::QuizRead => "quiz:read",
+            ApiScope::QuizWrite => "quiz:write",
+            ApiScope::DeckGenerate => "deck:generate",
+            ApiScope::DeckRead => "deck:read",
+            ApiScope::DeckWrite => "deck:write",
+        }
+    }
+
+    /// Scopes from a comma-separated list such as `quiz:generate,quiz:read`.
+    pub fn parse_list(list: &str) -> Result<Vec<ApiScope>, String> {
+        let mut scopes = Vec::new();
+        for name in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let Some(scope) = ApiScope::ALL.into_iter().find(|s| s.name() == name) else {
+                return Err(format!("Unknown scope {}", name));
+            };
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        Ok(scopes)
+    }
+
+    /// The scope a route under `/api` needs, or `None` for routes keys
+    /// cannot use, such as account and admin routes.
+    pub fn for_path(path: &str) -> Option<ApiScope> {
+        let route = path.trim_start_matches("/api/").split('/').next()?;
+        match route {
+            "generate_quiz" => Some(ApiScope::QuizGenerate),
+            "export_quiz" | "questions" | "exam_pdf" | "exam_key_pdf" | "attempts" => {
+                Some(ApiScope::QuizRead)
+            }
+            "create_quiz" | "publish_quiz" | "import_questions" => Some(ApiScope::QuizWrite),
+            "generate_flashcard" => Some(ApiScope::DeckGenerate),
+            "decks" | "export_deck" => Some(ApiScope::DeckRead),
+            "create_deck" | "edit_deck_card" | "remove_deck_card" | "share_deck" | "fork_deck"
+            | "import_deck" | "create_flash" => Some(ApiScope::DeckWrite),
+            _ => None,
+        }
+    }
+}
+
+fn random_chars(count: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(count)
+        .map(char::from)
+        .collect()
+}
+
+/// A new key with its public prefix and the hash to store.
+pub fn new_api_key() -> (String, String, String) {
+    let prefix = format!("{}{}", KEY_PREFIX, random_chars(PREFIX_LENGTH));
+    let key = format!("{}_{}", prefix, random_chars(SECRET_LENGTH));
+    let hash = hash_token(&key);
+    (key, prefix, hash)
+}
+
+/// The API key in an `Authorization` header, if it carries one.
+pub fn bearer_key(authorization: &str) -> Option<&str> {
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+    let credentials = credentials.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && credentials.starts_with(KEY_PREFIX))
+        .then_some(credentials)
+}
+
+/// Checks API keys on the routes it wraps. Requests without a key pass
+/// through unchanged; requests with one get the key in their extensions,
+/// where [`Caller`] finds it.
+pub struct ApiKeyAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ApiKeyAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyAuthMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ApiKeyAuthMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let key = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|h| h.to_str().ok())
+                .and_then(bearer_key)
+                .map(str::to_string);
+            let Some(key) = key else {
+                return service.call(req).await.map(|r| r.map_into_left_body());
+            };
+
+            let checked = match req.app_data::<web::Data<Database>>() {
+                Some(db) => check_key(db, &key, req.path()).await,
+                None => Err(ApiError::Internal("Database is not available".to_string())),
+            };
+            match checked {
+                Ok(api_key) => {
+                    req.extensions_mut().insert(api_key);
+                    service.call(req).await.map(|r| r.map_into_left_body())
+                }
+                Err(error) => Ok(req
+                    .into_response(error.error_response())
+                    .map_into_right_body()),
+            }
+        })
+    }
+}
+
+/// Who is making a request: the owner of the API key it carries, or else
+/// the signed-in user.
+pub struct Caller {
+    user_id: String,
+}
+
+impl Caller {
+    pub fn new(user_id: &str) -> Self {
+        Caller {
+            user_id: user_id.to_string(),
+        }
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// Refuses requests that act for a user other than the caller.
+    pub fn check(&self, user_id: &str) -> Result<(), ApiError> {
+        if self.user_id == user_id {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(
+                "You can only act on your own account".to_string(),
+            ))
+        }
+    }
+}
+
+impl FromRequest for Caller {
+    type Error = ApiError;
+    type Future = Ready<Result<Caller, ApiError>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if let Some(api_key) = req.extensions().get::<ApiKey>() {
+            return ready(Ok(Caller::new(&api_key.user_id)));
+        }
+        let caller = Session::from_request(req, payload)
+            .into_inner()
+            .map(|session| Caller::new(session.user_id()));
+        ready(caller)
+    }
+}
+
+async fn check_key(db: &Database, key: &str, path: &str) -> Result<ApiKey, ApiError> {
+    let Some(scope) = ApiScope::for_path(path) else {
+        return Err(ApiError::Forbidden(
+            "This route cannot be used with an API key".to_string(),
+        ));
+    };
+    let api_key = match authenticate_api_key(db, key).await {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            return Err(ApiError::Unauthorized(
+                "API key is invalid or revoked".to_string(),
+            ))
+        }
+        Err(error) => return Err(ApiError::Internal(error)),
+    };
+    if !api_key.scopes.contains(&scope) {
+        return Err(ApiError::Forbidden(format!(
+            "API key lacks the {} scope",
+            scope.name()
+        )));
+    }
+    Ok(api_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_names_match_storage() {
+        for scope in ApiScope::ALL {
+            assert_eq!(serde_json::to_value(scope).unwrap(), scope.name());
+        }
+        assert_eq!(
+            ApiScope::parse_list("quiz:generate, quiz:read,quiz:generate").unwrap(),
+            vec![ApiScope::QuizGenerate, ApiScope::QuizRead]
+        );
+        assert!(ApiScope::parse_list("quiz:delete").is_err());
+    }
+
+    #[test]
+    fn test_routes_need_scopes() {
+        assert_eq!(
+            ApiScope::for_path("/api/generate_quiz"),
+            Some(ApiScope::QuizGenerate)
+        );
+        assert_eq!(
+            ApiScope::for_path("/api/export_quiz/q1/f1"),
+            Some(ApiScope::QuizRead)
+        );
+        assert_eq!(ApiScope::for_path("/api/api_keys/create"), None);
+        assert_eq!(ApiScope::for_path("/api/admin/users/a1"), None);
+    }
+
+    #[test]
+    fn test_caller_owns_ids() {
+        let caller = Caller::new("u1");
+        assert!(caller.check("u1").is_ok());
+        assert!(matches!(caller.check("u2"), Err(ApiError::Forbidden(_))));
+    }
+
+    #[test]
+    fn test_keys() {
+        let (key, prefix, hash) = new_api_key();
+        assert!(key.starts_with(&format!("{}_", prefix)));
+        assert_eq!(prefix.len(), KEY_PREFIX.len() + PREFIX_LENGTH);
+        assert_eq!(hash, hash_token(&key));
+        assert_eq!(bearer_key(&format!("Bearer {}", key)), Some(key.as_str()));
+        assert_eq!(bearer_key("Bearer eyJhbGciOi.jwt.token"), None);
+        assert_eq!(bearer_key("Basic qk_abc"), None);
+    }
+}
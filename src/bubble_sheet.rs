@@ -0,0 +1,149 @@
+//! Geometry of the printed bubble sheet, shared by the PDF renderer and the
+//! scanner so both agree on where every mark is. Positions are in millimetres
+//! from the bottom-left corner of an A4 page.
+
+pub const PAGE_WIDTH: f32 = 210.0;
+pub const PAGE_HEIGHT: f32 = 297.0;
+
+/// Solid squares in the page corners, used to find the sheet in a scan.
+pub const FIDUCIAL_SIZE: f32 = 8.0;
+pub const FIDUCIALS: [(f32, f32); 4] = [(15.0, 282.0), (195.0, 282.0), (15.0, 15.0), (195.0, 15.0)];
+
+/// The printed sheet code: a row of squares, filled for 1 bits.
+pub const CODE_BITS: usize = 16;
+pub const CODE_CELL: f32 = 4.0;
+const CODE_X: f32 = 40.0;
+const CODE_STEP: f32 = 8.0;
+const CODE_Y: f32 = 252.0;
+/// Solid square left of the code row; a scan is upright when it is found.
+pub const ORIENTATION_MARK: (f32, f32) = (CODE_X - CODE_STEP, CODE_Y);
+
+pub const BUBBLE_RADIUS: f32 = 2.6;
+pub const MAX_OPTIONS: usize = 6;
+pub const ROWS_PER_COLUMN: usize = 25;
+const COLUMN_X: [f32; 2] = [30.0, 118.0];
+const FIRST_BUBBLE_OFFSET: f32 = 14.0;
+const OPTION_STEP: f32 = 8.0;
+const FIRST_ROW_Y: f32 = 232.0;
+const ROW_STEP: f32 = 8.5;
+
+/// Questions one sheet holds.
+pub const QUESTIONS_PER_SHEET: usize = ROWS_PER_COLUMN * COLUMN_X.len();
+/// Sheets one exam copy can have, as numbered in the sheet code.
+pub const MAX_SHEETS: usize = 4;
+/// Highest student number the sheet code can carry.
+pub const MAX_STUDENTS: usize = (1 << 11) - 1;
+
+/// What a sheet belongs to. `student` counts from 1 in `QuizTable.student_id`
+/// order, 0 being a copy printed for no student in particular.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SheetCode {
+    pub variant: usize,
+    pub sheet: usize,
+    pub student: usize,
+}
+
+impl SheetCode {
+    /// Bits 0-1 hold the variant, 2-3 the sheet, 4-14 the student and bit 15
+    /// makes the number of set bits even.
+    pub fn encode(&self) -> u16 {
+        let code = (self.variant as u16 & 0b11)
+            | (self.sheet as u16 & 0b11) << 2
+            | (self.student.min(MAX_STUDENTS) as u16) << 4;
+        code | ((code.count_ones() as u16 & 1) << 15)
+    }
+
+    /// Reads a code back, or `None` when the parity bit shows a misread.
+    pub fn decode(code: u16) -> Option<SheetCode> {
+        if !code.count_ones().is_multiple_of(2) {
+            return None;
+        }
+        Some(SheetCode {
+            variant: (code & 0b11) as usize,
+            sheet: (code >> 2 & 0b11) as usize,
+            student: (code >> 4 & 0x7ff) as usize,
+        })
+    }
+}
+
+pub fn code_cell_center(bit: usize) -> (f32, f32) {
+    (CODE_X + bit as f32 * CODE_STEP, CODE_Y)
+}
+
+/// Where the number of the question in `slot` (0-based on its sheet) goes.
+pub fn label_position(slot: usize) -> (f32, f32) {
+    let (x, y) = row_origin(slot);
+    (x, y - 1.2)
+}
+
+pub fn bubble_center(slot: usize, option: usize) -> (f32, f32) {
+    let (x, y) = row_origin(slot);
+    (x + FIRST_BUBBLE_OFFSET + option as f32 * OPTION_STEP, y)
+}
+
+/// Where the option letters heading each column go.
+pub fn option_heading(column: usize, option: usize) -> (f32, f32) {
+    let x = COLUMN_X[column] + FIRST_BUBBLE_OFFSET + option as f32 * OPTION_STEP;
+    (x - 1.0, FIRST_ROW_Y + 6.0)
+}
+
+pub fn columns() -> usize {
+    COLUMN_X.len()
+}
+
+fn row_origin(slot: usize) -> (f32, f32) {
+    let column = slot / ROWS_PER_COLUMN;
+    let row = slot % ROWS_PER_COLUMN;
+    (COLUMN_X[column], FIRST_ROW_Y - row as f32 * ROW_STEP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_bits() {
+        let code = SheetCode {
+            variant: 2,
+            sheet: 1,
+            student: 5,
+        }
+        .encode();
+        assert_eq!(code & 0x7fff, 0b101_0110);
+        assert!(code.count_ones().is_multiple_of(2));
+    }
+
+    #[test]
+    fn test_code_round_trip() {
+        let code = SheetCode {
+            variant: 2,
+            sheet: 1,
+            student: 1234,
+        };
+        assert_eq!(SheetCode::decode(code.encode()), Some(code));
+    }
+
+    #[test]
+    fn test_single_bit_error_detected() {
+        let code = SheetCode {
+            variant: 3,
+            sheet: 0,
+            student: 7,
+        }
+        .encode();
+        for bit in 0..CODE_BITS {
+            assert_eq!(SheetCode::decode(code ^ (1 << bit)), None);
+        }
+    }
+
+    #[test]
+    fn test_grid_stays_between_fiducials() {
+        let (_, lowest) = bubble_center(QUESTIONS_PER_SHEET - 1, 0);
+        let (rightmost, _) = bubble_center(QUESTIONS_PER_SHEET - 1, MAX_OPTIONS - 1);
+        assert!(lowest - BUBBLE_RADIUS > FIDUCIALS[2].1 + FIDUCIAL_SIZE / 2.0);
+        assert!(rightmost + BUBBLE_RADIUS < FIDUCIALS[1].0 - FIDUCIAL_SIZE / 2.0);
+        // Read upside down, the orientation mark must fall on blank paper.
+        let (x, _) = ORIENTATION_MARK;
+        assert!(PAGE_WIDTH - x - CODE_CELL / 2.0 > rightmost + BUBBLE_RADIUS);
+    }
+}
@@ -0,0 +1,155 @@
+//! Cloze deletions in Anki's `{{c1::answer}}` / `{{c1::answer::hint}}`
+//! syntax. A text with deletions numbered 1..n makes n cards; card k hides
+//! deletion k and shows the others.
+
+use crate::model::Flashcard;
+
+struct Deletion<'a> {
+    start: usize,
+    end: usize,
+    number: u8,
+    answer: &'a str,
+    hint: Option<&'a str>,
+}
+
+fn deletions(text: &str) -> Vec<Deletion<'_>> {
+    let mut found = Vec::new();
+    let mut from = 0;
+
+    while let Some(offset) = text[from..].find("{{c") {
+        let start = from + offset;
+        let body_start = start + 3;
+        let Some(close) = text[body_start..].find("}}") else {
+            break;
+        };
+        let end = body_start + close + 2;
+        let body = &text[body_start..body_start + close];
+
+        let mut parts = body.splitn(3, "::");
+        let number = parts.next().and_then(|n| n.parse::<u8>().ok());
+        match (number, parts.next()) {
+            (Some(number), Some(answer)) if number > 0 => {
+                found.push(Deletion {
+                    start,
+                    end,
+                    number,
+                    answer,
+                    hint: parts.next(),
+                });
+                from = end;
+            }
+            _ => from = body_start,
+        }
+    }
+
+    found
+}
+
+/// The distinct deletion numbers in `text`, ascending.
+pub fn numbers(text: &str) -> Vec<u8> {
+    let mut numbers: Vec<u8> = deletions(text).iter().map(|d| d.number).collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+    numbers
+}
+
+/// Front and back of card `number` of `text`. The front shows `[...]`, or
+/// `[hint]`, in place of that deletion.
+pub fn render(text: &str, number: u8) -> (String, String) {
+    let mut front = String::with_capacity(text.len());
+    let mut back = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for deletion in deletions(text) {
+        front.push_str(&text[last..deletion.start]);
+        back.push_str(&text[last..deletion.start]);
+
+        if deletion.number == number {
+            front.push_str(&format!("[{}]", deletion.hint.unwrap_or("...")));
+        } else {
+            front.push_str(deletion.answer);
+        }
+        back.push_str(deletion.answer);
+        last = deletion.end;
+    }
+
+    front.push_str(&text[last..]);
+    back.push_str(&text[last..]);
+    (front, back)
+}
+
+/// Front and back of any card: the topic and content of a basic card, or the
+/// rendered deletion of a cloze card.
+pub fn card_sides(card: &Flashcard) -> (String, String) {
+    match card.cloze {
+        Some(number) => render(&card.content, number),
+        None => (card.topic.clone(), card.content.clone()),
+    }
+}
+
+/// Pulls the sentences out of the model's reply to the cloze prompt,
+/// tolerating a Markdown code fence around the JSON.
+pub fn parse_sentences(reply: &str) -> Result<Vec<String>, String> {
+    let json = reply
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```");
+
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    let sentences = match value.get("cloze_sentences").and_then(|s| s.as_array()) {
+        Some(s) => s,
+        None => return Err("Reply has no cloze_sentences array".to_string()),
+    };
+
+    Ok(sentences
+        .iter()
+        .filter_map(|s| s.as_str())
+        .filter(|s| !numbers(s).is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str =
+        "{{c1::Mitochondria}} produce {{c2::ATP::a molecule}} through {{c1::respiration}}.";
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(numbers(TEXT), vec![1, 2]);
+        assert!(numbers("no deletions {{here}}").is_empty());
+    }
+
+    #[test]
+    fn test_render_hides_only_its_number() {
+        let (front, back) = render(TEXT, 1);
+        assert_eq!(front, "[...] produce ATP through [...].");
+        assert_eq!(back, "Mitochondria produce ATP through respiration.");
+
+        let (front, _) = render(TEXT, 2);
+        assert_eq!(
+            front,
+            "Mitochondria produce [a molecule] through respiration."
+        );
+    }
+
+    #[test]
+    fn test_parse_sentences_drops_ones_without_deletions() {
+        let reply =
+            "```json\n{\"cloze_sentences\": [\"{{c1::Paris}} is in France.\", \"No cloze.\"]}\n```";
+        assert_eq!(
+            parse_sentences(reply).unwrap(),
+            vec!["{{c1::Paris}} is in France."]
+        );
+    }
+
+    #[test]
+    fn test_malformed_markup_left_as_is() {
+        let (front, back) = render("{{cx::oops}} and {{c1::fine}} {{c2::open", 1);
+        assert_eq!(front, "{{cx::oops}} and [...] {{c2::open");
+        assert_eq!(back, "{{cx::oops}} and fine {{c2::open");
+    }
+}
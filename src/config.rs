@@ -0,0 +1,644 @@
+//! Server settings, read once at startup. Each setting has a default, can
+//! be set in `.config/config.toml` (or the file named by `CONFIG_FILE`),
+//! and can be overridden by an environment variable. Everything is checked
+//! before the server starts, and all problems are reported together.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+use serde::Deserialize;
+
+const DEFAULT_CONFIG_FILE: &str = ".config/config.toml";
+const DEFAULT_FRONTEND_URL: &str = "http://localhost:3000";
+/// Enough for an HS256 key: 256 bits of printable characters.
+const JWT_SECRET_MIN_LEN: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    /// `None` when Gemini is not configured, so nothing can be generated.
+    pub gemini: Option<GeminiConfig>,
+    pub smtp: Option<SmtpConfig>,
+    pub oidc: Option<OidcConfig>,
+    pub lti: Option<LtiConfig>,
+    pub bootstrap_admin: Option<BootstrapAdmin>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Origins allowed to call the API from a browser.
+    pub cors_origins: Vec<String>,
+    /// Where the frontend is served; links in emails point there.
+    pub app_base_url: String,
+    /// Signs session tokens. Without it a random secret is made at startup,
+    /// so sessions end whenever the server restarts.
+    pub jwt_secret: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DatabaseConfig {
+    pub uri: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct GeminiConfig {
+    pub api_endpoint: String,
+    pub project_id: String,
+    /// Sometimes called "region" in gCloud docs.
+    pub location_id: String,
+    pub model: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SmtpTls {
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Clone, Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    /// Exactly as the provider's tokens name it, trailing `/` included.
+    pub issuer: String,
+    pub client_id: String,
+    /// `None` for public clients.
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    /// The claim listing the user's groups or roles.
+    pub role_claim: String,
+    pub admin_groups: Vec<String>,
+    pub faculty_groups: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LtiConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub deployment_ids: Vec<String>,
+    pub auth_login_url: String,
+    pub auth_token_url: String,
+    pub jwks_url: String,
+    pub tool_url: String,
+    pub private_key_file: String,
+    pub key_id: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct BootstrapAdmin {
+    pub username: String,
+    pub password: String,
+}
+
+/// The file as written. Everything is optional so the environment can fill
+/// in or override any of it.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    server: RawServer,
+    database: RawDatabase,
+    gemini: RawGemini,
+    smtp: RawSmtp,
+    oidc: RawOidc,
+    lti: RawLti,
+    bootstrap_admin: RawBootstrapAdmin,
+    /// Environment variables by name, as in the older `[env]` layout. Real
+    /// environment variables take precedence.
+    env: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawServer {
+    host: Option<String>,
+    port: Option<u16>,
+    cors_origins: Option<Vec<String>>,
+    app_base_url: Option<String>,
+    jwt_secret: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawDatabase {
+    uri: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawGemini {
+    api_endpoint: Option<String>,
+    project_id: Option<String>,
+    location_id: Option<String>,
+    model: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawSmtp {
+    host: Option<String>,
+    port: Option<u16>,
+    tls: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    from: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawOidc {
+    issuer: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_uri: Option<String>,
+    role_claim: Option<String>,
+    admin_groups: Option<Vec<String>>,
+    faculty_groups: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawLti {
+    issuer: Option<String>,
+    client_id: Option<String>,
+    deployment_ids: Option<Vec<String>>,
+    auth_login_url: Option<String>,
+    auth_token_url: Option<String>,
+    jwks_url: Option<String>,
+    tool_url: Option<String>,
+    private_key_file: Option<String>,
+    key_id: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawBootstrapAdmin {
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// Reads environment variables and collects those that do not parse.
+struct Overrides<'a> {
+    var: &'a dyn Fn(&str) -> Option<String>,
+    errors: Vec<String>,
+}
+
+impl Overrides<'_> {
+    fn string(&mut self, field: &mut Option<String>, name: &str) {
+        if let Some(value) = (self.var)(name) {
+            *field = Some(value);
+        }
+    }
+
+    fn list(&mut self, field: &mut Option<Vec<String>>, name: &str) {
+        if let Some(value) = (self.var)(name) {
+            *field = Some(value.split(',').map(str::to_string).collect());
+        }
+    }
+
+    fn port(&mut self, field: &mut Option<u16>, name: &str) {
+        if let Some(value) = (self.var)(name) {
+            match value.trim().parse() {
+                Ok(port) => *field = Some(port),
+                Err(_) => self.errors.push(format!("{} must be a port number", name)),
+            }
+        }
+    }
+}
+
+impl RawConfig {
+    fn apply_env(&mut self, overrides: &mut Overrides) {
+        let server = &mut self.server;
+        overrides.string(&mut server.host, "SERVER_HOST");
+        overrides.port(&mut server.port, "SERVER_PORT");
+        overrides.list(&mut server.cors_origins, "CORS_ORIGINS");
+        overrides.string(&mut server.app_base_url, "APP_BASE_URL");
+        overrides.string(&mut server.jwt_secret, "JWT_SECRET");
+
+        overrides.string(&mut self.database.uri, "MONGODB_URI");
+        overrides.string(&mut self.database.name, "DATABASE_NAME");
+
+        let gemini = &mut self.gemini;
+        overrides.string(&mut gemini.api_endpoint, "API_ENDPOINT");
+        overrides.string(&mut gemini.project_id, "PROJECT_ID");
+        overrides.string(&mut gemini.location_id, "LOCATION_ID");
+        overrides.string(&mut gemini.model, "GEMINI_MODEL");
+
+        let smtp = &mut self.smtp;
+        overrides.string(&mut smtp.host, "SMTP_HOST");
+        overrides.port(&mut smtp.port, "SMTP_PORT");
+        overrides.string(&mut smtp.tls, "SMTP_TLS");
+        overrides.string(&mut smtp.username, "SMTP_USERNAME");
+        overrides.string(&mut smtp.password, "SMTP_PASSWORD");
+        overrides.string(&mut smtp.from, "MAIL_FROM");
+
+        let oidc = &mut self.oidc;
+        overrides.string(&mut oidc.issuer, "OIDC_ISSUER");
+        overrides.string(&mut oidc.client_id, "OIDC_CLIENT_ID");
+        overrides.string(&mut oidc.client_secret, "OIDC_CLIENT_SECRET");
+        overrides.string(&mut oidc.redirect_uri, "OIDC_REDIRECT_URI");
+        overrides.string(&mut oidc.role_claim, "OIDC_ROLE_CLAIM");
+        overrides.list(&mut oidc.admin_groups, "OIDC_ADMIN_GROUPS");
+        overrides.list(&mut oidc.faculty_groups, "OIDC_FACULTY_GROUPS");
+
+        let lti = &mut self.lti;
+        overrides.string(&mut lti.issuer, "LTI_ISSUER");
+        overrides.string(&mut lti.client_id, "LTI_CLIENT_ID");
+        overrides.list(&mut lti.deployment_ids, "LTI_DEPLOYMENT_IDS");
+        overrides.string(&mut lti.auth_login_url, "LTI_AUTH_LOGIN_URL");
+        overrides.string(&mut lti.auth_token_url, "LTI_AUTH_TOKEN_URL");
+        overrides.string(&mut lti.jwks_url, "LTI_JWKS_URL");
+        overrides.string(&mut lti.tool_url, "LTI_TOOL_URL");
+        overrides.string(&mut lti.private_key_file, "LTI_PRIVATE_KEY_FILE");
+        overrides.string(&mut lti.key_id, "LTI_KEY_ID");
+
+        let admin = &mut self.bootstrap_admin;
+        overrides.string(&mut admin.username, "BOOTSTRAP_ADMIN_USERNAME");
+        overrides.string(&mut admin.password, "BOOTSTRAP_ADMIN_PASSWORD");
+    }
+}
+
+/// `value` trimmed, treating blank as unset.
+fn present(value: Option<String>) -> Option<String> {
+    value
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn list(values: Option<Vec<String>>) -> Vec<String> {
+    values
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|s| present(Some(s)))
+        .collect()
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .is_some_and(|rest| !rest.is_empty())
+}
+
+/// Checks a section that is only used when `key` is set: every field in
+/// `fields` must then be set too.
+fn section<const N: usize>(
+    errors: &mut Vec<String>,
+    key: (&str, Option<String>),
+    fields: [(&str, Option<String>); N],
+) -> Option<(String, [String; N])> {
+    let (key_name, key) = key;
+    let Some(key) = present(key) else {
+        for (name, value) in &fields {
+            if present(value.clone()).is_some() {
+                errors.push(format!("{} is set but {} is not", name, key_name));
+            }
+        }
+        return None;
+    };
+    let mut missing = Vec::new();
+    let values = fields.map(|(name, value)| match present(value) {
+        Some(s) => s,
+        None => {
+            missing.push(name);
+            String::new()
+        }
+    });
+    if !missing.is_empty() {
+        for name in missing {
+            errors.push(format!("{} is required when {} is set", name, key_name));
+        }
+        return None;
+    }
+    Some((key, values))
+}
+
+impl Config {
+    /// The settings for this server: the config file, overridden by the
+    /// environment.
+    pub fn load() -> Result<Config, Vec<String>> {
+        let (path, required) = match env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+        let file = match fs::read_to_string(&path) {
+            Ok(s) => Some(s),
+            Err(_) if !required => None,
+            Err(err) => return Err(vec![format!("Could not read {}: {}", path, err)]),
+        };
+        Config::from_sources(file.as_deref(), &|name| env::var(name).ok())
+    }
+
+    fn from_sources(
+        file: Option<&str>,
+        var: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Config, Vec<String>> {
+        let mut raw: RawConfig = match file.map(toml::from_str).transpose() {
+            Ok(s) => s.unwrap_or_default(),
+            Err(err) => return Err(vec![format!("Invalid config file: {}", err)]),
+        };
+        let file_env = std::mem::take(&mut raw.env);
+        let var = |name: &str| var(name).or_else(|| file_env.get(name).cloned());
+        let mut overrides = Overrides {
+            var: &var,
+            errors: Vec::new(),
+        };
+        raw.apply_env(&mut overrides);
+
+        let mut errors = overrides.errors;
+        let config = raw.resolve(&mut errors);
+        match errors.is_empty() {
+            true => Ok(config),
+            false => Err(errors),
+        }
+    }
+}
+
+impl RawConfig {
+    fn resolve(self, errors: &mut Vec<String>) -> Config {
+        let app_base_url = present(self.server.app_base_url)
+            .unwrap_or_else(|| DEFAULT_FRONTEND_URL.to_string())
+            .trim_end_matches('/')
+            .to_string();
+        if !is_http_url(&app_base_url) {
+            errors.push("APP_BASE_URL must be an http or https URL".to_string());
+        }
+        let cors_origins: Vec<String> = match self.server.cors_origins {
+            Some(origins) => list(Some(origins))
+                .into_iter()
+                .map(|s| s.trim_end_matches('/').to_string())
+                .collect(),
+            None => vec![app_base_url.clone()],
+        };
+        for origin in &cors_origins {
+            let has_path = origin
+                .split_once("://")
+                .is_some_and(|(_, host)| host.contains('/'));
+            if !is_http_url(origin) || has_path {
+                errors.push(format!("CORS origin {} must be a scheme and host", origin));
+            }
+        }
+        let server = ServerConfig {
+            host: present(self.server.host).unwrap_or_else(|| "127.0.0.1".to_string()),
+            port: self.server.port.unwrap_or(8000),
+            cors_origins,
+            app_base_url,
+            jwt_secret: present(self.server.jwt_secret),
+        };
+        if server.port == 0 {
+            errors.push("SERVER_PORT must not be 0".to_string());
+        }
+        if server
+            .jwt_secret
+            .as_ref()
+            .is_some_and(|s| s.len() < JWT_SECRET_MIN_LEN)
+        {
+            errors.push(format!(
+                "JWT_SECRET must be at least {} characters",
+                JWT_SECRET_MIN_LEN
+            ));
+        }
+
+        let database = DatabaseConfig {
+            uri: present(self.database.uri)
+                .unwrap_or_else(|| "mongodb://localhost:27017".to_string()),
+            name: present(self.database.name).unwrap_or_else(|| "LEANLEARN".to_string()),
+        };
+        if !database.uri.starts_with("mongodb://") && !database.uri.starts_with("mongodb+srv://") {
+            errors.push("MONGODB_URI must start with mongodb:// or mongodb+srv://".to_string());
+        }
+        if database.name.contains(['/', '\\', '.', ' ', '"', '$']) {
+            errors.push(format!("{} is not a valid database name", database.name));
+        }
+
+        let gemini = section(
+            errors,
+            ("API_ENDPOINT", self.gemini.api_endpoint),
+            [
+                ("PROJECT_ID", self.gemini.project_id),
+                ("LOCATION_ID", self.gemini.location_id),
+            ],
+        )
+        .map(|(api_endpoint, [project_id, location_id])| GeminiConfig {
+            api_endpoint,
+            project_id,
+            location_id,
+            model: present(self.gemini.model).unwrap_or_else(|| "gemini-pro".to_string()),
+        });
+
+        let tls = match present(self.smtp.tls).as_deref() {
+            None | Some("starttls") => SmtpTls::StartTls,
+            Some("none") => SmtpTls::None,
+            Some("tls") => SmtpTls::Tls,
+            Some(other) => {
+                errors.push(format!(
+                    "SMTP_TLS must be none, starttls or tls, not {}",
+                    other
+                ));
+                SmtpTls::StartTls
+            }
+        };
+        let smtp = section(
+            errors,
+            ("SMTP_HOST", self.smtp.host),
+            [("MAIL_FROM", self.smtp.from)],
+        )
+        .map(|(host, [from])| SmtpConfig {
+            host,
+            port: self.smtp.port,
+            tls,
+            username: present(self.smtp.username),
+            password: present(self.smtp.password),
+            from,
+        });
+
+        let oidc = section(
+            errors,
+            ("OIDC_ISSUER", self.oidc.issuer),
+            [
+                ("OIDC_CLIENT_ID", self.oidc.client_id),
+                ("OIDC_REDIRECT_URI", self.oidc.redirect_uri),
+            ],
+        )
+        .map(|(issuer, [client_id, redirect_uri])| OidcConfig {
+            issuer,
+            client_id,
+            client_secret: present(self.oidc.client_secret),
+            redirect_uri,
+            role_claim: present(self.oidc.role_claim).unwrap_or_else(|| "groups".to_string()),
+            admin_groups: list(self.oidc.admin_groups),
+            faculty_groups: list(self.oidc.faculty_groups),
+        });
+
+        let deployment_ids = list(self.lti.deployment_ids);
+        let lti = section(
+            errors,
+            ("LTI_ISSUER", self.lti.issuer),
+            [
+                ("LTI_CLIENT_ID", self.lti.client_id),
+                ("LTI_DEPLOYMENT_IDS", deployment_ids.first().cloned()),
+                ("LTI_AUTH_LOGIN_URL", self.lti.auth_login_url),
+                ("LTI_AUTH_TOKEN_URL", self.lti.auth_token_url),
+                ("LTI_JWKS_URL", self.lti.jwks_url),
+                ("LTI_TOOL_URL", self.lti.tool_url),
+                ("LTI_PRIVATE_KEY_FILE", self.lti.private_key_file),
+            ],
+        )
+        .map(
+            |(
+                issuer,
+                [client_id, _, auth_login_url, auth_token_url, jwks_url, tool_url, private_key_file],
+            )| LtiConfig {
+                issuer,
+                client_id,
+                deployment_ids,
+                auth_login_url,
+                auth_token_url,
+                jwks_url,
+                tool_url: tool_url.trim_end_matches('/').to_string(),
+                private_key_file,
+                key_id: present(self.lti.key_id).unwrap_or_else(|| "quiz-api".to_string()),
+            },
+        );
+
+        let bootstrap_admin = section(
+            errors,
+            ("BOOTSTRAP_ADMIN_USERNAME", self.bootstrap_admin.username),
+            [("BOOTSTRAP_ADMIN_PASSWORD", self.bootstrap_admin.password)],
+        )
+        .map(|(username, [password])| BootstrapAdmin { username, password });
+
+        Config {
+            server,
+            database,
+            gemini,
+            smtp,
+            oidc,
+            lti,
+            bootstrap_admin,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file: Option<&str>, vars: &[(&str, &str)]) -> Result<Config, Vec<String>> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_sources(file, &|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load(None, &[]).unwrap();
+        assert_eq!(config.server.host, "127.0.0.1");
+        assert_eq!(config.server.port, 8000);
+        assert_eq!(config.server.cors_origins, vec!["http://localhost:3000"]);
+        assert_eq!(config.database.name, "LEANLEARN");
+        assert!(config.gemini.is_none() && config.smtp.is_none() && config.lti.is_none());
+        assert!(config.server.jwt_secret.is_none());
+    }
+
+    #[test]
+    fn test_jwt_secret() {
+        let secret = "0123456789abcdef0123456789abcdef";
+        let config = load(
+            Some("[server]\njwt_secret = \"short\"\n"),
+            &[("JWT_SECRET", secret)],
+        );
+        assert_eq!(config.unwrap().server.jwt_secret.as_deref(), Some(secret));
+
+        let errors = load(None, &[("JWT_SECRET", "short")]).unwrap_err();
+        assert_eq!(errors, vec!["JWT_SECRET must be at least 32 characters"]);
+    }
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let file = r#"
+            [server]
+            port = 9000
+            cors_origins = ["https://quiz.example.edu/"]
+
+            [database]
+            name = "quizzes"
+
+            [gemini]
+            api_endpoint = "us-central1-aiplatform.googleapis.com"
+            project_id = "file-project"
+            location_id = "us-central1"
+        "#;
+        let config = load(
+            Some(file),
+            &[
+                ("PROJECT_ID", "env-project"),
+                ("GEMINI_MODEL", "gemini-1.5-pro"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.server.cors_origins, vec!["https://quiz.example.edu"]);
+        assert_eq!(config.database.name, "quizzes");
+        let gemini = config.gemini.unwrap();
+        assert_eq!(gemini.project_id, "env-project");
+        assert_eq!(gemini.model, "gemini-1.5-pro");
+    }
+
+    #[test]
+    fn test_env_table_is_read() {
+        let file = r#"
+            [env]
+            API_ENDPOINT = "us-central1-aiplatform.googleapis.com"
+            PROJECT_ID = "file-project"
+            LOCATION_ID = "us-central1"
+        "#;
+        let config = load(Some(file), &[("PROJECT_ID", "env-project")]).unwrap();
+        assert_eq!(config.gemini.unwrap().project_id, "env-project");
+    }
+
+    #[test]
+    fn test_problems_are_reported_together() {
+        let errors = load(
+            Some("[server]\nport = 8000\n"),
+            &[
+                ("SERVER_PORT", "http"),
+                ("MONGODB_URI", "localhost:27017"),
+                ("OIDC_ISSUER", "https://id.example.edu"),
+                ("SMTP_TLS", "ssl"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "SERVER_PORT must be a port number",
+                "MONGODB_URI must start with mongodb:// or mongodb+srv://",
+                "SMTP_TLS must be none, starttls or tls, not ssl",
+                "OIDC_CLIENT_ID is required when OIDC_ISSUER is set",
+                "OIDC_REDIRECT_URI is required when OIDC_ISSUER is set",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_are_refused() {
+        let errors = load(Some("[server]\nprot = 8000\n"), &[]).unwrap_err();
+        assert!(errors[0].contains("unknown field `prot`"));
+    }
+}
@@ -0,0 +1,772 @@
+//! Conversion of flashcard decks to and from Anki packages (`.apkg`) and
+//! CSV/TSV files.
+//!
+//! Cards are written as Anki "Basic" notes with the card topic on the front
+//! and its content on the back. Review progress travels with the cards: in a
+//! package as Anki's own card scheduling, and in CSV/TSV as extra columns.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rusqlite::Connection;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::model::{Deck, Flashcard, ReviewState};
+use crate::scheduler::START_EASE;
+
+/// Column order of exported CSV/TSV files. Only the first two are needed
+/// on import.
+const CSV_HEADER: [&str; 9] = [
+    "front",
+    "back",
+    "tags",
+    "due",
+    "interval_days",
+    "ease",
+    "repetitions",
+    "lapses",
+    "cloze",
+];
+
+/// Keys of the `#key:value` lines Anki writes at the top of its text
+/// exports.
+const ANKI_TEXT_HEADERS: [&str; 10] = [
+    "separator",
+    "html",
+    "tags",
+    "columns",
+    "notetype",
+    "deck",
+    "notetype column",
+    "deck column",
+    "guid column",
+    "tags column",
+];
+
+const ANKI_SCHEMA: &str = "
+CREATE TABLE col (id integer primary key, crt integer not null, mod integer not null,
+    scm integer not null, ver integer not null, dty integer not null, usn integer not null,
+    ls integer not null, conf text not null, models text not null, decks text not null,
+    dconf text not null, tags text not null);
+CREATE TABLE notes (id integer primary key, guid text not null, mid integer not null,
+    mod integer not null, usn integer not null, tags text not null, flds text not null,
+    sfld integer not null, csum integer not null, flags integer not null, data text not null);
+CREATE TABLE cards (id integer primary key, nid integer not null, did integer not null,
+    ord integer not null, mod integer not null, usn integer not null, type integer not null,
+    queue integer not null, due integer not null, ivl integer not null, factor integer not null,
+    reps integer not null, lapses integer not null, left integer not null, odue integer not null,
+    odid integer not null, flags integer not null, data text not null);
+CREATE TABLE revlog (id integer primary key, cid integer not null, usn integer not null,
+    ease integer not null, ivl integer not null, lastIvl integer not null,
+    factor integer not null, time integer not null, type integer not null);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+";
+
+/// A card read from a file, with the review progress it carried if any.
+pub struct ImportedCard {
+    pub card: Flashcard,
+    pub review: Option<ReviewState>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeckFormat {
+    Apkg,
+    Csv,
+    Tsv,
+}
+
+impl DeckFormat {
+    pub fn parse(format: &str) -> Option<DeckFormat> {
+        match format.trim_start_matches('.').to_lowercase().as_str() {
+            "apkg" => Some(DeckFormat::Apkg),
+            "csv" => Some(DeckFormat::Csv),
+            "tsv" | "txt" => Some(DeckFormat::Tsv),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DeckFormat::Apkg => "apkg",
+            DeckFormat::Csv => "csv",
+            DeckFormat::Tsv => "tsv",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DeckFormat::Apkg => "application/octet-stream",
+            DeckFormat::Csv => "text/csv",
+            DeckFormat::Tsv => "text/tab-separated-values",
+        }
+    }
+}
+
+pub fn export_deck(
+    deck: &Deck,
+    reviews: &HashMap<String, ReviewState>,
+    format: DeckFormat,
+) -> Result<Vec<u8>, String> {
+    match format {
+        DeckFormat::Apkg => export_apkg(deck, reviews),
+        DeckFormat::Csv => export_delimited(deck, reviews, b','),
+        DeckFormat::Tsv => export_delimited(deck, reviews, b'\t'),
+    }
+}
+
+/// Reads cards from `bytes`, giving each a fresh id. Review progress found
+/// in the file is attached for `student_id`.
+pub fn import_deck(
+    bytes: &[u8],
+    format: DeckFormat,
+    student_id: &str,
+) -> Result<Vec<ImportedCard>, String> {
+    match format {
+        DeckFormat::Apkg => import_apkg(bytes, student_id),
+        DeckFormat::Csv => import_delimited(bytes, b',', student_id),
+        DeckFormat::Tsv => import_delimited(bytes, b'\t', student_id),
+    }
+}
+
+fn new_card(topic: String, content: String, tags: Vec<String>) -> Flashcard {
+    Flashcard {
+        _id: Uuid::new_v4().to_string(),
+        topic,
+        content,
+        tags,
+        cloze: None,
+    }
+}
+
+fn new_review(student_id: &str, card_id: &str) -> ReviewState {
+    ReviewState {
+        _id: format!("{}:{}", student_id, card_id),
+        student_id: student_id.to_string(),
+        card_id: card_id.to_string(),
+        ease: START_EASE,
+        interval_days: 0,
+        repetitions: 0,
+        lapses: 0,
+        due: bson::DateTime::now(),
+        last_reviewed: None,
+    }
+}
+
+fn export_delimited(
+    deck: &Deck,
+    reviews: &HashMap<String, ReviewState>,
+    delimiter: u8,
+) -> Result<Vec<u8>, String> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+
+    writer
+        .write_record(CSV_HEADER)
+        .map_err(|err| err.to_string())?;
+
+    for card in &deck.cards {
+        let mut record = vec![
+            card.topic.clone(),
+            card.content.clone(),
+            card.tags.join(" "),
+        ];
+        match reviews.get(&card._id) {
+            Some(review) => record.extend([
+                review.due.to_chrono().to_rfc3339(),
+                review.interval_days.to_string(),
+                review.ease.to_string(),
+                review.repetitions.to_string(),
+                review.lapses.to_string(),
+            ]),
+            None => record.extend(vec![String::new(); 5]),
+        }
+        record.push(card.cloze.map(|n| n.to_string()).unwrap_or_default());
+        writer
+            .write_record(&record)
+            .map_err(|err| err.to_string())?;
+    }
+
+    writer.into_inner().map_err(|err| err.to_string())
+}
+
+fn import_delimited(
+    bytes: &[u8],
+    delimiter: u8,
+    student_id: &str,
+) -> Result<Vec<ImportedCard>, String> {
+    // Anki's own text exports start with `#key:value` lines and have no
+    // header row, so both are optional. Other lines starting with `#` are
+    // cards.
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(bytes);
+
+    let mut cards = Vec::new();
+    let mut preamble = true;
+    for record in reader.records() {
+        let record = record.map_err(|err| err.to_string())?;
+        let field = |n: usize| record.get(n).unwrap_or("").trim();
+
+        if preamble {
+            if is_anki_text_header(field(0)) {
+                continue;
+            }
+            preamble = false;
+            if field(0).eq_ignore_ascii_case(CSV_HEADER[0]) {
+                continue;
+            }
+        }
+        if field(0).is_empty() {
+            continue;
+        }
+
+        let mut card = new_card(
+            field(0).to_string(),
+            field(1).to_string(),
+            field(2).split_whitespace().map(str::to_string).collect(),
+        );
+        card.cloze = field(8).parse().ok().filter(|n| *n > 0);
+
+        let review = DateTime::parse_from_rfc3339(field(3)).ok().map(|due| {
+            let mut review = new_review(student_id, &card._id);
+            review.due = bson::DateTime::from_chrono(due.with_timezone(&Utc));
+            review.interval_days = field(4).parse().unwrap_or(0);
+            review.ease = field(5).parse().unwrap_or(START_EASE);
+            review.repetitions = field(6).parse().unwrap_or(0);
+            review.lapses = field(7).parse().unwrap_or(0);
+            review
+        });
+
+        cards.push(ImportedCard { card, review });
+    }
+
+    Ok(cards)
+}
+
+fn is_anki_text_header(field: &str) -> bool {
+    field
+        .strip_prefix('#')
+        .and_then(|line| line.split_once(':'))
+        .is_some_and(|(key, _)| ANKI_TEXT_HEADERS.contains(&key))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>")
+}
+
+/// Plain text of an HTML field, as found in Anki notes and LMS exports.
+pub fn strip_html(text: &str) -> String {
+    let text = text
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n")
+        .replace("<br />", "\n");
+    let mut plain = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    plain
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+/// Anki's note checksum: the first 8 hex digits of the SHA-1 of the sort
+/// field with HTML removed.
+fn field_checksum(field: &str) -> i64 {
+    let digest = Sha1::digest(strip_html(field).as_bytes());
+    i64::from(u32::from_be_bytes([
+        digest[0], digest[1], digest[2], digest[3],
+    ]))
+}
+
+/// A note type: Anki's "Basic" front/back model, or its "Cloze" model when
+/// `cloze` is set.
+fn model_json(id: i64, cloze: bool, deck_id: i64, modified: i64) -> serde_json::Value {
+    let (name, kind, fields, qfmt, afmt) = if cloze {
+        (
+            "Cloze",
+            1,
+            ["Text", "Back Extra"],
+            "{{cloze:Text}}",
+            "{{cloze:Text}}<br>\n{{Back Extra}}",
+        )
+    } else {
+        (
+            "Basic",
+            0,
+            ["Front", "Back"],
+            "{{Front}}",
+            "{{FrontSide}}\n\n<hr id=answer>\n\n{{Back}}",
+        )
+    };
+    let field = |ord: usize| json!({ "name": fields[ord], "ord": ord, "sticky": false, "rtl": false, "font": "Arial", "size": 20, "media": [] });
+    json!({
+        "id": id,
+        "name": name,
+        "type": kind,
+        "mod": modified,
+        "usn": -1,
+        "sortf": 0,
+        "did": deck_id,
+        "tmpls": [{
+            "name": if cloze { "Cloze" } else { "Card 1" },
+            "ord": 0,
+            "qfmt": qfmt,
+            "afmt": afmt,
+            "bqfmt": "",
+            "bafmt": "",
+            "did": null,
+        }],
+        "flds": [field(0), field(1)],
+        "css": ".card { font-family: arial; font-size: 20px; text-align: center; }\n.cloze { font-weight: bold; color: blue; }",
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}",
+        "tags": [],
+        "vers": [],
+        "req": [[0, "any", [0]]],
+    })
+}
+
+fn export_apkg(deck: &Deck, reviews: &HashMap<String, ReviewState>) -> Result<Vec<u8>, String> {
+    let now = Utc::now();
+    let now_ms = now.timestamp_millis();
+    // Review due dates are counted in days from the collection's creation.
+    let created = now.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let basic_id = now_ms;
+    let cloze_id = now_ms + 1;
+    let deck_id = now_ms + 2;
+
+    // Cloze cards cut from the same text belong to one Anki note, one card
+    // per deletion; basic cards are a note each.
+    let mut notes: Vec<Vec<&Flashcard>> = Vec::new();
+    for card in &deck.cards {
+        let note = notes.iter_mut().find(|note| {
+            card.cloze.is_some()
+                && note[0].cloze.is_some()
+                && note[0].content == card.content
+                && note[0].topic == card.topic
+        });
+        match note {
+            Some(note) => note.push(card),
+            None => notes.push(vec![card]),
+        }
+    }
+
+    let file = tempfile::NamedTempFile::new().map_err(|err| err.to_string())?;
+    {
+        let conn = Connection::open(file.path()).map_err(|err| err.to_string())?;
+        conn.execute_batch(ANKI_SCHEMA)
+            .map_err(|err| err.to_string())?;
+
+        let models = json!({
+            basic_id.to_string(): model_json(basic_id, false, deck_id, now.timestamp()),
+            cloze_id.to_string(): model_json(cloze_id, true, deck_id, now.timestamp()),
+        });
+        let deck_json = |id: i64, name: &str| {
+            json!({
+                "id": id,
+                "name": name,
+                "mod": now.timestamp(),
+                "usn": -1,
+                "lrnToday": [0, 0],
+                "revToday": [0, 0],
+                "newToday": [0, 0],
+                "timeToday": [0, 0],
+                "collapsed": false,
+                "desc": "",
+                "dyn": 0,
+                "conf": 1,
+                "extendNew": 10,
+                "extendRev": 50,
+            })
+        };
+        let decks = json!({
+            "1": deck_json(1, "Default"),
+            deck_id.to_string(): deck_json(deck_id, &deck.name),
+        });
+        let dconf = json!({ "1": {
+            "id": 1,
+            "name": "Default",
+            "mod": 0,
+            "usn": 0,
+            "maxTaken": 60,
+            "autoplay": true,
+            "timer": 0,
+            "replayq": true,
+            "dyn": false,
+            "new": { "delays": [1, 10], "ints": [1, 4, 7], "initialFactor": 2500, "order": 1, "perDay": 20, "bury": true, "separate": true },
+            "rev": { "perDay": 200, "ease4": 1.3, "fuzz": 0.05, "maxIvl": 36500, "bury": true, "minSpace": 1 },
+            "lapse": { "delays": [10], "mult": 0, "minInt": 1, "leechFails": 8, "leechAction": 0 },
+        }});
+        let conf = json!({
+            "nextPos": deck.cards.len() + 1,
+            "estTimes": true,
+            "activeDecks": [1],
+            "sortType": "noteFld",
+            "timeLim": 0,
+            "sortBackwards": false,
+            "addToCur": true,
+            "curDeck": 1,
+            "newSpread": 0,
+            "dueCounts": true,
+            "curModel": basic_id.to_string(),
+            "collapseTime": 1200,
+        });
+
+        conn.execute(
+            "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+            rusqlite::params![
+                created.timestamp(),
+                now_ms,
+                conf.to_string(),
+                models.to_string(),
+                decks.to_string(),
+                dconf.to_string(),
+            ],
+        )
+        .map_err(|err| err.to_string())?;
+
+        let mut card_id = now_ms;
+        for (i, note) in notes.iter().enumerate() {
+            let note_id = now_ms + i as i64;
+            let first = note[0];
+            let (model_id, fields) = match first.cloze {
+                Some(_) => (
+                    cloze_id,
+                    [escape_html(&first.content), escape_html(&first.topic)],
+                ),
+                None => (
+                    basic_id,
+                    [escape_html(&first.topic), escape_html(&first.content)],
+                ),
+            };
+            let tags = if first.tags.is_empty() {
+                String::new()
+            } else {
+                format!(" {} ", first.tags.join(" "))
+            };
+
+            conn.execute(
+                "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+                rusqlite::params![
+                    note_id,
+                    Uuid::new_v4().simple().to_string()[..10].to_string(),
+                    model_id,
+                    now.timestamp(),
+                    tags,
+                    fields.join("\x1f"),
+                    strip_html(&fields[0]),
+                    field_checksum(&fields[0]),
+                ],
+            )
+            .map_err(|err| err.to_string())?;
+
+            for card in note {
+                // type/queue 2 is a review card due `due` days after creation,
+                // 0 a new card where `due` is its position in the new queue.
+                let (kind, due, ivl, factor, reps, lapses) = match reviews.get(&card._id) {
+                    Some(r) if r.repetitions > 0 => (
+                        2,
+                        (r.due.to_chrono() - created).num_days(),
+                        r.interval_days,
+                        (r.ease * 1000.0).round() as i64,
+                        r.repetitions,
+                        r.lapses,
+                    ),
+                    _ => (0, i as i64 + 1, 0, 0, 0, 0),
+                };
+                let ord = card.cloze.map_or(0, |number| number as i64 - 1);
+
+                conn.execute(
+                    "INSERT INTO cards VALUES (?1, ?2, ?3, ?4, ?5, -1, ?6, ?6, ?7, ?8, ?9, ?10, ?11, 0, 0, 0, 0, '')",
+                    rusqlite::params![
+                        card_id,
+                        note_id,
+                        deck_id,
+                        ord,
+                        now.timestamp(),
+                        kind,
+                        due,
+                        ivl,
+                        factor,
+                        reps,
+                        lapses
+                    ],
+                )
+                .map_err(|err| err.to_string())?;
+                card_id += 1;
+            }
+        }
+    }
+
+    let collection = std::fs::read(file.path()).map_err(|err| err.to_string())?;
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+    zip.start_file("collection.anki2", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(&collection).map_err(|err| err.to_string())?;
+    zip.start_file("media", options)
+        .map_err(|err| err.to_string())?;
+    zip.write_all(b"{}").map_err(|err| err.to_string())?;
+
+    Ok(zip.finish().map_err(|err| err.to_string())?.into_inner())
+}
+
+fn import_apkg(bytes: &[u8], student_id: &str) -> Result<Vec<ImportedCard>, String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|err| err.to_string())?;
+
+    // Packages from Anki 2.1 carry `collection.anki21` next to a legacy
+    // `collection.anki2`; prefer the newer one when both exist.
+    let name = ["collection.anki21", "collection.anki2"]
+        .into_iter()
+        .find(|name| archive.by_name(name).is_ok());
+    let name = match name {
+        Some(name) => name,
+        None if archive.by_name("collection.anki21b").is_ok() => {
+            return Err(
+                "Compressed Anki collections are not supported, export with \"Support older Anki versions\""
+                    .to_string(),
+            )
+        }
+        None => return Err("Not an Anki package".to_string()),
+    };
+
+    let mut collection = Vec::new();
+    archive
+        .by_name(name)
+        .map_err(|err| err.to_string())?
+        .read_to_end(&mut collection)
+        .map_err(|err| err.to_string())?;
+
+    let mut file = tempfile::NamedTempFile::new().map_err(|err| err.to_string())?;
+    file.write_all(&collection).map_err(|err| err.to_string())?;
+
+    let conn = Connection::open(file.path()).map_err(|err| err.to_string())?;
+    let (created, models): (i64, String) = conn
+        .query_row("SELECT crt, models FROM col", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .map_err(|err| err.to_string())?;
+    let created = Utc
+        .timestamp_opt(created, 0)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    // Note types of kind 1 are cloze types.
+    let models: serde_json::Value = serde_json::from_str(&models).map_err(|err| err.to_string())?;
+    let cloze_models: Vec<i64> = models
+        .as_object()
+        .map(|models| {
+            models
+                .values()
+                .filter(|model| model["type"].as_i64() == Some(1))
+                .filter_map(|model| model["id"].as_i64())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut statement = conn
+        .prepare(
+            "SELECT n.mid, n.flds, n.tags, c.ord, c.type, c.due, c.ivl, c.factor, c.reps, c.lapses
+             FROM cards c JOIN notes n ON n.id = c.nid
+             ORDER BY n.id, c.ord",
+        )
+        .map_err(|err| err.to_string())?;
+
+    let rows = statement
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+                row.get::<_, i64>(6)?,
+                row.get::<_, i64>(7)?,
+                row.get::<_, u32>(8)?,
+                row.get::<_, u32>(9)?,
+            ))
+        })
+        .map_err(|err| err.to_string())?;
+
+    let mut cards = Vec::new();
+    for row in rows {
+        let (model_id, fields, tags, ord, kind, due, ivl, factor, reps, lapses) =
+            row.map_err(|err| err.to_string())?;
+        let fields: Vec<&str> = fields.split('\x1f').collect();
+        let tags = tags.split_whitespace().map(str::to_string).collect();
+
+        let mut card = if cloze_models.contains(&model_id) {
+            let mut card = new_card(
+                strip_html(fields.get(1).unwrap_or(&"")),
+                strip_html(fields[0]),
+                tags,
+            );
+            card.cloze = Some(ord as u8 + 1);
+            card
+        } else if ord == 0 {
+            new_card(
+                strip_html(fields[0]),
+                strip_html(&fields[1..].join("\n")),
+                tags,
+            )
+        } else {
+            // Extra templates of a basic note, such as a reversed card.
+            continue;
+        };
+        card.tags.dedup();
+
+        let review = match kind {
+            2 => {
+                let mut review = new_review(student_id, &card._id);
+                review.due = bson::DateTime::from_chrono(created + Duration::days(due));
+                review.interval_days = ivl;
+                review.ease = factor as f64 / 1000.0;
+                review.repetitions = reps;
+                review.lapses = lapses;
+                Some(review)
+            }
+            _ => None,
+        };
+
+        cards.push(ImportedCard { card, review });
+    }
+
+    Ok(cards)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deck() -> Deck {
+        Deck {
+            _id: "deck".to_string(),
+            owner_id: "owner".to_string(),
+            name: "Cells".to_string(),
+            cards: vec![
+                new_card(
+                    "Mitochondria".to_string(),
+                    "Powerhouse of the cell, makes ATP".to_string(),
+                    vec!["biology".to_string(), "cells".to_string()],
+                ),
+                new_card(
+                    "Ribosome".to_string(),
+                    "Builds proteins from <mRNA>".to_string(),
+                    Vec::new(),
+                ),
+            ],
+            shared_with: HashMap::new(),
+            class_shares: HashMap::new(),
+            forked_from: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn reviews(deck: &Deck) -> HashMap<String, ReviewState> {
+        let card = &deck.cards[0];
+        let mut review = new_review("student", &card._id);
+        review.interval_days = 6;
+        review.ease = 2.36;
+        review.repetitions = 2;
+        review.lapses = 1;
+        review.due = bson::DateTime::from_chrono(Utc::now() + Duration::days(6));
+        HashMap::from([(card._id.clone(), review)])
+    }
+
+    fn assert_round_trip(format: DeckFormat) {
+        let deck = deck();
+        let bytes = export_deck(&deck, &reviews(&deck), format).unwrap();
+        let imported = import_deck(&bytes, format, "importer").unwrap();
+
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].card.topic, "Mitochondria");
+        assert_eq!(imported[0].card.tags, vec!["biology", "cells"]);
+        assert_eq!(imported[1].card.content, "Builds proteins from <mRNA>");
+        assert!(imported[1].review.is_none());
+
+        let review = imported[0].review.as_ref().unwrap();
+        assert_eq!(review.student_id, "importer");
+        assert_eq!(review.card_id, imported[0].card._id);
+        assert_eq!(review.interval_days, 6);
+        assert_eq!(review.ease, 2.36);
+        assert_eq!(review.repetitions, 2);
+        assert_eq!(review.lapses, 1);
+    }
+
+    #[test]
+    fn test_apkg_round_trip() {
+        assert_round_trip(DeckFormat::Apkg);
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        assert_round_trip(DeckFormat::Csv);
+    }
+
+    #[test]
+    fn test_tsv_round_trip() {
+        assert_round_trip(DeckFormat::Tsv);
+    }
+
+    #[test]
+    fn test_cloze_cards_share_a_note() {
+        let text = "{{c1::Paris}} is the capital of {{c2::France}}.";
+        let mut deck = deck();
+        deck.cards.truncate(1);
+        for number in [1, 2] {
+            let mut card = new_card("Geography".to_string(), text.to_string(), Vec::new());
+            card.cloze = Some(number);
+            deck.cards.push(card);
+        }
+
+        for format in [DeckFormat::Apkg, DeckFormat::Csv] {
+            let bytes = export_deck(&deck, &HashMap::new(), format).unwrap();
+            let imported = import_deck(&bytes, format, "importer").unwrap();
+            let cloze: Vec<_> = imported.iter().filter_map(|i| i.card.cloze).collect();
+            assert_eq!(imported.len(), 3);
+            assert_eq!(cloze, vec![1, 2]);
+            assert_eq!(imported[2].card.content, text);
+            assert_eq!(imported[2].card.topic, "Geography");
+        }
+    }
+
+    #[test]
+    fn test_anki_text_export() {
+        let text = "#separator:tab\n#html:false\nfront one\tback one\tdeck::tag\n";
+        let imported = import_deck(text.as_bytes(), DeckFormat::Tsv, "student").unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].card.content, "back one");
+        assert_eq!(imported[0].card.tags, vec!["deck::tag"]);
+    }
+
+    #[test]
+    fn test_hash_fronts_are_cards() {
+        let text = "#separator:tab\n#1 rule\tbe kind\n#hashtag\tsocial\n";
+        let imported = import_deck(text.as_bytes(), DeckFormat::Tsv, "student").unwrap();
+        let fronts: Vec<_> = imported.iter().map(|i| i.card.topic.as_str()).collect();
+        assert_eq!(fronts, vec!["#1 rule", "#hashtag"]);
+    }
+}
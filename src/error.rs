@@ -0,0 +1,180 @@
+//! The error handlers return. Every kind maps to an HTTP status and a
+//! machine-readable `code`, and is sent as an `ErrorResponse` so clients
+//! can tell failures apart without parsing messages.
+
+use std::fmt;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+
+use crate::model::{ErrorResponse, FieldError};
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// Fields that failed validation, with what is wrong with each.
+    Validation(Vec<FieldError>),
+    /// A request that cannot be acted on as sent.
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    /// The request clashes with the current state of the data.
+    Conflict(String),
+    /// A unique field, such as a username, already has the value sent.
+    Duplicate {
+        field: String,
+        message: String,
+    },
+    /// A link or attempt that has expired.
+    Gone(String),
+    /// Too many failed attempts; another may be made after `retry_after`
+    /// seconds.
+    RateLimited {
+        message: String,
+        retry_after: i64,
+    },
+    /// Gemini, the mail server, the identity provider or the LMS failed.
+    Upstream(String),
+    /// A feature this server has not been configured for.
+    Unavailable(String),
+    Database(String),
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Duplicate { .. } => "duplicate",
+            ApiError::Gone(_) => "gone",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Database(_) => "database_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::Validation(_) => "Invalid input",
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Duplicate { message, .. }
+            | ApiError::Gone(message)
+            | ApiError::RateLimited { message, .. }
+            | ApiError::Upstream(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Database(message)
+            | ApiError::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) | ApiError::Duplicate { .. } => StatusCode::CONFLICT,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let errors = match self {
+            ApiError::Validation(errors) => errors.clone(),
+            ApiError::Duplicate { field, message } => vec![FieldError {
+                field: field.clone(),
+                message: message.clone(),
+            }],
+            _ => Vec::new(),
+        };
+        let response_json = &ErrorResponse {
+            status: "fail".to_string(),
+            code: self.code().to_string(),
+            message: self.message().to_string(),
+            errors,
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::RateLimited { retry_after, .. } = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response.json(response_json)
+    }
+}
+
+impl From<mongodb::error::Error> for ApiError {
+    fn from(error: mongodb::error::Error) -> ApiError {
+        ApiError::Database(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    async fn body(error: ApiError) -> serde_json::Value {
+        let bytes = to_bytes(error.error_response().into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_error_body() {
+        let error = ApiError::NotFound("Quiz not found".to_string());
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            body(error).await,
+            serde_json::json!({
+                "status": "fail",
+                "code": "not_found",
+                "message": "Quiz not found",
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_field_errors_are_listed() {
+        let error = ApiError::Duplicate {
+            field: "username".to_string(),
+            message: "Username is already taken".to_string(),
+        };
+        assert_eq!(error.status_code(), StatusCode::CONFLICT);
+        let body = body(error).await;
+        assert_eq!(body["code"], "duplicate");
+        assert_eq!(body["errors"][0]["field"], "username");
+    }
+
+    #[test]
+    fn test_rate_limit_says_when_to_retry() {
+        let error = ApiError::RateLimited {
+            message: "Too many failed sign-ins".to_string(),
+            retry_after: 30,
+        };
+        let response = error.error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+    }
+}
@@ -0,0 +1,586 @@
+//! Paper exams: printable copies of a quiz, each in one of the variants
+//! labelled in `PAPER_VARIANTS`, optional bubble sheets and an answer key.
+
+use printpdf::{
+    calculate_points_for_circle, BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument,
+    PdfDocumentReference, PdfLayerReference, Rect,
+};
+
+use crate::bubble_sheet::{
+    self, SheetCode, BUBBLE_RADIUS, CODE_BITS, CODE_CELL, FIDUCIALS, FIDUCIAL_SIZE, MAX_OPTIONS,
+    MAX_SHEETS, MAX_STUDENTS, ORIENTATION_MARK, PAGE_HEIGHT, PAGE_WIDTH, QUESTIONS_PER_SHEET,
+};
+use crate::model::QuizTable;
+use crate::quiz_export::option_label;
+use crate::variant::{build_variant, paper_seed, QuizVariant, PAPER_VARIANTS};
+
+const MARGIN: f32 = 20.0;
+const LINE_HEIGHT: f32 = 5.5;
+const BODY_SIZE: f32 = 11.0;
+
+pub struct ExamHeader {
+    pub course: String,
+    pub date: String,
+}
+
+/// One printed copy of a quiz.
+pub struct ExamCopy {
+    /// Index into `PAPER_VARIANTS`.
+    pub variant: usize,
+    pub layout: QuizVariant,
+    /// Number of the student from 1, in `QuizTable.student_id` order, and
+    /// their id.
+    pub student: Option<(usize, String)>,
+}
+
+/// A copy for every student of the quiz, variants dealt out in turn, or with
+/// `per_student` unset (or no students) one copy of each variant.
+pub fn paper_copies(quiz: &QuizTable, variants: usize, per_student: bool) -> Vec<ExamCopy> {
+    let variants = variants.clamp(1, PAPER_VARIANTS.len());
+    let layout = |variant: usize| build_variant(quiz, paper_seed(&quiz._id, variant));
+
+    if per_student && !quiz.student_id.is_empty() {
+        quiz.student_id
+            .iter()
+            .enumerate()
+            .map(|(i, student)| ExamCopy {
+                variant: i % variants,
+                layout: layout(i % variants),
+                student: Some((i + 1, student.clone())),
+            })
+            .collect()
+    } else {
+        (0..variants)
+            .map(|variant| ExamCopy {
+                variant,
+                layout: layout(variant),
+                student: None,
+            })
+            .collect()
+    }
+}
+
+/// Why copies of `quiz` cannot have bubble sheets, if they cannot.
+pub fn bubble_sheet_error(quiz: &QuizTable, copies: &[ExamCopy]) -> Option<String> {
+    let questions = copies
+        .iter()
+        .map(|c| c.layout.question_order.len())
+        .max()
+        .unwrap_or(0);
+    if questions > QUESTIONS_PER_SHEET * MAX_SHEETS {
+        return Some(format!(
+            "Bubble sheets hold at most {} questions",
+            QUESTIONS_PER_SHEET * MAX_SHEETS
+        ));
+    }
+    if quiz.questions.iter().any(|q| q.len() - 1 > MAX_OPTIONS) {
+        return Some(format!(
+            "Bubble sheets hold at most {} options per question",
+            MAX_OPTIONS
+        ));
+    }
+    if quiz.student_id.len() > MAX_STUDENTS {
+        return Some(format!(
+            "Bubble sheets can be printed for at most {} students",
+            MAX_STUDENTS
+        ));
+    }
+    None
+}
+
+/// Splits `text` into lines of at most `width` characters, breaking at
+/// spaces where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word.to_string();
+            while word.chars().count() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let split = word
+                    .char_indices()
+                    .nth(width)
+                    .map_or(word.len(), |(i, _)| i);
+                lines.push(word[..split].to_string());
+                word = word[split..].to_string();
+            }
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Characters of `size` point Helvetica that fit in `width` millimetres,
+/// assuming fairly wide characters.
+fn chars_per_line(width: f32, size: f32) -> usize {
+    (width / (size * 0.55 * 0.3528)) as usize
+}
+
+/// A document being written top to bottom, starting new pages as needed.
+struct Writer {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    y: f32,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+}
+
+impl Writer {
+    fn new(title: &str) -> Result<Writer, String> {
+        let (doc, page, layer) =
+            PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        let regular = doc
+            .add_builtin_font(BuiltinFont::Helvetica)
+            .map_err(|err| err.to_string())?;
+        let bold = doc
+            .add_builtin_font(BuiltinFont::HelveticaBold)
+            .map_err(|err| err.to_string())?;
+        let layer = doc.get_page(page).get_layer(layer);
+        Ok(Writer {
+            doc,
+            layer,
+            y: PAGE_HEIGHT - MARGIN,
+            regular,
+            bold,
+        })
+    }
+
+    /// Starts a new page, unless nothing has been written on this one.
+    fn new_page(&mut self) {
+        if self.y >= PAGE_HEIGHT - MARGIN {
+            return;
+        }
+        let (page, layer) = self
+            .doc
+            .add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn ensure_space(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            self.new_page();
+        }
+    }
+
+    fn text_at(&self, text: &str, size: f32, x: f32, y: f32, bold: bool) {
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer.use_text(text, size, Mm(x), Mm(y), font);
+    }
+
+    fn line(&mut self, text: &str, size: f32, bold: bool) {
+        self.ensure_space(LINE_HEIGHT);
+        self.y -= size * 0.3528 + 1.5;
+        self.text_at(text, size, MARGIN, self.y, bold);
+    }
+
+    /// `text` wrapped to the page, the first line starting with `label` and
+    /// the others indented to line up after it.
+    fn paragraph(&mut self, label: &str, text: &str, indent: f32) {
+        let label_width = label.chars().count() as f32 * BODY_SIZE * 0.55 * 0.3528 + 1.0;
+        let width = PAGE_WIDTH - 2.0 * MARGIN - indent - label_width;
+        let lines = wrap(text, chars_per_line(width, BODY_SIZE));
+
+        // Keep short questions together on one page.
+        self.ensure_space(LINE_HEIGHT * lines.len().min(4) as f32);
+        for (i, line) in lines.iter().enumerate() {
+            self.ensure_space(LINE_HEIGHT);
+            self.y -= LINE_HEIGHT;
+            if i == 0 {
+                self.text_at(label, BODY_SIZE, MARGIN + indent, self.y, true);
+            }
+            self.text_at(
+                line,
+                BODY_SIZE,
+                MARGIN + indent + label_width,
+                self.y,
+                false,
+            );
+        }
+    }
+
+    /// Short `entries` laid out down `count` columns, continuing on new
+    /// pages.
+    fn columns(&mut self, entries: &[String], count: usize) {
+        let width = (PAGE_WIDTH - 2.0 * MARGIN) / count as f32;
+        let mut rest = entries;
+        while !rest.is_empty() {
+            let rows = (((self.y - MARGIN) / LINE_HEIGHT) as usize).max(1);
+            let (page, next) = rest.split_at(rest.len().min(rows * count));
+            for (i, entry) in page.iter().enumerate() {
+                let x = MARGIN + (i / rows) as f32 * width;
+                let y = self.y - (i % rows + 1) as f32 * LINE_HEIGHT;
+                self.text_at(entry, BODY_SIZE, x, y, false);
+            }
+            self.y -= page.len().min(rows) as f32 * LINE_HEIGHT;
+            rest = next;
+            if !rest.is_empty() {
+                self.new_page();
+            }
+        }
+    }
+
+    fn space(&mut self, height: f32) {
+        self.y -= height;
+    }
+
+    fn rule(&mut self) {
+        self.space(2.0);
+        self.layer.set_outline_thickness(0.5);
+        self.layer.add_line(Line {
+            points: vec![
+                (printpdf::Point::new(Mm(MARGIN), Mm(self.y)), false),
+                (
+                    printpdf::Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(self.y)),
+                    false,
+                ),
+            ],
+            is_closed: false,
+        });
+        self.space(2.0);
+    }
+
+    fn filled_square(&self, (x, y): (f32, f32), size: f32) {
+        let half = size / 2.0;
+        self.layer.add_rect(Rect::new(
+            Mm(x - half),
+            Mm(y - half),
+            Mm(x + half),
+            Mm(y + half),
+        ));
+    }
+
+    fn outlined_square(&self, (x, y): (f32, f32), size: f32) {
+        let half = size / 2.0;
+        self.layer.add_rect(
+            Rect::new(Mm(x - half), Mm(y - half), Mm(x + half), Mm(y + half))
+                .with_mode(printpdf::path::PaintMode::Stroke),
+        );
+    }
+
+    fn circle(&self, (x, y): (f32, f32), radius: f32) {
+        self.layer.add_line(Line {
+            points: calculate_points_for_circle(Mm(radius), Mm(x), Mm(y)),
+            is_closed: true,
+        });
+    }
+
+    fn finish(self) -> Result<Vec<u8>, String> {
+        self.doc.save_to_bytes().map_err(|err| err.to_string())
+    }
+}
+
+fn copy_heading(writer: &mut Writer, header: &ExamHeader, variant: usize, title: &str) {
+    writer.line(&header.course, 16.0, true);
+    writer.line(
+        &format!(
+            "{}    Variant {}    {}",
+            title, PAPER_VARIANTS[variant], header.date
+        ),
+        11.0,
+        false,
+    );
+}
+
+fn student_line(copy: &ExamCopy) -> String {
+    match &copy.student {
+        Some((_, student)) => format!("Student: {}", student),
+        None => "Name: ________________________    Student ID: ______________".to_string(),
+    }
+}
+
+/// The exam copies, each followed by its bubble sheets when `bubble_sheet`
+/// is set.
+pub fn render_exam(
+    quiz: &QuizTable,
+    copies: &[ExamCopy],
+    header: &ExamHeader,
+    bubble_sheet: bool,
+) -> Result<Vec<u8>, String> {
+    let mut writer = Writer::new(&header.course)?;
+
+    for copy in copies {
+        writer.new_page();
+        copy_heading(&mut writer, header, copy.variant, "Exam");
+        writer.line(&student_line(copy), 11.0, false);
+        writer.rule();
+        if bubble_sheet {
+            writer.line(
+                "Mark your answers on the bubble sheet by filling in the circles completely.",
+                10.0,
+                false,
+            );
+            writer.space(2.0);
+        }
+
+        for (i, (q, options)) in copy
+            .layout
+            .question_order
+            .iter()
+            .zip(&copy.layout.option_orders)
+            .enumerate()
+        {
+            let question = &quiz.questions[*q];
+            let mut text = question[0].clone();
+            if quiz.multi_select.contains_key(&q.to_string()) {
+                text.push_str(" (select all that apply)");
+            }
+
+            writer.space(2.0);
+            writer.paragraph(&format!("{}.", i + 1), &text, 0.0);
+            for (o, original) in options.iter().enumerate() {
+                writer.paragraph(
+                    &format!("{})", option_label(o)),
+                    &question[original + 1],
+                    8.0,
+                );
+            }
+        }
+
+        if bubble_sheet {
+            let sheets = copy
+                .layout
+                .question_order
+                .len()
+                .div_ceil(QUESTIONS_PER_SHEET)
+                .max(1);
+            for sheet in 0..sheets {
+                draw_bubble_sheet(&mut writer, copy, header, sheet, sheets);
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+fn draw_bubble_sheet(
+    writer: &mut Writer,
+    copy: &ExamCopy,
+    header: &ExamHeader,
+    sheet: usize,
+    sheets: usize,
+) {
+    writer.new_page();
+
+    for fiducial in FIDUCIALS {
+        writer.filled_square(fiducial, FIDUCIAL_SIZE);
+    }
+
+    writer.text_at(&header.course, 14.0, 30.0, 276.0, true);
+    writer.text_at(
+        &format!(
+            "Bubble sheet {} of {}    Variant {}    {}",
+            sheet + 1,
+            sheets,
+            PAPER_VARIANTS[copy.variant],
+            header.date
+        ),
+        10.0,
+        30.0,
+        269.0,
+        false,
+    );
+    writer.text_at(&student_line(copy), 10.0, 30.0, 262.0, false);
+
+    let code = SheetCode {
+        variant: copy.variant,
+        sheet,
+        student: copy.student.as_ref().map_or(0, |(n, _)| *n),
+    }
+    .encode();
+    writer.filled_square(ORIENTATION_MARK, CODE_CELL);
+    writer.layer.set_outline_thickness(0.5);
+    for bit in 0..CODE_BITS {
+        let center = bubble_sheet::code_cell_center(bit);
+        if code & (1 << bit) != 0 {
+            writer.filled_square(center, CODE_CELL);
+        } else {
+            writer.outlined_square(center, CODE_CELL);
+        }
+    }
+    writer.text_at("Do not mark the row above.", 7.0, 40.0, 246.0, false);
+
+    let first = sheet * QUESTIONS_PER_SHEET;
+    let slots = copy
+        .layout
+        .question_order
+        .len()
+        .saturating_sub(first)
+        .min(QUESTIONS_PER_SHEET);
+    let options_on_sheet = copy.layout.option_orders[first..first + slots]
+        .iter()
+        .map(Vec::len)
+        .max()
+        .unwrap_or(0);
+
+    for column in 0..bubble_sheet::columns() {
+        if column * bubble_sheet::ROWS_PER_COLUMN >= slots {
+            break;
+        }
+        for option in 0..options_on_sheet {
+            let (x, y) = bubble_sheet::option_heading(column, option);
+            writer.text_at(&option_label(option), 9.0, x, y, true);
+        }
+    }
+
+    writer.layer.set_outline_thickness(0.8);
+    for slot in 0..slots {
+        let (x, y) = bubble_sheet::label_position(slot);
+        writer.text_at(&format!("{}", first + slot + 1), 10.0, x, y, true);
+        for option in 0..copy.layout.option_orders[first + slot].len() {
+            writer.circle(bubble_sheet::bubble_center(slot, option), BUBBLE_RADIUS);
+        }
+    }
+
+    // The sheet takes the whole page.
+    writer.y = 0.0;
+}
+
+/// The correct options of every variant among `copies`, in the order each
+/// variant shows them, then which variant each student got.
+pub fn render_answer_key(
+    quiz: &QuizTable,
+    copies: &[ExamCopy],
+    header: &ExamHeader,
+) -> Result<Vec<u8>, String> {
+    let mut writer = Writer::new(&format!("{} answer key", header.course))?;
+
+    let mut seen = Vec::new();
+    for copy in copies {
+        if seen.contains(&copy.variant) {
+            continue;
+        }
+        seen.push(copy.variant);
+
+        writer.new_page();
+        copy_heading(&mut writer, header, copy.variant, "Answer key");
+        writer.rule();
+
+        let entries: Vec<String> = copy
+            .layout
+            .question_order
+            .iter()
+            .zip(&copy.layout.option_orders)
+            .enumerate()
+            .map(|(i, (q, options))| {
+                let correct: Vec<usize> = match quiz.multi_select.get(&q.to_string()) {
+                    Some(correct) => correct.iter().map(|o| *o as usize).collect(),
+                    None => vec![quiz.answers[*q] as usize],
+                };
+                let letters: Vec<String> = options
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, original)| correct.contains(original))
+                    .map(|(o, _)| option_label(o))
+                    .collect();
+                format!("{}. {}  (Q{})", i + 1, letters.join(", "), q + 1)
+            })
+            .collect();
+        writer.columns(&entries, 4);
+    }
+
+    let students: Vec<&ExamCopy> = copies.iter().filter(|c| c.student.is_some()).collect();
+    if !students.is_empty() {
+        writer.new_page();
+        writer.line("Variants by student", 14.0, true);
+        writer.rule();
+        for copy in students {
+            if let Some((_, student)) = &copy.student {
+                writer.paragraph(&format!("{}:", PAPER_VARIANTS[copy.variant]), student, 0.0);
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiz(students: usize) -> QuizTable {
+        QuizTable {
+            _id: "quiz".to_string(),
+            faculty_id: "faculty".to_string(),
+            questions: (0..60)
+                .map(|q| {
+                    let mut question = vec![format!("Question {} about something long enough to wrap onto a second line of the printed page", q)];
+                    question.extend((0..4).map(|o| format!("option {}", o)));
+                    question
+                })
+                .collect(),
+            answers: vec![1; 60],
+            student_id: (0..students).map(|s| format!("student{}", s)).collect(),
+            shuffle_questions: true,
+            shuffle_options: true,
+            ..Default::default()
+        }
+    }
+
+    fn header() -> ExamHeader {
+        ExamHeader {
+            course: "Biology 101".to_string(),
+            date: "2026-10-19".to_string(),
+        }
+    }
+
+    fn page_count(pdf: &[u8]) -> usize {
+        printpdf::lopdf::Document::load_mem(pdf)
+            .unwrap()
+            .get_pages()
+            .len()
+    }
+
+    #[test]
+    fn test_variants_dealt_to_students() {
+        let quiz = quiz(5);
+        let copies = paper_copies(&quiz, 2, true);
+        let variants: Vec<usize> = copies.iter().map(|c| c.variant).collect();
+        assert_eq!(variants, vec![0, 1, 0, 1, 0]);
+        assert_eq!(copies[2].layout, copies[0].layout);
+        assert_ne!(copies[1].layout, copies[0].layout);
+        assert_eq!(copies[4].student, Some((5, "student4".to_string())));
+    }
+
+    #[test]
+    fn test_one_copy_per_variant_without_students() {
+        let copies = paper_copies(&quiz(0), 9, true);
+        assert_eq!(copies.len(), PAPER_VARIANTS.len());
+        assert!(copies.iter().all(|c| c.student.is_none()));
+    }
+
+    #[test]
+    fn test_bubble_sheets_follow_each_copy() {
+        let quiz = quiz(2);
+        let copies = paper_copies(&quiz, 2, true);
+        let without = render_exam(&quiz, &copies, &header(), false).unwrap();
+        let with = render_exam(&quiz, &copies, &header(), true).unwrap();
+
+        // 60 questions need two sheets per copy.
+        assert_eq!(page_count(&with), page_count(&without) + 4);
+    }
+
+    #[test]
+    fn test_answer_key_has_a_page_per_variant() {
+        let quiz = quiz(0);
+        let copies = paper_copies(&quiz, 4, false);
+        let key = render_answer_key(&quiz, &copies, &header()).unwrap();
+        assert_eq!(page_count(&key), 4);
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    }
+}
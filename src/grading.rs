@@ -0,0 +1,179 @@
+use crate::model::{Answer, GradingStrategy, QuizMarks, QuizTable};
+
+/// Points for an attempt. `drawn` holds the original index of every question
+/// the attempt was given and `ans` is in original question and option order.
+pub fn score(quiz: &QuizTable, drawn: &[usize], ans: &[Answer]) -> f64 {
+    let scoring = &quiz.scoring;
+
+    drawn
+        .iter()
+        .map(|q| {
+            let weight = scoring.weights.get(*q).copied().unwrap_or(1.0);
+            let wrong = -scoring.negative_marking * weight;
+            let selected = ans.get(*q).map(Answer::selected).unwrap_or_default();
+            if selected.is_empty() {
+                return scoring.unanswered;
+            }
+
+            match quiz.multi_select.get(&q.to_string()) {
+                Some(correct) => {
+                    let hits = selected
+                        .iter()
+                        .filter(|s| correct.contains(&(**s as i32)))
+                        .count();
+                    let misses = selected.len() - hits;
+                    if hits == correct.len() && misses == 0 {
+                        weight
+                    } else if scoring.partial_credit && !correct.is_empty() {
+                        (hits as f64 - misses as f64).max(0.0) / correct.len() as f64 * weight
+                    } else {
+                        wrong
+                    }
+                }
+                None if selected.len() == 1 && selected[0] as i32 == quiz.answers[*q] => weight,
+                None => wrong,
+            }
+        })
+        .sum()
+}
+
+/// The grade kept in `QuizTable.student_marks` for a student, given all their
+/// graded attempts in the order they were submitted.
+pub fn recorded_grade(strategy: &GradingStrategy, attempts: &[QuizMarks]) -> Option<f64> {
+    match strategy {
+        GradingStrategy::Highest => attempts.iter().map(|a| a.marks).reduce(f64::max),
+        GradingStrategy::Latest => attempts.last().map(|a| a.marks),
+        GradingStrategy::Average => {
+            if attempts.is_empty() {
+                return None;
+            }
+            let total: f64 = attempts.iter().map(|a| a.marks).sum();
+            Some(total / attempts.len() as f64)
+        }
+    }
+}
+
+/// The most one attempt can score. Attempts that draw part of the pool see
+/// different questions, so this assumes the highest-weighted ones.
+pub fn max_score(quiz: &QuizTable) -> f64 {
+    let mut weights: Vec<f64> = (0..quiz.questions.len())
+        .map(|q| quiz.scoring.weights.get(q).copied().unwrap_or(1.0))
+        .collect();
+    weights.sort_by(|a, b| b.total_cmp(a));
+    let drawn = quiz.draw_count.unwrap_or(weights.len()).min(weights.len());
+    weights[..drawn].iter().sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Scoring;
+    use std::collections::HashMap;
+
+    fn quiz(scoring: Scoring) -> QuizTable {
+        let question = |q: usize| {
+            let mut question = vec![format!("question {q}")];
+            question.extend((0..4).map(|o| format!("option {o}")));
+            question
+        };
+
+        QuizTable {
+            _id: "quiz".to_string(),
+            faculty_id: "faculty".to_string(),
+            questions: (0..3).map(question).collect(),
+            answers: vec![0, 1, 0],
+            multi_select: HashMap::from([("2".to_string(), vec![1, 2])]),
+            scoring,
+            ..Default::default()
+        }
+    }
+
+    fn attempts(marks: &[f64]) -> Vec<QuizMarks> {
+        marks
+            .iter()
+            .enumerate()
+            .map(|(i, m)| QuizMarks {
+                _id: i.to_string(),
+                quiz_id: "quiz".to_string(),
+                student_id: "student".to_string(),
+                ans: Vec::new(),
+                marks: *m,
+                attempt: i as u32 + 1,
+                submitted_at: bson::DateTime::now(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_one_mark_per_correct_by_default() {
+        let quiz = quiz(Scoring::default());
+        let ans = vec![
+            Answer::Single(0),
+            Answer::Single(2),
+            Answer::Multiple(vec![2, 1]),
+        ];
+        assert_eq!(score(&quiz, &[0, 1, 2], &ans), 2.0);
+    }
+
+    #[test]
+    fn test_weights_and_negative_marking() {
+        let quiz = quiz(Scoring {
+            weights: vec![2.0, 4.0],
+            negative_marking: 0.25,
+            unanswered: 0.0,
+            partial_credit: false,
+        });
+        let ans = vec![Answer::Single(0), Answer::Single(3), Answer::default()];
+        assert_eq!(score(&quiz, &[0, 1, 2], &ans), 2.0 - 1.0);
+    }
+
+    #[test]
+    fn test_partial_credit_for_multi_select() {
+        let mut scoring = Scoring {
+            partial_credit: true,
+            ..Default::default()
+        };
+        let ans = vec![
+            Answer::default(),
+            Answer::default(),
+            Answer::Multiple(vec![1]),
+        ];
+        assert_eq!(score(&quiz(scoring.clone()), &[2], &ans), 0.5);
+
+        scoring.partial_credit = false;
+        assert_eq!(score(&quiz(scoring), &[2], &ans), 0.0);
+    }
+
+    #[test]
+    fn test_unanswered_rule_only_for_drawn_questions() {
+        let quiz = quiz(Scoring {
+            unanswered: -0.5,
+            ..Default::default()
+        });
+        assert_eq!(score(&quiz, &[1], &[]), -0.5);
+    }
+
+    #[test]
+    fn test_recorded_grade() {
+        let marks = attempts(&[4.0, 9.0, 5.0]);
+        assert_eq!(recorded_grade(&GradingStrategy::Highest, &marks), Some(9.0));
+        assert_eq!(recorded_grade(&GradingStrategy::Latest, &marks), Some(5.0));
+        assert_eq!(recorded_grade(&GradingStrategy::Average, &marks), Some(6.0));
+    }
+
+    #[test]
+    fn test_no_attempts_no_grade() {
+        assert_eq!(recorded_grade(&GradingStrategy::Average, &[]), None);
+    }
+
+    #[test]
+    fn test_max_score() {
+        let mut quiz = quiz(Scoring {
+            weights: vec![2.0, 4.0],
+            ..Default::default()
+        });
+        assert_eq!(max_score(&quiz), 7.0);
+        quiz.draw_count = Some(2);
+        assert_eq!(max_score(&quiz), 6.0);
+    }
+}
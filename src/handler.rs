@@ -1,89 +1,342 @@
-use std::env;
+use std::collections::{HashMap, HashSet};
 
 use crate::initialiser::Util;
 use crate::model::{
-    AIResponse, Content, CreateFlash, CreateQuiz, Faculty, Flashcard, GenerateContentRequest,
-    GenerateContentResponse, GenerationConfig, GenericResponse, Part, Quiz, RequestAIQuery,
-    Student, User,
+    AIResponse, AccountToken, AdminUserAction, Answer, ApiKey, ApiKeyCreatedResponse,
+    ApiKeySummary, ApiKeysResponse, ApiScope, AssignToClass, Attempt, AttemptHistoryResponse,
+    AttemptResponse, BankQuestion, ChangePassword, Class, ClassesResponse, CreateApiKey,
+    CreateClass, CreateDeck, CreateFlash, CreateQuiz, Deck, DeckAccess, DecksResponse,
+    DueCardsResponse, EditDeckCard, Enrollment, ExamPdf, ExportDeck, ExportQuiz, Faculty,
+    FieldError, FlashMode, Flashcard, ForgotPassword, ForkDeck, GenerateContentResponse,
+    GenericResponse, GradePushFailure, GrantExtraTime, ImportDeck, ImportQuestions,
+    ImportQuestionsResponse, ImportRoster, ImportRosterResponse, ItemError, ListUsers,
+    LoginFailures, LoginRecord, LoginResponse, LtiDeepLink, LtiLaunch, LtiLaunchResponse, LtiLogin,
+    LtiQuizChoice, LtiResourceLink, OidcCallback, OidcLogin, PasswordResetResponse, PickLtiQuiz,
+    PublishQuiz, PushGrades, PushGradesResponse, QuestionBankResponse, Quiz, QuizMarks, QuizTable,
+    RecoveryCodesResponse, RemoveDeckCard, RequestAIQuery, RequestVerification, ResetPassword,
+    ReviewCard, ReviewState, RevokeApiKey, RosterRowResult, SaveAnswer, ScanSheets,
+    ScanSheetsResponse, ScannedCopy, SetTwoFactorPolicy, SetUserActive, ShareDeck, SsoIdentity,
+    StartQuiz, Student, StudyCard, SubmitQuiz, TokenPurpose, TwoFactor, TwoFactorConfirm,
+    TwoFactorPolicy, TwoFactorSetup, TwoFactorSetupResponse, UnlockUser, UpdateUsername, User,
+    UserResponse, UserSummary, UserType, UsersResponse, VerifyEmail, MAX_QUESTION_OPTIONS,
 };
 
+use crate::bubble_sheet::QUESTIONS_PER_SHEET;
+use crate::cloze;
+use crate::deck_io::{export_deck, import_deck, DeckFormat};
+use crate::exam_pdf::{
+    bubble_sheet_error, paper_copies, render_answer_key, render_exam, ExamHeader,
+};
+use crate::grading::max_score;
 use crate::helpers::{
-    generate_ai_content, get_user_pwd, hasher, make_flashcards, make_quiz, verify,
+    attempt_deadline, attempt_expired, by_username, count_login_failure, enroll_in_class,
+    finalise_attempt, find_token, generate_ai_content, get_attempt, get_bank_questions, get_class,
+    get_deck, get_login_failures, get_login_record, get_marks, get_open_attempt,
+    get_owned_questions, get_quiz, get_sso_user, get_study_cards, get_user, get_user_classes,
+    get_user_decks, has_role, hash_recovery_code, hasher, is_admin, is_duplicate_key, issue_token,
+    list_users, make_cloze_flashcards, make_flashcards, make_quiz, provision_sso_user,
+    quiz_collection, record_audit, redeem_second_factor, redeem_token, remaining_seconds,
+    share_with_students, sync_sso_user, take_sign_in, temporary_password, two_factor_required,
+    username_collation, verify, TWO_FACTOR_POLICY_ID,
+};
+use crate::lti::{
+    DeepLinkingSettings, LaunchClaims, LoginInitiation, Lti, Score, DEEP_LINKING_REQUEST,
+    RESOURCE_LINK_REQUEST,
 };
+use crate::mailer::{password_reset_email, verification_email, Mailer};
+use crate::oidc::{random_secret, IdClaims};
+use crate::omr::{load_pages, marked_answers, read_sheet, ScannedSheet};
+use crate::quiz_export::{export_quiz, QuizFormat};
+use crate::quiz_import::{import_questions, question_key};
+use crate::roster::{class_matches, parse_roster};
+use crate::scheduler::{review, START_EASE};
+use crate::throttle::{ip_key, reset_key, user_key, IP_LOCKOUT_THRESHOLD, USER_LOCKOUT_THRESHOLD};
+use crate::tokens::new_token;
+use crate::two_factor::{code_step, new_secret, otpauth_uri, qr_svg, recovery_codes};
+use crate::validation::{credential_errors, email_errors, password_errors, username_errors};
+use crate::variant::{build_variant, paper_seed, PAPER_VARIANTS};
 
 use actix_web::web::Data;
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
 
 use bson::{doc, to_document};
-use gcp_auth::AuthenticationManager;
+use futures_util::TryStreamExt;
 
-use actix_multipart::form::MultipartForm;
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_multipart::form::{MultipartForm, MultipartFormConfig};
+use actix_web::{
+    get,
+    http::header::{self, ContentDisposition},
+    post, web, HttpRequest, HttpResponse,
+};
 
 extern crate mongodb;
-// use chrono::prelude::*;
-use mongodb::{bson::Document, Database};
+use crate::api_keys::{new_api_key, ApiKeyAuth, Caller};
+use crate::error::ApiError;
+use crate::jwt_utils::{make_token, JWTPayload, Session, SESSION_SECS};
+use chrono::prelude::*;
+use mongodb::{
+    bson::Document,
+    options::{
+        FindOneAndUpdateOptions, FindOptions, ReplaceOptions, ReturnDocument, UpdateOptions,
+    },
+    Database,
+};
 use uuid::Uuid;
 
-static MODEL_NAME: &str = "gemini-pro";
-
 #[get("/healthchecker")]
-async fn health_checker_handler() -> impl Responder {
+async fn health_checker_handler() -> Result<HttpResponse, ApiError> {
     const MESSAGE: &str = "All Ok";
 
     let response_json = &GenericResponse {
         status: "success".to_string(),
         message: MESSAGE.to_string(),
     };
-    HttpResponse::Ok().json(response_json)
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+fn too_many_attempts(wait: chrono::Duration) -> ApiError {
+    let seconds = wait.num_seconds().max(1);
+    ApiError::RateLimited {
+        message: format!("Too many failed sign-ins; try again in {} seconds", seconds),
+        retry_after: seconds,
+    }
+}
+
+fn client_ip(req: &HttpRequest) -> String {
+    req.peer_addr()
+        .map(|a| a.ip().to_string())
+        .unwrap_or_default()
+}
+
+fn invalid_credentials() -> ApiError {
+    ApiError::Unauthorized("Invalid username or password".to_string())
+}
+
+/// Refuses a sign-in if the username or the client address must wait
+/// after earlier failures.
+async fn check_throttle(db: &Database, username: &str, ip: &str) -> Result<(), ApiError> {
+    let keys = [user_key(username), ip_key(ip)];
+    let records = get_login_failures(&keys, db.collection("login_failures"))
+        .await
+        .map_err(ApiError::Internal)?;
+    match records.iter().filter_map(|r| r.wait(Utc::now())).max() {
+        Some(wait) => Err(too_many_attempts(wait)),
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed sign-in against the username and the client address,
+/// auditing any lockout it causes. Returns `refusal`, or a request to wait
+/// if this failure means the next try has to.
+async fn login_failed(db: &Database, username: &str, ip: &str, refusal: ApiError) -> ApiError {
+    let coll = db.collection::<LoginFailures>("login_failures");
+    let now = Utc::now();
+    let subjects = [
+        (user_key(username), USER_LOCKOUT_THRESHOLD, "account_locked"),
+        (ip_key(ip), IP_LOCKOUT_THRESHOLD, "address_locked"),
+    ];
+    let mut wait = None;
+    for (key, threshold, kind) in subjects {
+        let record = match count_login_failure(&key, threshold, coll.clone()).await {
+            Ok((record, locked)) => {
+                if locked {
+                    let detail = format!("Locked after {} failed sign-ins", record.failures);
+                    if let Err(error) =
+                        record_audit(db, kind, Some(username), Some(ip), None, detail).await
+                    {
+                        log::error!("Could not record audit event: {}", error);
+                    }
+                }
+                record
+            }
+            Err(error) => {
+                log::error!("Could not record failed sign-in: {}", error);
+                continue;
+            }
+        };
+        wait = wait.max(record.wait(now));
+    }
+    match wait {
+        Some(wait) => too_many_attempts(wait),
+        None => refusal,
+    }
+}
+
+/// The active account `username`, if `password` is its password. Checks
+/// are throttled like `/login`, and unknown users are refused exactly like
+/// wrong passwords, so probing for usernames is throttled too.
+async fn password_account(
+    db: &Database,
+    util: &Util,
+    username: &str,
+    password: &str,
+    ip: &str,
+) -> Result<LoginRecord, ApiError> {
+    check_throttle(db, username, ip).await?;
+
+    let verified = match get_login_record(username, db.collection("users")).await {
+        Ok(s) => verify(password.to_string(), s.password.clone(), util.argon.clone())
+            .map(|ok| ok.then_some(s)),
+        Err(ApiError::NotFound(_)) => Ok(None),
+        Err(error) => return Err(error),
+    };
+    match verified {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(login_failed(db, username, ip, invalid_credentials()).await),
+        Err(error) => Err(ApiError::Internal(error)),
+    }
+}
+
+/// Refuses a user whose password is temporary, from an admin or a roster
+/// import. It only opens `/change_password`.
+fn check_password_current(user: &LoginRecord) -> Result<(), ApiError> {
+    if user.must_reset_password {
+        return Err(ApiError::Forbidden(
+            "Password must be changed before signing in".to_string(),
+        ));
+    }
+    Ok(())
 }
 
+/// Asks for the second factor of a user whose password was right, or refuses
+/// them if their role requires one they have not set up. Wrong codes count
+/// as failed sign-ins.
+async fn check_second_factor(
+    db: &Database,
+    util: &Util,
+    user: &LoginRecord,
+    code: Option<&str>,
+    ip: &str,
+) -> Result<(), ApiError> {
+    let enabled = user.two_factor.as_ref().is_some_and(|t| t.enabled);
+    match (enabled, code) {
+        (false, _) => {
+            let required = two_factor_required(user.user_type, db.collection("settings"))
+                .await
+                .map_err(ApiError::Internal)?;
+            if required {
+                return Err(ApiError::Forbidden(
+                    "Two-factor authentication must be set up before signing in".to_string(),
+                ));
+            }
+            Ok(())
+        }
+        (true, None) => Err(ApiError::Unauthorized(
+            "Two-factor code required".to_string(),
+        )),
+        (true, Some(code)) => {
+            let redeemed =
+                redeem_second_factor(user, code, util.argon.clone(), db.collection("users"))
+                    .await
+                    .map_err(ApiError::Internal)?;
+            if !redeemed {
+                return Err(login_failed(db, &user.username, ip, invalid_code()).await);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Signs a user in. Users with two-factor authentication enabled also send
+/// `code`, either a one-time password or a recovery code.
 #[post("/login")]
 async fn login_user(
+    req: HttpRequest,
     db: web::Data<Database>,
     util: Data<Util>,
     form: web::Form<User>,
-) -> impl Responder {
-    let coll = db.collection::<Document>("users");
+) -> Result<HttpResponse, ApiError> {
+    let ip = client_ip(&req);
+    let user = password_account(&db, &util, &form.username, &form.password, &ip).await?;
+    check_password_current(&user)?;
+    check_second_factor(&db, &util, &user, form.code.as_deref(), &ip).await?;
 
-    let get_user_passwd = match get_user_pwd(form.username.clone(), coll).await {
-        Ok(s) => s,
-        Err(error) => return error.to_string(),
+    let filter = doc! { "_id": user_key(&form.username) };
+    if let Err(error) = db
+        .collection::<LoginFailures>("login_failures")
+        .delete_one(filter, None)
+        .await
+    {
+        log::error!("Could not clear failed sign-ins: {}", error);
+    }
+    signed_in(&util, user._id, user.user_type)
+}
+
+/// Starts a session for a user who has proved who they are.
+fn signed_in(
+    util: &Util,
+    user_id: String,
+    role: Option<UserType>,
+) -> Result<HttpResponse, ApiError> {
+    let token = session_token(util, &user_id, role)?;
+    let response_json = &LoginResponse {
+        status: "success".to_string(),
+        message: "Login Successful".to_string(),
+        token,
+        user_id,
     };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+/// A session token for `user_id`, sent back as `Authorization: Bearer`.
+fn session_token(util: &Util, user_id: &str, role: Option<UserType>) -> Result<String, ApiError> {
+    let payload = JWTPayload::new(user_id, role, SESSION_SECS);
+    make_token(&payload, &util.jwt_secret).map_err(ApiError::Internal)
+}
 
-    let res = match verify(form.password.clone(), get_user_passwd, util.Argon.clone()) {
-        Ok(b) => {
-            if b {
-                let response_json = &GenericResponse {
-                    status: "success".to_string(),
-                    message: "user exists".to_string(),
-                };
-                HttpResponse::Ok().json(response_json)
-            } else {
-                let response_json = &GenericResponse {
-                    status: "success".to_string(),
-                    message: "user doesnot exists".to_string(),
-                };
-                HttpResponse::Ok().json(response_json)
+#[post("/admin/unlock_user")]
+async fn unlock_user(
+    db: web::Data<Database>,
+    session: Session,
+    form: web::Form<UnlockUser>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = check_admin(&db, &session).await?;
+
+    let filter = doc! { "_id": user_key(&form.username) };
+    match db
+        .collection::<LoginFailures>("login_failures")
+        .delete_one(filter, None)
+        .await
+    {
+        Ok(s) if s.deleted_count == 0 => Err(ApiError::NotFound(
+            "User has no failed sign-ins".to_string(),
+        )),
+        Ok(_) => {
+            let detail = "Failed sign-ins cleared by an admin".to_string();
+            if let Err(error) = record_audit(
+                &db,
+                "account_unlocked",
+                Some(&form.username),
+                None,
+                Some(&admin_id),
+                detail,
+            )
+            .await
+            {
+                log::error!("Could not record audit event: {}", error);
             }
-        }
-        Err(error) => {
             let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
+                status: "success".to_string(),
+                message: "User unlocked".to_string(),
             };
-            HttpResponse::InternalServerError().json(response_json)
+            Ok(HttpResponse::Ok().json(response_json))
         }
-    };
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
 
-    "Login Successful".to_string()
+fn gemini_disabled() -> ApiError {
+    ApiError::Unavailable("Gemini is not configured on this server".to_string())
 }
 
 #[post("/generate_flashcard")]
-async fn generate_flashcard(MultipartForm(body): MultipartForm<RequestAIQuery>) -> impl Responder {
+async fn generate_flashcard(
+    util: Data<Util>,
+    _caller: Caller,
+    MultipartForm(body): MultipartForm<RequestAIQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(gemini) = &util.gemini else {
+        return Err(gemini_disabled());
+    };
     let prompt = format!(
         "Extract {:?} key points from the text. Present the information in a JSON format with two fields:
 * key_points_array: An array containing each key point as a string.
@@ -106,64 +359,27 @@ Text: {:?}
         body.count, body.content
     );
 
-    let gen_response: GenerateContentResponse = match generate_ai_content(prompt).await {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
-        }
-    };
+    let gen_response: GenerateContentResponse = generate_ai_content(gemini, prompt)
+        .await
+        .map_err(ApiError::Upstream)?;
 
     let response_json = &AIResponse {
         status: "success".to_string(),
         response: gen_response,
     };
 
-    HttpResponse::Ok().json(response_json)
-}
-
-async fn generate_flashcard_tester(topic: String, count: i8) -> Result<String, String> {
-    let prompt = format!(
-        "Extract {:?} key points from the text. Present the information in a JSON format with two fields:
-* key_points_array: An array containing each key point as a string.
-* number_of_key_points: The number of elements in the key_points_array.
-Use only these valid fields.
-
-Text: {:?}
-
-**Example:**
-{{
-    \"key_points_array\": [
-        \"key point 0\",
-        \"key point 1\",
-        \"key point 2\",
-        \"key point 3\",
-        \"key point 4\",
-    ],
-    \"number_of_key_points\": 5
-}}
-",
-        count, topic
-    );
-
-    let gen_response: GenerateContentResponse = match generate_ai_content(prompt).await {
-        Ok(s) => s,
-        Err(error) => return Err(error),
-    };
-
-    let part = &gen_response.candidates[0].content.parts[0];
-
-    match part {
-        Part::Text(t) => return Ok(t.to_string()),
-        _ => return Err("not the same type".to_string()),
-    };
+    Ok(HttpResponse::Ok().json(response_json))
 }
 
 #[post("/generate_quiz")]
-async fn generate_quiz(MultipartForm(body): MultipartForm<RequestAIQuery>) -> impl Responder {
+async fn generate_quiz(
+    util: Data<Util>,
+    _caller: Caller,
+    MultipartForm(body): MultipartForm<RequestAIQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(gemini) = &util.gemini else {
+        return Err(gemini_disabled());
+    };
     let prompt = format!("**Prompt:**
 
 Given a passage of text `{:?}` and an integer {:?}, generate a JSON object containing {:?} multiple choice questions (MCQs) based on the text. Each MCQ should have the following structure:
@@ -199,23 +415,31 @@ Given a passage of text `{:?}` and an integer {:?}, generate a JSON object conta
 ]
 }}", body.content, body.count, body.count, body.count );
 
-    let gen_response: GenerateContentResponse = match generate_ai_content(prompt).await {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
-        }
-    };
+    let gen_response: GenerateContentResponse = generate_ai_content(gemini, prompt)
+        .await
+        .map_err(ApiError::Upstream)?;
 
     let response_json = &AIResponse {
         status: "success".to_string(),
         response: gen_response,
     };
 
-    HttpResponse::Ok().json(response_json)
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+/// Refuses the request if any field has `errors`.
+fn invalid_input(errors: Vec<FieldError>) -> Result<(), ApiError> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(ApiError::Validation(errors))
+}
+
+fn username_taken() -> ApiError {
+    ApiError::Duplicate {
+        field: "username".to_string(),
+        message: "Username is already taken".to_string(),
+    }
 }
 
 #[post("/add_student")]
@@ -223,56 +447,52 @@ async fn add_student(
     db: web::Data<Database>,
     util: Data<Util>,
     form: web::Form<User>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = credential_errors(&form.username, &form.password);
+    errors.extend(email_errors(form.email.as_deref()));
+    invalid_input(errors)?;
     let coll = db.collection::<Document>("users");
 
-    let pwd = match hasher(form.password.to_owned(), util.Argon.clone()) {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
-        }
-    };
+    let pwd = hasher(form.password.to_owned(), util.argon.clone()).map_err(ApiError::Internal)?;
 
     let user = Student {
         _id: Uuid::new_v4().to_string(),
         username: form.username.clone(),
         password: pwd,
+        user_type: UserType::Student,
+        active: true,
+        must_reset_password: false,
+        display_name: None,
+        email: form.email.clone(),
+        email_verified: false,
+        class: None,
         quiz: Some(Vec::new()),
         flashes: Some(Vec::new()),
     };
 
-    let bson_user = match to_document(&user) {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
-        }
-    };
+    let bson_user = to_document(&user).map_err(|error| ApiError::Internal(error.to_string()))?;
 
     let resp = match coll.insert_one(bson_user, None).await {
         Ok(s) => s,
+        Err(error) if is_duplicate_key(&error) => return Err(username_taken()),
         Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
+            return Err(ApiError::from(error));
         }
     };
 
+    if let Some(email) = &user.email {
+        // Registration stands even if the mail fails; the user can ask again.
+        if let Err(error) = send_verification(&db, &util, &user._id, &user.username, email).await {
+            log::error!("Could not send verification email: {}", error);
+        }
+    }
+
     let response_json = &GenericResponse {
         status: "success".to_string(),
         message: resp.inserted_id.to_string(),
     };
 
-    HttpResponse::Ok().json(response_json)
+    Ok(HttpResponse::Ok().json(response_json))
 }
 
 #[post("/add_faculty")]
@@ -280,189 +500,3635 @@ async fn add_faculty(
     db: web::Data<Database>,
     util: Data<Util>,
     form: web::Form<User>,
-) -> impl Responder {
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = credential_errors(&form.username, &form.password);
+    errors.extend(email_errors(form.email.as_deref()));
+    invalid_input(errors)?;
     let coll = db.collection::<Document>("users");
 
-    let pwd = match hasher(form.password.to_owned(), util.Argon.clone()) {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
-        }
-    };
+    let pwd = hasher(form.password.to_owned(), util.argon.clone()).map_err(ApiError::Internal)?;
 
     let user = Faculty {
         _id: Uuid::new_v4().to_string(),
         username: form.username.clone(),
         password: pwd,
+        user_type: UserType::Faculty,
+        active: true,
+        must_reset_password: false,
+        email: form.email.clone(),
+        email_verified: false,
         quiz: Some(Vec::new()),
         flashes: Some(Vec::new()),
     };
 
-    let bson_user = match to_document(&user) {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
-        }
-    };
+    let bson_user = to_document(&user).map_err(|error| ApiError::Internal(error.to_string()))?;
 
     let resp = match coll.insert_one(bson_user, None).await {
         Ok(s) => s,
+        Err(error) if is_duplicate_key(&error) => return Err(username_taken()),
         Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
+            return Err(ApiError::from(error));
         }
     };
 
+    if let Some(email) = &user.email {
+        // Registration stands even if the mail fails; the user can ask again.
+        if let Err(error) = send_verification(&db, &util, &user._id, &user.username, email).await {
+            log::error!("Could not send verification email: {}", error);
+        }
+    }
+
     let response_json = &GenericResponse {
         status: "success".to_string(),
         message: resp.inserted_id.to_string(),
     };
 
-    HttpResponse::Ok().json(response_json)
+    Ok(HttpResponse::Ok().json(response_json))
 }
 
-#[post("/create_flash")]
-async fn create_flash(db: web::Data<Database>, form: web::Form<CreateFlash>) -> impl Responder {
-    let coll = db.collection::<Document>("users");
-    let cont = match make_flashcards(form.topic.clone(), form.count).await {
-        Ok(s) => s,
-        Err(error) => {
+/// The id of the signed-in admin. Refuses anyone else, including admins
+/// deactivated or demoted since they signed in.
+async fn check_admin(db: &Database, session: &Session) -> Result<String, ApiError> {
+    match is_admin(session.user_id(), db.collection("users")).await {
+        Ok(true) => Ok(session.user_id().to_string()),
+        Ok(false) => Err(ApiError::Forbidden(
+            "Only admins can manage users".to_string(),
+        )),
+        Err(error) => Err(ApiError::Internal(error)),
+    }
+}
+
+/// Refuses a signed-in user acting on someone else's account.
+fn own_account(session: &Session, user_id: &str) -> Result<(), ApiError> {
+    if session.user_id() == user_id {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(
+            "You can only manage your own account".to_string(),
+        ))
+    }
+}
+
+/// Applies `update` to `user_id`, answering 404 if there is no such user.
+async fn update_user(
+    db: &Database,
+    user_id: &str,
+    update: Document,
+    message: &str,
+) -> Result<HttpResponse, ApiError> {
+    let coll = db.collection::<UserSummary>("users");
+    match coll.update_one(doc! { "_id": user_id }, update, None).await {
+        Ok(s) if s.matched_count == 0 => Err(ApiError::NotFound("User not found".to_string())),
+        Ok(_) => {
             let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
+                status: "success".to_string(),
+                message: message.to_string(),
             };
-            return HttpResponse::InternalServerError().json(response_json);
+            Ok(HttpResponse::Ok().json(response_json))
         }
-    };
+        Err(error) if is_duplicate_key(&error) => Err(username_taken()),
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
 
-    let flash = Flashcard {
-        _id: Uuid::new_v4().to_string(),
-        topic: form.topic.clone(),
-        content: cont.clone(),
+#[get("/admin/users")]
+async fn list_users_handler(
+    db: web::Data<Database>,
+    session: Session,
+    query: web::Query<ListUsers>,
+) -> Result<HttpResponse, ApiError> {
+    check_admin(&db, &session).await?;
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(50).clamp(1, 200);
+    match list_users(query.role, page, per_page, db.collection("users")).await {
+        Ok((users, total)) => Ok(HttpResponse::Ok().json(&UsersResponse {
+            status: "success".to_string(),
+            users,
+            page,
+            per_page,
+            total,
+        })),
+        Err(error) => Err(ApiError::Internal(error)),
+    }
+}
+
+#[get("/admin/user/{user_id}")]
+async fn get_user_handler(
+    db: web::Data<Database>,
+    session: Session,
+    user_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    check_admin(&db, &session).await?;
+
+    match get_user(&user_id, db.collection("users")).await {
+        Ok(user) => Ok(HttpResponse::Ok().json(&UserResponse {
+            status: "success".to_string(),
+            user,
+        })),
+        Err(error) => Err(error),
+    }
+}
+
+#[post("/admin/update_username")]
+async fn update_username(
+    db: web::Data<Database>,
+    session: Session,
+    form: web::Form<UpdateUsername>,
+) -> Result<HttpResponse, ApiError> {
+    check_admin(&db, &session).await?;
+
+    invalid_input(username_errors(&form.username))?;
+
+    let update = doc! { "$set": { "username": &form.username } };
+    update_user(&db, &form.user_id, update, "Username updated").await
+}
+
+#[post("/admin/set_active")]
+async fn set_user_active(
+    db: web::Data<Database>,
+    session: Session,
+    form: web::Form<SetUserActive>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = check_admin(&db, &session).await?;
+    if admin_id == form.user_id && !form.active {
+        return Err(ApiError::BadRequest(
+            "Admins cannot deactivate themselves".to_string(),
+        ));
+    }
+
+    let update = doc! { "$set": { "active": form.active } };
+    let message = if form.active {
+        "User reactivated"
+    } else {
+        "User deactivated"
     };
+    update_user(&db, &form.user_id, update, message).await
+}
 
-    let bson_flash = match to_document(&flash) {
-        Ok(s) => s,
-        Err(error) => {
+#[post("/admin/delete_user")]
+async fn delete_user(
+    db: web::Data<Database>,
+    session: Session,
+    form: web::Form<AdminUserAction>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = check_admin(&db, &session).await?;
+    if admin_id == form.user_id {
+        return Err(ApiError::BadRequest(
+            "Admins cannot delete themselves".to_string(),
+        ));
+    }
+
+    let coll = db.collection::<UserSummary>("users");
+    match coll.delete_one(doc! { "_id": &form.user_id }, None).await {
+        Ok(s) if s.deleted_count == 0 => Err(ApiError::NotFound("User not found".to_string())),
+        Ok(_) => {
             let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
+                status: "success".to_string(),
+                message: "User deleted".to_string(),
             };
-            return HttpResponse::InternalServerError().json(response_json);
+            Ok(HttpResponse::Ok().json(response_json))
         }
-    };
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
 
-    let filter = doc! { "_id": &form.user_id };
-    let update = doc! { "$push": { "flashes": bson_flash} };
+#[post("/admin/force_password_reset")]
+async fn force_password_reset(
+    db: web::Data<Database>,
+    session: Session,
+    util: Data<Util>,
+    form: web::Form<AdminUserAction>,
+) -> Result<HttpResponse, ApiError> {
+    check_admin(&db, &session).await?;
 
-    let resp = match coll.update_one(filter, update, None).await {
-        Ok(s) => s,
-        Err(error) => {
+    let temporary = temporary_password();
+    let pwd = hasher(temporary.clone(), util.argon.clone()).map_err(ApiError::Internal)?;
+
+    let update = doc! { "$set": { "password": pwd, "must_reset_password": true } };
+    let message = "Password reset; it must be changed at the next sign-in";
+    update_user(&db, &form.user_id, update, message).await?;
+    Ok(HttpResponse::Ok().json(&PasswordResetResponse {
+        status: "success".to_string(),
+        message: message.to_string(),
+        temporary_password: temporary,
+    }))
+}
+
+/// Replaces the password, asking for the old one and, when two-factor
+/// authentication is on, a code.
+#[post("/change_password")]
+async fn change_password(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<ChangePassword>,
+) -> Result<HttpResponse, ApiError> {
+    let ip = client_ip(&req);
+    let user = password_account(&db, &util, &form.username, &form.password, &ip).await?;
+    let code = form.code.as_deref().filter(|c| !c.trim().is_empty());
+    check_second_factor(&db, &util, &user, code, &ip).await?;
+
+    invalid_input(password_errors(&form.new_password, &form.username))?;
+    let pwd = hasher(form.new_password.clone(), util.argon.clone()).map_err(ApiError::Internal)?;
+
+    let coll = db.collection::<UserSummary>("users");
+    let filter = doc! { "_id": &user._id };
+    let update = doc! { "$set": { "password": pwd, "must_reset_password": false } };
+    match coll.update_one(filter, update, None).await {
+        Ok(_) => {
             let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
+                status: "success".to_string(),
+                message: "Password changed".to_string(),
             };
-            return HttpResponse::InternalServerError().json(response_json);
+            Ok(HttpResponse::Ok().json(response_json))
         }
-    };
-
-    if resp.modified_count == 0 {
-        let response_json = &GenericResponse {
-            status: "fail".to_string(),
-            message: "Failed to update value".to_string(),
-        };
-        return HttpResponse::InternalServerError().json(response_json);
+        Err(error) => Err(ApiError::from(error)),
     }
+}
 
-    let response_json = &GenericResponse {
-        status: "success".to_string(),
-        message: cont,
+const OIDC_LOGIN_MINUTES: i64 = 10;
+
+fn sso_disabled() -> ApiError {
+    ApiError::Unavailable("Single sign-on is not configured on this server".to_string())
+}
+
+/// Starts a single sign-on by sending the browser to the identity provider.
+#[get("/oidc/login")]
+async fn oidc_login(db: web::Data<Database>, util: Data<Util>) -> Result<HttpResponse, ApiError> {
+    let Some(oidc) = &util.oidc else {
+        return Err(sso_disabled());
+    };
+
+    let (state, state_hash) = new_token();
+    let login = OidcLogin {
+        _id: state_hash,
+        nonce: random_secret(),
+        pkce_verifier: random_secret(),
+        expires_at: bson::DateTime::from_chrono(
+            Utc::now() + chrono::Duration::minutes(OIDC_LOGIN_MINUTES),
+        ),
     };
+    let url = oidc
+        .authorize_url(&state, &login.nonce, &login.pkce_verifier)
+        .await
+        .map_err(ApiError::Upstream)?;
 
-    HttpResponse::Ok().json(response_json)
+    match db
+        .collection::<OidcLogin>("oidc_logins")
+        .insert_one(&login, None)
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .finish()),
+        Err(error) => Err(ApiError::from(error)),
+    }
 }
 
-#[post("/create_quiz")]
-async fn create_quiz(db: web::Data<Database>, form: web::Form<CreateQuiz>) -> impl Responder {
-    let coll = db.collection::<Document>("users");
-    let cont = match make_quiz(form.topic.clone(), form.count).await {
+/// Finishes a single sign-on with a session, as `/login` does. Users
+/// signing in for the first time are created with the role their
+/// identity-provider groups map to.
+#[get("/oidc/callback")]
+async fn oidc_callback(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    query: web::Query<OidcCallback>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(oidc) = &util.oidc else {
+        return Err(sso_disabled());
+    };
+
+    let login = take_sign_in::<OidcLogin>(&query.state, db.collection("oidc_logins"))
+        .await
+        .map_err(ApiError::BadRequest)?;
+    let claims = match (&query.code, &query.error) {
+        (Some(code), None) => oidc.sign_in(code, &login.pkce_verifier, &login.nonce).await,
+        (_, error) => Err(query
+            .error_description
+            .clone()
+            .or(error.clone())
+            .unwrap_or_else(|| "The identity provider sent no code".to_string())),
+    };
+    let claims = match claims {
         Ok(s) => s,
         Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
+            return Err(ApiError::Unauthorized(format!(
+                "Single sign-on failed: {}",
+                error
+            )));
         }
     };
 
-    let quiz = Quiz {
-        _id: Uuid::new_v4().to_string(),
-        quizzes: cont.clone(),
+    let identity = SsoIdentity {
+        issuer: oidc.issuer().to_string(),
+        subject: claims.sub.clone(),
     };
+    let user = sso_user(&db, &util, identity, oidc.role(&claims), &claims).await?;
+    signed_in(&util, user._id, user.user_type)
+}
 
-    let bson_quiz = match to_document(&quiz) {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
+/// The user signing in as `identity`, created on their first sign-in and
+/// otherwise brought up to date with what the provider says about them.
+async fn sso_user(
+    db: &Database,
+    util: &Util,
+    identity: SsoIdentity,
+    role: UserType,
+    claims: &IdClaims,
+) -> Result<UserSummary, ApiError> {
+    let coll = db.collection::<UserSummary>("users");
+    let user = match get_sso_user(&identity, coll.clone()).await {
+        Ok(Some(user)) if !user.active => {
+            return Err(ApiError::Forbidden("Account is deactivated".to_string()));
+        }
+        Ok(Some(user)) => sync_sso_user(&user._id, role, claims, coll).await,
+        Ok(None) => {
+            let created = provision_sso_user(
+                identity,
+                role,
+                claims,
+                util.argon.clone(),
+                db.collection("users"),
+            )
+            .await;
+            if let Ok(user) = &created {
+                let detail = format!("Created on first single sign-on as {:?}", role);
+                if let Err(error) = record_audit(
+                    db,
+                    "sso_user_created",
+                    Some(&user.username),
+                    None,
+                    None,
+                    detail,
+                )
+                .await
+                {
+                    log::error!("Could not record audit event: {}", error);
+                }
+            }
+            created
         }
+        Err(error) => Err(error),
     };
 
-    let filter = doc! { "_id": &form.user_id };
-    let update = doc! { "$push": { "quiz": bson_quiz} };
+    user.map_err(ApiError::Internal)
+}
 
-    let resp = match coll.update_one(filter, update, None).await {
-        Ok(s) => s,
-        Err(error) => {
-            let response_json = &GenericResponse {
-                status: "fail".to_string(),
-                message: error.to_string(),
-            };
-            return HttpResponse::InternalServerError().json(response_json);
-        }
+const LTI_LOGIN_MINUTES: i64 = 10;
+const LTI_DEEP_LINK_MINUTES: i64 = 30;
+
+fn lti_disabled() -> ApiError {
+    ApiError::Unavailable("LTI is not configured on this server".to_string())
+}
+
+/// Answers the platform's login initiation by sending the browser back to
+/// it to authenticate, which then posts the launch to `/lti/launch`.
+async fn start_lti_login(
+    db: &Database,
+    util: &Util,
+    init: &LoginInitiation,
+) -> Result<HttpResponse, ApiError> {
+    let Some(lti) = &util.lti else {
+        return Err(lti_disabled());
     };
 
-    if resp.modified_count == 0 {
-        let response_json = &GenericResponse {
-            status: "fail".to_string(),
-            message: "Failed to update value".to_string(),
-        };
-        return HttpResponse::InternalServerError().json(response_json);
+    let (state, state_hash) = new_token();
+    let login = LtiLogin {
+        _id: state_hash,
+        nonce: random_secret(),
+        expires_at: bson::DateTime::from_chrono(
+            Utc::now() + chrono::Duration::minutes(LTI_LOGIN_MINUTES),
+        ),
+    };
+    let url = lti
+        .login_redirect(init, &state, &login.nonce)
+        .map_err(ApiError::BadRequest)?;
+
+    match db
+        .collection::<LtiLogin>("lti_logins")
+        .insert_one(&login, None)
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .finish()),
+        Err(error) => Err(ApiError::from(error)),
     }
+}
 
-    let response_json = &GenericResponse {
-        status: "success".to_string(),
-        message: cont,
-    };
+#[get("/lti/login")]
+async fn lti_login_get(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    query: web::Query<LoginInitiation>,
+) -> Result<HttpResponse, ApiError> {
+    start_lti_login(&db, &util, &query).await
+}
 
-    HttpResponse::Ok().json(response_json)
+#[post("/lti/login")]
+async fn lti_login_post(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<LoginInitiation>,
+) -> Result<HttpResponse, ApiError> {
+    start_lti_login(&db, &util, &form).await
 }
 
-pub fn config(conf: &mut web::ServiceConfig) {
-    let scope = web::scope("/api")
-        .service(health_checker_handler)
-        .service(generate_flashcard)
+/// The tool's public keys, for the platform to check what the tool signs.
+#[get("/lti/jwks")]
+async fn lti_jwks(util: Data<Util>) -> Result<HttpResponse, ApiError> {
+    match &util.lti {
+        Some(lti) => Ok(HttpResponse::Ok().json(lti.jwks())),
+        None => Err(lti_disabled()),
+    }
+}
+
+#[post("/lti/launch")]
+async fn lti_launch(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<LtiLaunch>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(lti) = &util.lti else {
+        return Err(lti_disabled());
+    };
+
+    let login = take_sign_in::<LtiLogin>(&form.state, db.collection("lti_logins"))
+        .await
+        .map_err(ApiError::BadRequest)?;
+    let claims = match lti.validate_launch(&form.id_token, &login.nonce).await {
+        Ok(s) => s,
+        Err(error) => {
+            return Err(ApiError::Unauthorized(format!(
+                "LTI launch failed: {}",
+                error
+            )));
+        }
+    };
+
+    let identity = SsoIdentity {
+        issuer: lti.issuer().to_string(),
+        subject: claims.user.sub.clone(),
+    };
+    let user = sso_user(&db, &util, identity, claims.role(), &claims.user).await?;
+    let token = session_token(&util, &user._id, user.user_type)?;
+
+    match claims.message_type.as_str() {
+        RESOURCE_LINK_REQUEST => lti_resource_link(&db, lti, &claims, user, token).await,
+        DEEP_LINKING_REQUEST => lti_deep_linking(&db, &claims, user, token).await,
+        other => Err(ApiError::BadRequest(format!(
+            "Unsupported LTI message {}",
+            other
+        ))),
+    }
+}
+
+/// Records the course placement being launched, then opens its quiz:
+/// students are assigned to it, and instructors of a placement without a
+/// quiz yet go on to create one.
+async fn lti_resource_link(
+    db: &Database,
+    lti: &Lti,
+    claims: &LaunchClaims,
+    user: UserSummary,
+    token: String,
+) -> Result<HttpResponse, ApiError> {
+    let Some(link) = &claims.resource_link else {
+        return Err(ApiError::BadRequest(
+            "Launch has no resource link".to_string(),
+        ));
+    };
+    let student = user.user_type == Some(UserType::Student);
+
+    let mut set = doc! {
+        "issuer": lti.issuer(),
+        "deployment_id": &claims.deployment_id,
+        "resource_link_id": &link.id,
+        "context_id": claims.context.as_ref().map(|c| c.id.clone()),
+    };
+    if let Some(quiz_id) = claims.quiz_id() {
+        set.insert("quiz_id", quiz_id);
+    }
+    if let Some(lineitem) = claims.score_lineitem() {
+        set.insert("lineitem", lineitem);
+    }
+    let mut update = doc! { "$set": set };
+    if student {
+        update.insert("$addToSet", doc! { "students": &user._id });
+    }
+    let filter = doc! {
+        "_id": format!("{}|{}|{}", lti.issuer(), claims.deployment_id, link.id),
+    };
+    let options = UpdateOptions::builder().upsert(true).build();
+    if let Err(error) = db
+        .collection::<LtiResourceLink>("lti_links")
+        .update_one(filter, update, options)
+        .await
+    {
+        return Err(ApiError::from(error));
+    }
+
+    let action = match (claims.quiz_id(), student) {
+        (Some(quiz_id), true) => {
+            let filter = doc! { "_id": quiz_id };
+            let update = doc! { "$addToSet": { "student_id": &user._id } };
+            match quiz_collection(db).update_one(filter, update, None).await {
+                Ok(s) if s.matched_count == 0 => {
+                    return Err(ApiError::NotFound("Quiz not found".to_string()));
+                }
+                Ok(_) => "take_quiz",
+                Err(error) => {
+                    return Err(ApiError::from(error));
+                }
+            }
+        }
+        (Some(_), false) => "manage_quiz",
+        (None, true) => {
+            return Err(ApiError::NotFound(
+                "No quiz has been added to this link yet".to_string(),
+            ));
+        }
+        (None, false) => "create_quiz",
+    };
+
+    let response_json = &LtiLaunchResponse {
+        status: "success".to_string(),
+        action: action.to_string(),
+        user,
+        token,
+        quiz_id: claims.quiz_id().map(str::to_string),
+        deep_link_id: None,
+        quizzes: None,
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+/// Lists the instructor's quizzes to pick one from. The choice goes to
+/// `/lti/deep_link` with the returned `deep_link_id`.
+async fn lti_deep_linking(
+    db: &Database,
+    claims: &LaunchClaims,
+    user: UserSummary,
+    token: String,
+) -> Result<HttpResponse, ApiError> {
+    if user.user_type == Some(UserType::Student) {
+        return Err(ApiError::Forbidden(
+            "Only instructors can add quizzes to a course".to_string(),
+        ));
+    }
+    let Some(settings) = &claims.deep_linking else {
+        return Err(ApiError::BadRequest(
+            "Launch has no deep linking settings".to_string(),
+        ));
+    };
+
+    let (deep_link_token, hash) = new_token();
+    let deep_link = LtiDeepLink {
+        _id: hash,
+        faculty_id: user._id.clone(),
+        deployment_id: claims.deployment_id.clone(),
+        return_url: settings.deep_link_return_url.clone(),
+        data: settings.data.clone(),
+        expires_at: bson::DateTime::from_chrono(
+            Utc::now() + chrono::Duration::minutes(LTI_DEEP_LINK_MINUTES),
+        ),
+    };
+    if let Err(error) = db
+        .collection::<LtiDeepLink>("lti_deep_links")
+        .insert_one(&deep_link, None)
+        .await
+    {
+        return Err(ApiError::from(error));
+    }
+
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let quizzes: Result<Vec<QuizTable>, _> = match quiz_collection(db)
+        .find(doc! { "faculty_id": &user._id }, options)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(error) => Err(error),
+    };
+    let quizzes = quizzes?;
+
+    let response_json = &LtiLaunchResponse {
+        status: "success".to_string(),
+        action: "pick_quiz".to_string(),
+        user,
+        token,
+        quiz_id: None,
+        deep_link_id: Some(deep_link_token),
+        quizzes: Some(
+            quizzes
+                .into_iter()
+                .map(|q| LtiQuizChoice {
+                    quiz_id: q._id,
+                    questions: q.draw_count.unwrap_or(q.questions.len()),
+                    from: q.from,
+                    to: q.to,
+                })
+                .collect(),
+        ),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+/// Sends the picked quiz back to the LMS as a link with a gradebook column.
+/// The page posts itself to the platform, so the browser should open it.
+#[post("/lti/deep_link")]
+async fn lti_deep_link(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<PickLtiQuiz>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(lti) = &util.lti else {
+        return Err(lti_disabled());
+    };
+
+    let coll = db.collection::<LtiDeepLink>("lti_deep_links");
+    let deep_link = match take_sign_in(&form.deep_link_id, coll).await {
+        Ok(s) if s.faculty_id == form.faculty_id => s,
+        Ok(_) => {
+            return Err(ApiError::Forbidden(
+                "The deep link was started by someone else".to_string(),
+            ));
+        }
+        Err(error) => {
+            return Err(ApiError::BadRequest(error));
+        }
+    };
+    let quiz = match get_quiz(&form.quiz_id, quiz_collection(&db)).await {
+        Ok(s) if s.faculty_id == form.faculty_id => s,
+        Ok(_) => {
+            return Err(ApiError::Forbidden(
+                "Only the quiz's author can add it to a course".to_string(),
+            ));
+        }
+        Err(error) => {
+            return Err(error);
+        }
+    };
+
+    let settings = DeepLinkingSettings {
+        deep_link_return_url: deep_link.return_url,
+        data: deep_link.data,
+    };
+    let title = form.title.as_deref().unwrap_or("Quiz");
+    match lti.deep_linking_response(
+        &settings,
+        &deep_link.deployment_id,
+        &quiz._id,
+        title,
+        max_score(&quiz),
+    ) {
+        Ok(page) => Ok(HttpResponse::Ok()
+            .content_type(header::ContentType::html())
+            .body(page)),
+        Err(error) => Err(ApiError::Internal(error)),
+    }
+}
+
+/// Posts each student's recorded grade to the gradebook column of every
+/// course placement they launched the quiz from. Only the quiz's author,
+/// signed in, can do this.
+#[post("/lti/push_grades")]
+async fn lti_push_grades(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    caller: Caller,
+    form: web::Form<PushGrades>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(lti) = &util.lti else {
+        return Err(lti_disabled());
+    };
+
+    let quiz = match get_quiz(&form.quiz_id, quiz_collection(&db)).await {
+        Ok(s) if s.faculty_id == caller.user_id() => s,
+        Ok(_) => {
+            return Err(ApiError::Forbidden(
+                "Only the quiz's author can push its grades".to_string(),
+            ));
+        }
+        Err(error) => {
+            return Err(error);
+        }
+    };
+
+    let filter = doc! {
+        "quiz_id": &quiz._id,
+        "issuer": lti.issuer(),
+        "lineitem": { "$type": "string" },
+    };
+    let links: Result<Vec<LtiResourceLink>, _> = match db
+        .collection::<LtiResourceLink>("lti_links")
+        .find(filter, None)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(error) => Err(error),
+    };
+    let links = match links {
+        Ok(s) if s.is_empty() => {
+            return Err(ApiError::Conflict(
+                "The quiz has no gradebook column in the LMS".to_string(),
+            ));
+        }
+        Ok(s) => s,
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    // The LMS knows students by their platform user id.
+    let students: Vec<&String> = links.iter().flat_map(|l| &l.students).collect();
+    let filter = doc! { "_id": { "$in": &students }, "sso.issuer": lti.issuer() };
+    let users: Result<Vec<UserSummary>, _> = match db
+        .collection::<UserSummary>("users")
+        .find(filter, None)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(error) => Err(error),
+    };
+    let subjects: HashMap<String, String> = match users {
+        Ok(s) => s
+            .into_iter()
+            .filter_map(|u| Some((u._id, u.sso?.subject)))
+            .collect(),
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    let token = lti.score_token().await.map_err(ApiError::Upstream)?;
+
+    let maximum = max_score(&quiz);
+    let mut pushed = 0;
+    let mut failed = Vec::new();
+    for link in &links {
+        let Some(lineitem) = &link.lineitem else {
+            continue;
+        };
+        for student_id in &link.students {
+            // Students who have not submitted have no grade to send.
+            let Some(grade) = quiz.student_marks.get(student_id) else {
+                continue;
+            };
+            let Some(subject) = subjects.get(student_id) else {
+                failed.push(GradePushFailure {
+                    student_id: student_id.clone(),
+                    message: "Student has no account in the LMS".to_string(),
+                });
+                continue;
+            };
+            let score = Score::final_grade(subject.clone(), *grade, maximum);
+            match lti.post_score(&token, lineitem, &score).await {
+                Ok(()) => pushed += 1,
+                Err(error) => failed.push(GradePushFailure {
+                    student_id: student_id.clone(),
+                    message: error,
+                }),
+            }
+        }
+    }
+
+    let response_json = &PushGradesResponse {
+        status: "success".to_string(),
+        pushed,
+        failed,
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+/// The faculty member or admin `username`, if `password` is theirs.
+async fn two_factor_account(
+    db: &Database,
+    util: &Util,
+    username: &str,
+    password: &str,
+    ip: &str,
+) -> Result<LoginRecord, ApiError> {
+    let user = password_account(db, util, username, password, ip).await?;
+    if !matches!(user.user_type, Some(UserType::Faculty | UserType::Admin)) {
+        return Err(ApiError::Forbidden(
+            "Two-factor authentication is only available to faculty and admins".to_string(),
+        ));
+    }
+    Ok(user)
+}
+
+/// Fresh recovery codes in plain text, and their hashes to store.
+fn new_recovery_codes(util: &Util) -> Result<(Vec<String>, Vec<String>), String> {
+    let codes = recovery_codes();
+    let hashes = codes
+        .iter()
+        .map(|c| hash_recovery_code(c, &util.argon))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((codes, hashes))
+}
+
+fn two_factor_not_enabled() -> ApiError {
+    ApiError::Conflict("Two-factor authentication is not enabled".to_string())
+}
+
+fn invalid_code() -> ApiError {
+    ApiError::Unauthorized("Invalid two-factor code".to_string())
+}
+
+/// Starts enrolling an authenticator app. Nothing changes at sign-in until
+/// `/two_factor/enable` confirms a code from it.
+#[post("/two_factor/setup")]
+async fn setup_two_factor(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<TwoFactorSetup>,
+) -> Result<HttpResponse, ApiError> {
+    let user =
+        two_factor_account(&db, &util, &form.username, &form.password, &client_ip(&req)).await?;
+    if user.two_factor.as_ref().is_some_and(|t| t.enabled) {
+        return Err(ApiError::Conflict(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let secret = new_secret();
+    let enrolment =
+        otpauth_uri(&secret, &user.username).and_then(|uri| qr_svg(&uri).map(|svg| (uri, svg)));
+    let (otpauth_uri, qr_svg) = enrolment.map_err(ApiError::Internal)?;
+
+    let two_factor = TwoFactor {
+        secret: secret.clone(),
+        enabled: false,
+        recovery_codes: vec![],
+        last_step: None,
+        enabled_at: None,
+    };
+    let two_factor =
+        bson::to_bson(&two_factor).map_err(|error| ApiError::Internal(error.to_string()))?;
+    let filter = doc! { "_id": &user._id, "two_factor.enabled": { "$ne": true } };
+    let update = doc! { "$set": { "two_factor": two_factor } };
+    match db
+        .collection::<LoginRecord>("users")
+        .update_one(filter, update, None)
+        .await
+    {
+        Ok(_) => {
+            let response_json = &TwoFactorSetupResponse {
+                status: "success".to_string(),
+                secret,
+                otpauth_uri,
+                qr_svg,
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+#[post("/two_factor/enable")]
+async fn enable_two_factor(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<TwoFactorConfirm>,
+) -> Result<HttpResponse, ApiError> {
+    let user =
+        two_factor_account(&db, &util, &form.username, &form.password, &client_ip(&req)).await?;
+    let Some(pending) = user.two_factor.as_ref().filter(|t| !t.enabled) else {
+        return Err(ApiError::Conflict(
+            "Start two-factor setup first".to_string(),
+        ));
+    };
+    let now = Utc::now().timestamp() as u64;
+    let Some(step) = code_step(&pending.secret, &form.code, now, None) else {
+        return Err(invalid_code());
+    };
+
+    let (codes, hashes) = new_recovery_codes(&util).map_err(ApiError::Internal)?;
+    // Matching on the secret makes sure the code was for the setup still stored.
+    let filter = doc! {
+        "_id": &user._id,
+        "two_factor.secret": &pending.secret,
+        "two_factor.enabled": false,
+    };
+    let update = doc! { "$set": {
+        "two_factor.enabled": true,
+        "two_factor.recovery_codes": hashes,
+        "two_factor.last_step": step as i64,
+        "two_factor.enabled_at": bson::DateTime::now(),
+    } };
+    match db
+        .collection::<LoginRecord>("users")
+        .update_one(filter, update, None)
+        .await
+    {
+        Ok(s) if s.modified_count == 0 => Err(ApiError::Conflict(
+            "Two-factor setup changed; start again".to_string(),
+        )),
+        Ok(_) => {
+            let detail = "Two-factor authentication enabled".to_string();
+            if let Err(error) = record_audit(
+                &db,
+                "two_factor_enabled",
+                Some(&user.username),
+                None,
+                None,
+                detail,
+            )
+            .await
+            {
+                log::error!("Could not record audit event: {}", error);
+            }
+            let response_json = &RecoveryCodesResponse {
+                status: "success".to_string(),
+                recovery_codes: codes,
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+#[post("/two_factor/disable")]
+async fn disable_two_factor(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<TwoFactorConfirm>,
+) -> Result<HttpResponse, ApiError> {
+    let user =
+        two_factor_account(&db, &util, &form.username, &form.password, &client_ip(&req)).await?;
+    if !user.two_factor.as_ref().is_some_and(|t| t.enabled) {
+        return Err(two_factor_not_enabled());
+    }
+
+    match two_factor_required(user.user_type, db.collection("settings")).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return Err(ApiError::Forbidden(
+                "Two-factor authentication is required for your role".to_string(),
+            ));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error));
+        }
+    }
+
+    let coll = db.collection::<LoginRecord>("users");
+    match redeem_second_factor(&user, &form.code, util.argon.clone(), coll.clone()).await {
+        Ok(true) => {}
+        Ok(false) => return Err(invalid_code()),
+        Err(error) => {
+            return Err(ApiError::Internal(error));
+        }
+    }
+
+    let update = doc! { "$unset": { "two_factor": "" } };
+    match coll
+        .update_one(doc! { "_id": &user._id }, update, None)
+        .await
+    {
+        Ok(_) => {
+            let detail = "Two-factor authentication disabled".to_string();
+            if let Err(error) = record_audit(
+                &db,
+                "two_factor_disabled",
+                Some(&user.username),
+                None,
+                None,
+                detail,
+            )
+            .await
+            {
+                log::error!("Could not record audit event: {}", error);
+            }
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Two-factor authentication disabled".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+/// Replaces all recovery codes, e.g. after some were used.
+#[post("/two_factor/recovery_codes")]
+async fn regenerate_recovery_codes(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<TwoFactorConfirm>,
+) -> Result<HttpResponse, ApiError> {
+    let user =
+        two_factor_account(&db, &util, &form.username, &form.password, &client_ip(&req)).await?;
+    if !user.two_factor.as_ref().is_some_and(|t| t.enabled) {
+        return Err(two_factor_not_enabled());
+    }
+
+    let coll = db.collection::<LoginRecord>("users");
+    match redeem_second_factor(&user, &form.code, util.argon.clone(), coll.clone()).await {
+        Ok(true) => {}
+        Ok(false) => return Err(invalid_code()),
+        Err(error) => {
+            return Err(ApiError::Internal(error));
+        }
+    }
+    let (codes, hashes) = new_recovery_codes(&util).map_err(ApiError::Internal)?;
+
+    let update = doc! { "$set": { "two_factor.recovery_codes": hashes } };
+    match coll
+        .update_one(doc! { "_id": &user._id }, update, None)
+        .await
+    {
+        Ok(_) => {
+            let response_json = &RecoveryCodesResponse {
+                status: "success".to_string(),
+                recovery_codes: codes,
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+const API_KEY_NAME_MAX_LEN: usize = 100;
+
+/// Creates a personal API key. The password, and a two-factor code when
+/// two-factor authentication is on, are asked for again since the key
+/// outlives any sign-in, and users who could not sign in get no key.
+#[post("/api_keys/create")]
+async fn create_api_key(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<CreateApiKey>,
+) -> Result<HttpResponse, ApiError> {
+    let name = form.name.trim();
+    let mut errors = Vec::new();
+    if name.is_empty() || name.chars().count() > API_KEY_NAME_MAX_LEN {
+        errors.push(FieldError {
+            field: "name".to_string(),
+            message: format!("Must be 1 to {} characters", API_KEY_NAME_MAX_LEN),
+        });
+    }
+    let scopes = match ApiScope::parse_list(&form.scopes) {
+        Ok(s) if s.is_empty() => {
+            errors.push(FieldError {
+                field: "scopes".to_string(),
+                message: "At least one scope is required".to_string(),
+            });
+            s
+        }
+        Ok(s) => s,
+        Err(error) => {
+            errors.push(FieldError {
+                field: "scopes".to_string(),
+                message: error,
+            });
+            Vec::new()
+        }
+    };
+    invalid_input(errors)?;
+
+    let ip = client_ip(&req);
+    let user = password_account(&db, &util, &form.username, &form.password, &ip).await?;
+    check_password_current(&user)?;
+    let code = form.code.as_deref().filter(|c| !c.trim().is_empty());
+    check_second_factor(&db, &util, &user, code, &ip).await?;
+
+    let (key, prefix, hash) = new_api_key();
+    let api_key = ApiKey {
+        _id: Uuid::new_v4().to_string(),
+        user_id: user._id.clone(),
+        name: name.to_string(),
+        prefix,
+        hash,
+        scopes,
+        created_at: bson::DateTime::now(),
+        last_used_at: None,
+        revoked_at: None,
+    };
+    if let Err(error) = db
+        .collection::<ApiKey>("api_keys")
+        .insert_one(&api_key, None)
+        .await
+    {
+        return Err(ApiError::from(error));
+    }
+
+    let detail = format!("API key {} ({}) created", api_key.prefix, api_key.name);
+    if let Err(error) = record_audit(
+        &db,
+        "api_key_created",
+        Some(&user.username),
+        None,
+        None,
+        detail,
+    )
+    .await
+    {
+        log::error!("Could not record audit event: {}", error);
+    }
+    let response_json = &ApiKeyCreatedResponse {
+        status: "success".to_string(),
+        key,
+        api_key: api_key.into(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[get("/api_keys/{user_id}")]
+async fn list_api_keys(
+    db: web::Data<Database>,
+    session: Session,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = path.into_inner();
+    own_account(&session, &user_id)?;
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let keys = match db
+        .collection::<ApiKey>("api_keys")
+        .find(doc! { "user_id": &user_id }, options)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect::<Vec<ApiKey>>().await,
+        Err(error) => Err(error),
+    };
+    match keys {
+        Ok(keys) => {
+            let response_json = &ApiKeysResponse {
+                status: "success".to_string(),
+                api_keys: keys.into_iter().map(ApiKeySummary::from).collect(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+#[post("/api_keys/revoke")]
+async fn revoke_api_key(
+    db: web::Data<Database>,
+    session: Session,
+    form: web::Form<RevokeApiKey>,
+) -> Result<HttpResponse, ApiError> {
+    own_account(&session, &form.user_id)?;
+    let filter = doc! { "_id": &form.key_id, "user_id": &form.user_id, "revoked_at": null };
+    let update = doc! { "$set": { "revoked_at": bson::DateTime::now() } };
+    let revoked = match db
+        .collection::<ApiKey>("api_keys")
+        .find_one_and_update(filter, update, None)
+        .await
+    {
+        Ok(Some(s)) => s,
+        Ok(None) => {
+            return Err(ApiError::NotFound("No such active API key".to_string()));
+        }
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    let username = get_user(&form.user_id, db.collection("users"))
+        .await
+        .map(|u| u.username)
+        .ok();
+    let detail = format!("API key {} ({}) revoked", revoked.prefix, revoked.name);
+    if let Err(error) = record_audit(
+        &db,
+        "api_key_revoked",
+        username.as_deref(),
+        None,
+        Some(&form.user_id),
+        detail,
+    )
+    .await
+    {
+        log::error!("Could not record audit event: {}", error);
+    }
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: "API key revoked".to_string(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+/// Requires, or stops requiring, two-factor authentication for a role.
+#[post("/admin/two_factor_policy")]
+async fn set_two_factor_policy(
+    db: web::Data<Database>,
+    session: Session,
+    form: web::Form<SetTwoFactorPolicy>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = check_admin(&db, &session).await?;
+    if form.role == UserType::Student {
+        return Err(ApiError::BadRequest(
+            "Two-factor authentication is only available to faculty and admins".to_string(),
+        ));
+    }
+
+    let role = bson::to_bson(&form.role).map_err(|error| ApiError::Internal(error.to_string()))?;
+    let update = match form.required {
+        true => doc! { "$addToSet": { "required_roles": role } },
+        false => doc! { "$pull": { "required_roles": role } },
+    };
+    let options = UpdateOptions::builder().upsert(true).build();
+    match db
+        .collection::<TwoFactorPolicy>("settings")
+        .update_one(doc! { "_id": TWO_FACTOR_POLICY_ID }, update, options)
+        .await
+    {
+        Ok(_) => {
+            let detail = format!(
+                "Two-factor authentication {} for {:?}",
+                if form.required {
+                    "required"
+                } else {
+                    "optional"
+                },
+                form.role
+            );
+            if let Err(error) = record_audit(
+                &db,
+                "two_factor_policy_changed",
+                None,
+                None,
+                Some(&admin_id),
+                detail,
+            )
+            .await
+            {
+                log::error!("Could not record audit event: {}", error);
+            }
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Two-factor policy updated".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+/// Removes a user's authenticator and recovery codes when they have lost
+/// both, so they can set it up again.
+#[post("/admin/reset_two_factor")]
+async fn reset_two_factor(
+    db: web::Data<Database>,
+    session: Session,
+    form: web::Form<AdminUserAction>,
+) -> Result<HttpResponse, ApiError> {
+    let admin_id = check_admin(&db, &session).await?;
+
+    let filter = doc! { "_id": &form.user_id, "two_factor": { "$exists": true } };
+    let update = doc! { "$unset": { "two_factor": "" } };
+    match db
+        .collection::<LoginRecord>("users")
+        .update_one(filter, update, None)
+        .await
+    {
+        Ok(s) if s.matched_count == 0 => Err(two_factor_not_enabled()),
+        Ok(_) => {
+            let detail = format!("Two-factor authentication reset for {}", form.user_id);
+            if let Err(error) =
+                record_audit(&db, "two_factor_reset", None, None, Some(&admin_id), detail).await
+            {
+                log::error!("Could not record audit event: {}", error);
+            }
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Two-factor authentication reset".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+/// Creates or updates students from a roster file, for the signed-in
+/// faculty member or admin. New students' temporary passwords are in the
+/// response.
+#[post("/import_roster")]
+async fn import_roster(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    caller: Caller,
+    MultipartForm(form): MultipartForm<ImportRoster>,
+) -> Result<HttpResponse, ApiError> {
+    let faculty_id = caller.user_id();
+    let roles = [UserType::Faculty, UserType::Admin];
+    match has_role(faculty_id, &roles, db.collection("users")).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(ApiError::Forbidden(
+                "Only faculty can import rosters".to_string(),
+            ));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error));
+        }
+    }
+
+    let parsed = match std::fs::read(form.file.file.path())
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| parse_roster(&bytes))
+    {
+        Ok(s) => s,
+        Err(error) => {
+            return Err(ApiError::Validation(vec![FieldError {
+                field: "file".to_string(),
+                message: error,
+            }]));
+        }
+    };
+
+    let coll = db.collection::<UserSummary>("users");
+    let usernames: Vec<&str> = parsed.rows.iter().map(|r| r.username.as_str()).collect();
+    let options = FindOptions::builder()
+        .collation(username_collation())
+        .build();
+    let existing: HashMap<String, UserSummary> = match coll
+        .find(doc! { "username": { "$in": usernames } }, options)
+        .await
+    {
+        Ok(cursor) => cursor
+            .try_collect::<Vec<_>>()
+            .await?
+            .into_iter()
+            .map(|u| (u.username.to_lowercase(), u))
+            .collect(),
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    let classes: Vec<Class> = get_user_classes(faculty_id, db.collection("classes"))
+        .await
+        .map_err(ApiError::Internal)?
+        .into_iter()
+        .filter(|c| c.faculty_id == faculty_id)
+        .collect();
+
+    let mut errors = parsed.errors;
+    let mut rows = Vec::new();
+    for row in parsed.rows {
+        let mut profile = doc! {};
+        if let Some(display_name) = &row.display_name {
+            profile.insert("display_name", display_name);
+        }
+        if let Some(email) = &row.email {
+            profile.insert("email", email);
+        }
+        if let Some(class) = &row.class {
+            profile.insert("class", class);
+        }
+        let class = row
+            .class
+            .as_deref()
+            .and_then(|label| classes.iter().find(|c| class_matches(c, label)));
+
+        let mut result = if let Some(user) = existing.get(&row.username.to_lowercase()) {
+            if user.user_type != Some(UserType::Student) {
+                errors.push(ItemError {
+                    item: row.row,
+                    message: "Username belongs to an account that is not a student".to_string(),
+                });
+                continue;
+            }
+            if !profile.is_empty() {
+                let filter = doc! { "_id": &user._id };
+                if let Err(error) = coll
+                    .update_one(filter, doc! { "$set": profile }, None)
+                    .await
+                {
+                    errors.push(ItemError {
+                        item: row.row,
+                        message: error.to_string(),
+                    });
+                    continue;
+                }
+            }
+            RosterRowResult {
+                row: row.row,
+                student_id: user._id.clone(),
+                username: user.username.clone(),
+                created: false,
+                temporary_password: None,
+                class_id: None,
+            }
+        } else {
+            let temporary = temporary_password();
+            let pwd = match hasher(temporary.clone(), util.argon.clone()) {
+                Ok(s) => s,
+                Err(error) => {
+                    errors.push(ItemError {
+                        item: row.row,
+                        message: error,
+                    });
+                    continue;
+                }
+            };
+            let student = Student {
+                _id: Uuid::new_v4().to_string(),
+                username: row.username.clone(),
+                password: pwd,
+                user_type: UserType::Student,
+                active: true,
+                must_reset_password: true,
+                display_name: row.display_name,
+                email: row.email,
+                email_verified: false,
+                class: row.class,
+                quiz: Some(Vec::new()),
+                flashes: Some(Vec::new()),
+            };
+            let on_insert = match to_document(&student) {
+                Ok(s) => s,
+                Err(error) => {
+                    errors.push(ItemError {
+                        item: row.row,
+                        message: error.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            // An upsert keeps re-running the same roster from creating the
+            // student twice, even when two uploads race.
+            let filter = doc! { "username": &row.username };
+            let options = UpdateOptions::builder()
+                .upsert(true)
+                .collation(username_collation())
+                .build();
+            match db
+                .collection::<Document>("users")
+                .update_one(filter, doc! { "$setOnInsert": on_insert }, options)
+                .await
+            {
+                Ok(s) if s.upserted_id.is_some() => RosterRowResult {
+                    row: row.row,
+                    student_id: student._id,
+                    username: row.username,
+                    created: true,
+                    temporary_password: Some(temporary),
+                    class_id: None,
+                },
+                Ok(_) => {
+                    errors.push(ItemError {
+                        item: row.row,
+                        message: "Username was registered during the import".to_string(),
+                    });
+                    continue;
+                }
+                Err(error) => {
+                    errors.push(ItemError {
+                        item: row.row,
+                        message: error.to_string(),
+                    });
+                    continue;
+                }
+            }
+        };
+
+        if let Some(class) = class {
+            match enroll_in_class(&db, &class._id, &result.student_id).await {
+                Ok(()) => result.class_id = Some(class._id.clone()),
+                Err(error) => errors.push(ItemError {
+                    item: row.row,
+                    message: format!("Could not enroll in class: {}", error),
+                }),
+            }
+        }
+        rows.push(result);
+    }
+
+    errors.sort_by_key(|e| e.item);
+    Ok(HttpResponse::Ok().json(&ImportRosterResponse {
+        status: "success".to_string(),
+        created: rows.iter().filter(|r| r.created).count(),
+        updated: rows.iter().filter(|r| !r.created).count(),
+        rows,
+        errors,
+    }))
+}
+
+#[post("/create_class")]
+async fn create_class(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<CreateClass>,
+) -> Result<HttpResponse, ApiError> {
+    let roles = [UserType::Faculty, UserType::Admin];
+    match has_role(caller.user_id(), &roles, db.collection("users")).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(ApiError::Forbidden(
+                "Only faculty can create classes".to_string(),
+            ));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error));
+        }
+    }
+
+    let class = Class {
+        _id: Uuid::new_v4().to_string(),
+        faculty_id: caller.user_id().to_string(),
+        course: form.course.clone(),
+        section: form.section.clone(),
+        term: form.term.clone(),
+        students: Vec::new(),
+        quizzes: Vec::new(),
+        decks: Vec::new(),
+        created_at: Utc::now(),
+    };
+
+    match db
+        .collection::<Class>("classes")
+        .insert_one(&class, None)
+        .await
+    {
+        Ok(_) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: class._id,
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+/// The caller's classes. Only the classes they teach list the students.
+#[get("/classes/{user_id}")]
+async fn list_classes(
+    db: web::Data<Database>,
+    caller: Caller,
+    user_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&user_id)?;
+    match get_user_classes(&user_id, db.collection("classes")).await {
+        Ok(classes) => Ok(HttpResponse::Ok().json(&ClassesResponse {
+            status: "success".to_string(),
+            classes: classes
+                .into_iter()
+                .map(|class| roster_for(class, &user_id))
+                .collect(),
+        })),
+        Err(error) => Err(ApiError::Internal(error)),
+    }
+}
+
+/// `class` as `user_id` may see it: without the other students unless they
+/// teach it.
+fn roster_for(mut class: Class, user_id: &str) -> Class {
+    if class.faculty_id != user_id {
+        class.students.retain(|s| s == user_id);
+    }
+    class
+}
+
+/// The class, if the caller teaches it, or the response refusing them.
+async fn owned_class(db: &Database, class_id: &str, caller: &Caller) -> Result<Class, ApiError> {
+    match get_class(class_id, db.collection("classes")).await {
+        Ok(class) if class.faculty_id == caller.user_id() => Ok(class),
+        Ok(_) => Err(ApiError::Forbidden(
+            "Only the class's faculty can manage it".to_string(),
+        )),
+        Err(error) => Err(error),
+    }
+}
+
+#[post("/enroll")]
+async fn enroll(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<Enrollment>,
+) -> Result<HttpResponse, ApiError> {
+    let class = owned_class(&db, &form.class_id, &caller).await?;
+
+    match has_role(
+        &form.student_id,
+        &[UserType::Student],
+        db.collection("users"),
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(ApiError::NotFound("Student not found".to_string()));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error));
+        }
+    }
+
+    match enroll_in_class(&db, &class._id, &form.student_id).await {
+        Ok(()) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Student enrolled".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::Internal(error)),
+    }
+}
+
+/// Removes a student from a class and the class's decks. Quizzes already
+/// assigned stay assigned so their marks and printed sheets stay valid.
+#[post("/unenroll")]
+async fn unenroll(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<Enrollment>,
+) -> Result<HttpResponse, ApiError> {
+    let class = owned_class(&db, &form.class_id, &caller).await?;
+
+    let filter = doc! { "_id": &class._id };
+    let update = doc! { "$pull": { "students": &form.student_id } };
+    if let Err(error) = db
+        .collection::<Class>("classes")
+        .update_one(filter, update, None)
+        .await
+    {
+        return Err(ApiError::from(error));
+    }
+
+    // Only the access this class gave goes; decks shared with the student
+    // directly or through another class stay shared.
+    let decks = db.collection::<Deck>("decks");
+    let key = format!("class_shares.{}", form.student_id);
+    let filter = doc! { &key: &class._id };
+    let update = doc! { "$pull": { &key: &class._id } };
+    decks.update_many(filter, update, None).await?;
+    let filter = doc! { &key: { "$size": 0 } };
+    let update = doc! { "$unset": { &key: "" } };
+    decks.update_many(filter, update, None).await?;
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: "Student unenrolled".to_string(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/assign_to_class")]
+async fn assign_to_class(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<AssignToClass>,
+) -> Result<HttpResponse, ApiError> {
+    let class = owned_class(&db, &form.class_id, &caller).await?;
+    if form.quiz_id.is_none() && form.deck_id.is_none() {
+        return Err(ApiError::BadRequest(
+            "Give a quiz_id, a deck_id or both".to_string(),
+        ));
+    }
+
+    if let Some(quiz_id) = &form.quiz_id {
+        match get_quiz(quiz_id, quiz_collection(&db)).await {
+            Ok(quiz) if quiz.faculty_id == caller.user_id() => {}
+            Ok(_) => {
+                return Err(ApiError::Forbidden(
+                    "Only the quiz's faculty can assign it".to_string(),
+                ));
+            }
+            Err(error) => {
+                return Err(error);
+            }
+        }
+    }
+    if let Some(deck_id) = &form.deck_id {
+        match get_deck(deck_id, db.collection::<Deck>("decks")).await {
+            Ok(deck) if deck.owner_id == caller.user_id() => {}
+            Ok(_) => {
+                return Err(ApiError::Forbidden(
+                    "Only the deck's owner can assign it".to_string(),
+                ));
+            }
+            Err(error) => {
+                return Err(error);
+            }
+        }
+    }
+
+    let quizzes: Vec<String> = form.quiz_id.iter().cloned().collect();
+    let decks: Vec<String> = form.deck_id.iter().cloned().collect();
+    let filter = doc! { "_id": &class._id };
+    let update = doc! { "$addToSet": {
+        "quizzes": { "$each": &quizzes },
+        "decks": { "$each": &decks },
+    } };
+    // Share with the students as of this update, so a student enrolling at
+    // the same time is covered either here or by their enrollment.
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    let class = db
+        .collection::<Class>("classes")
+        .find_one_and_update(filter, update, options)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Class not found".to_string()))?;
+
+    match share_with_students(&db, &class._id, &quizzes, &decks, &class.students).await {
+        Ok(()) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: format!("Assigned to {} students", class.students.len()),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::Internal(error)),
+    }
+}
+
+const RESET_TOKEN_MINUTES: i64 = 60;
+const VERIFICATION_TOKEN_HOURS: i64 = 24;
+
+fn email_disabled() -> ApiError {
+    ApiError::Unavailable("Email is not configured on this server".to_string())
+}
+
+async fn send_verification(
+    db: &Database,
+    util: &Util,
+    user_id: &str,
+    username: &str,
+    email: &str,
+) -> Result<(), String> {
+    let Some(mailer) = &util.mailer else {
+        return Ok(());
+    };
+    let token = issue_token(
+        db,
+        user_id,
+        TokenPurpose::EmailVerification,
+        Some(email.to_string()),
+        chrono::Duration::hours(VERIFICATION_TOKEN_HOURS),
+    )
+    .await?;
+    let link = mailer.link("verify-email", &token);
+    mailer
+        .send(
+            email,
+            verification_email(username, &link, VERIFICATION_TOKEN_HOURS),
+        )
+        .await
+}
+
+#[post("/request_email_verification")]
+async fn request_email_verification(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<RequestVerification>,
+) -> Result<HttpResponse, ApiError> {
+    if util.mailer.is_none() {
+        return Err(email_disabled());
+    }
+
+    let user = get_user(&form.user_id, db.collection("users")).await?;
+    let Some(email) = user.email.filter(|_| !user.email_verified) else {
+        return Err(ApiError::BadRequest(
+            "User has no unverified email address".to_string(),
+        ));
+    };
+
+    match send_verification(&db, &util, &user._id, &user.username, &email).await {
+        Ok(()) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Verification email sent".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::Upstream(error)),
+    }
+}
+
+#[post("/verify_email")]
+async fn verify_email(
+    db: web::Data<Database>,
+    form: web::Form<VerifyEmail>,
+) -> Result<HttpResponse, ApiError> {
+    let token = redeem_token(&db, &form.token, TokenPurpose::EmailVerification).await?;
+
+    // The address must still be the one the link was sent to.
+    let filter = doc! { "_id": &token.user_id, "email": &token.email };
+    let update = doc! { "$set": { "email_verified": true } };
+    match db
+        .collection::<UserSummary>("users")
+        .update_one(filter, update, None)
+        .await
+    {
+        Ok(s) if s.matched_count == 0 => Err(ApiError::Conflict(
+            "Email address has changed since the link was sent".to_string(),
+        )),
+        Ok(_) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Email verified".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+/// Mails a reset link to `username` if it has a verified email address.
+/// Runs apart from the request, so the time taken to answer does not tell
+/// whether it did.
+async fn send_password_reset(db: Database, mailer: Mailer, username: String) {
+    let coll = db.collection::<UserSummary>("users");
+    let filter = doc! { "username": &username, "active": { "$ne": false } };
+    let user = match coll.find_one(filter, by_username()).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return,
+        Err(error) => {
+            log::error!("Could not look up user for password reset: {}", error);
+            return;
+        }
+    };
+    let Some(email) = user.email.as_ref().filter(|_| user.email_verified) else {
+        return;
+    };
+
+    let sent = match issue_token(
+        &db,
+        &user._id,
+        TokenPurpose::PasswordReset,
+        None,
+        chrono::Duration::minutes(RESET_TOKEN_MINUTES),
+    )
+    .await
+    {
+        Ok(token) => {
+            let link = mailer.link("reset-password", &token);
+            let message = password_reset_email(&user.username, &link, RESET_TOKEN_MINUTES);
+            mailer.send(email, message).await
+        }
+        Err(error) => Err(error),
+    };
+    if let Err(error) = sent {
+        log::error!("Could not send password reset email: {}", error);
+    }
+}
+
+/// Mails a reset link if the account has a verified email address. The
+/// answer is the same either way so it cannot be used to find out who has
+/// an account. Requests are throttled per username and client address like
+/// failed sign-ins, but counted apart from them.
+#[post("/forgot_password")]
+async fn forgot_password(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<ForgotPassword>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(mailer) = &util.mailer else {
+        return Err(email_disabled());
+    };
+
+    let ip = client_ip(&req);
+    let keys = [
+        reset_key(&user_key(&form.username)),
+        reset_key(&ip_key(&ip)),
+    ];
+    let coll = db.collection::<LoginFailures>("login_failures");
+    let records = get_login_failures(&keys, coll.clone())
+        .await
+        .map_err(ApiError::Internal)?;
+    if let Some(wait) = records.iter().filter_map(|r| r.wait(Utc::now())).max() {
+        return Err(too_many_attempts(wait));
+    }
+    let thresholds = [USER_LOCKOUT_THRESHOLD, IP_LOCKOUT_THRESHOLD];
+    for (key, threshold) in keys.iter().zip(thresholds) {
+        if let Err(error) = count_login_failure(key, threshold, coll.clone()).await {
+            log::error!("Could not record password reset request: {}", error);
+        }
+    }
+
+    actix_web::rt::spawn(send_password_reset(
+        db.get_ref().clone(),
+        mailer.clone(),
+        form.username.clone(),
+    ));
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: "If the account has a verified email address, a reset link has been sent"
+            .to_string(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/reset_password")]
+async fn reset_password(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    form: web::Form<ResetPassword>,
+) -> Result<HttpResponse, ApiError> {
+    let token = find_token(&db, &form.token, TokenPurpose::PasswordReset).await?;
+    // A link whose user has since been deleted is as good as expired.
+    let user = match get_user(&token.user_id, db.collection("users")).await {
+        Ok(s) => s,
+        Err(ApiError::NotFound(_)) => {
+            return Err(ApiError::Gone("Link is invalid or has expired".to_string()));
+        }
+        Err(error) => return Err(error),
+    };
+
+    // Checked before the token is used up, so a weak password can be retried.
+    invalid_input(password_errors(&form.new_password, &user.username))?;
+    redeem_token(&db, &form.token, TokenPurpose::PasswordReset).await?;
+
+    let pwd = hasher(form.new_password.clone(), util.argon.clone()).map_err(ApiError::Internal)?;
+
+    let update = doc! { "$set": { "password": pwd, "must_reset_password": false } };
+    if let Err(error) = db
+        .collection::<UserSummary>("users")
+        .update_one(doc! { "_id": &user._id }, update, None)
+        .await
+    {
+        return Err(ApiError::from(error));
+    }
+
+    // Any other reset links sent to the user stop working too.
+    let filter = doc! { "user_id": &user._id, "purpose": "password_reset" };
+    if let Err(error) = db
+        .collection::<AccountToken>("account_tokens")
+        .delete_many(filter, None)
+        .await
+    {
+        log::error!("Could not revoke reset links: {}", error);
+    }
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: "Password changed".to_string(),
+    };
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/create_flash")]
+async fn create_flash(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    caller: Caller,
+    form: web::Form<CreateFlash>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.user_id)?;
+    let Some(gemini) = &util.gemini else {
+        return Err(gemini_disabled());
+    };
+    // Check the deck before spending a generation on it.
+    if let Some(deck_id) = &form.deck_id {
+        match get_deck(deck_id, db.collection::<Deck>("decks")).await {
+            Ok(deck) if deck.owner_id == form.user_id => {}
+            Ok(_) => {
+                return Err(ApiError::Forbidden(
+                    "Only the deck's owner can add cards to it".to_string(),
+                ));
+            }
+            Err(error) => {
+                return Err(error);
+            }
+        }
+    }
+
+    let coll = db.collection::<Document>("users");
+    let generated = match form.mode {
+        FlashMode::KeyPoints => make_flashcards(gemini, form.topic.clone(), form.count).await,
+        FlashMode::Cloze => make_cloze_flashcards(gemini, form.topic.clone(), form.count).await,
+    };
+    let cont = generated.map_err(ApiError::Upstream)?;
+
+    let flashes = match form.mode {
+        FlashMode::KeyPoints => vec![Flashcard {
+            _id: Uuid::new_v4().to_string(),
+            topic: form.topic.clone(),
+            content: cont.clone(),
+            tags: Vec::new(),
+            cloze: None,
+        }],
+        FlashMode::Cloze => {
+            let sentences = cloze::parse_sentences(&cont).map_err(ApiError::Upstream)?;
+            sentences
+                .iter()
+                .flat_map(|sentence| {
+                    cloze::numbers(sentence)
+                        .into_iter()
+                        .map(|number| Flashcard {
+                            _id: Uuid::new_v4().to_string(),
+                            topic: form.topic.clone(),
+                            content: sentence.clone(),
+                            tags: Vec::new(),
+                            cloze: Some(number),
+                        })
+                })
+                .collect()
+        }
+    };
+
+    let mut bson_flashes = Vec::new();
+    for flash in &flashes {
+        match to_document(flash) {
+            Ok(s) => bson_flashes.push(s),
+            Err(error) => {
+                return Err(ApiError::Internal(error.to_string()));
+            }
+        }
+    }
+
+    let resp = match &form.deck_id {
+        Some(deck_id) => {
+            let filter = doc! { "_id": deck_id, "owner_id": &form.user_id };
+            let update = doc! { "$push": { "cards": { "$each": bson_flashes } } };
+            db.collection::<Deck>("decks")
+                .update_one(filter, update, None)
+                .await
+        }
+        None => {
+            let filter = doc! { "_id": &form.user_id };
+            let update = doc! { "$push": { "flashes": { "$each": bson_flashes } } };
+            coll.update_one(filter, update, None).await
+        }
+    };
+
+    let resp = resp?;
+
+    if resp.matched_count == 0 {
+        return Err(ApiError::NotFound(match form.deck_id {
+            Some(_) => "Deck not found".to_string(),
+            None => "User not found".to_string(),
+        }));
+    }
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: cont,
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/create_quiz")]
+async fn create_quiz(
+    db: web::Data<Database>,
+    util: Data<Util>,
+    caller: Caller,
+    form: web::Form<CreateQuiz>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.user_id)?;
+    let Some(gemini) = &util.gemini else {
+        return Err(gemini_disabled());
+    };
+    let coll = db.collection::<Document>("users");
+    let cont = make_quiz(gemini, form.topic.clone(), form.count)
+        .await
+        .map_err(ApiError::Upstream)?;
+
+    let quiz = Quiz {
+        _id: Uuid::new_v4().to_string(),
+        quizzes: cont.clone(),
+    };
+
+    let bson_quiz = to_document(&quiz).map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let filter = doc! { "_id": &form.user_id };
+    let update = doc! { "$push": { "quiz": bson_quiz} };
+
+    let resp = coll.update_one(filter, update, None).await?;
+
+    if resp.modified_count == 0 {
+        return Err(ApiError::Internal("Failed to update value".to_string()));
+    }
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: cont,
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/publish_quiz")]
+async fn publish_quiz(
+    db: web::Data<Database>,
+    caller: Caller,
+    body: web::Json<PublishQuiz>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&body.faculty_id)?;
+    let coll = quiz_collection(&db);
+
+    let pool = body.questions.len();
+    let valid_answers = body.answers.len() == pool
+        && body
+            .questions
+            .iter()
+            .zip(&body.answers)
+            .all(|(question, answer)| {
+                (3..=MAX_QUESTION_OPTIONS + 1).contains(&question.len())
+                    && *answer >= 0
+                    && (*answer as usize) < question.len() - 1
+            });
+    if pool == 0 || !valid_answers {
+        return Err(ApiError::BadRequest(format!(
+            "Every question needs 2 to {} options and a valid answer",
+            MAX_QUESTION_OPTIONS
+        )));
+    }
+
+    let valid_multi_select = body.multi_select.iter().all(|(q, correct)| {
+        match q.parse::<usize>().ok().and_then(|q| body.questions.get(q)) {
+            Some(question) => {
+                !correct.is_empty()
+                    && correct
+                        .iter()
+                        .all(|o| *o >= 0 && (*o as usize) < question.len() - 1)
+            }
+            None => false,
+        }
+    });
+    if !valid_multi_select {
+        return Err(ApiError::BadRequest(
+            "multi_select must map question indexes to their correct options".to_string(),
+        ));
+    }
+
+    let scoring = &body.scoring;
+    if scoring.weights.len() > pool
+        || scoring.weights.iter().any(|w| *w < 0.0)
+        || scoring.negative_marking < 0.0
+    {
+        return Err(ApiError::BadRequest(
+            "weights must be one per question and, like negative_marking, not negative".to_string(),
+        ));
+    }
+
+    if body.feedback.len() > pool {
+        return Err(ApiError::BadRequest(
+            "feedback must be one per question".to_string(),
+        ));
+    }
+
+    if matches!(body.draw_count, Some(n) if n == 0 || n > pool) {
+        return Err(ApiError::BadRequest(format!(
+            "draw_count must be between 1 and {}",
+            pool
+        )));
+    }
+
+    if matches!(body.max_attempts, Some(0)) || matches!(body.cooldown_minutes, Some(n) if n < 0) {
+        return Err(ApiError::BadRequest(
+            "max_attempts must be positive and cooldown_minutes not negative".to_string(),
+        ));
+    }
+
+    if matches!(body.duration_minutes, Some(n) if n <= 0) {
+        return Err(ApiError::BadRequest(
+            "duration_minutes must be positive".to_string(),
+        ));
+    }
+
+    let body = body.into_inner();
+    let quiz = QuizTable {
+        _id: Uuid::new_v4().to_string(),
+        faculty_id: body.faculty_id,
+        questions: body.questions,
+        answers: body.answers,
+        student_id: body.student_id,
+        student_marks: HashMap::new(),
+        from: body.from,
+        to: body.to,
+        created_at: Utc::now(),
+        shuffle_questions: body.shuffle_questions,
+        shuffle_options: body.shuffle_options,
+        draw_count: body.draw_count,
+        duration_minutes: body.duration_minutes,
+        extra_time: HashMap::new(),
+        max_attempts: body.max_attempts,
+        cooldown_minutes: body.cooldown_minutes,
+        grading: body.grading,
+        multi_select: body.multi_select,
+        scoring: body.scoring,
+        feedback: body.feedback,
+    };
+
+    let resp = coll.insert_one(&quiz, None).await?;
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: resp.inserted_id.to_string(),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[get("/export_quiz/{quiz_id}/{faculty_id}")]
+async fn export_quiz_handler(
+    db: web::Data<Database>,
+    caller: Caller,
+    path: web::Path<(String, String)>,
+    query: web::Query<ExportQuiz>,
+) -> Result<HttpResponse, ApiError> {
+    let (quiz_id, faculty_id) = path.into_inner();
+    caller.check(&faculty_id)?;
+
+    let format = match QuizFormat::parse(query.format.as_deref().unwrap_or("moodle")) {
+        Some(s) => s,
+        None => {
+            return Err(ApiError::BadRequest(
+                "format must be moodle, gift, qti or csv".to_string(),
+            ));
+        }
+    };
+
+    let quiz = get_quiz(&quiz_id, quiz_collection(&db)).await?;
+
+    // The export carries the answer key.
+    if quiz.faculty_id != faculty_id {
+        return Err(ApiError::Forbidden(
+            "Only the quiz's faculty can export it".to_string(),
+        ));
+    }
+
+    let bytes = export_quiz(&quiz, format).map_err(ApiError::Internal)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition::attachment(format!(
+            "quiz-{}.{}",
+            quiz._id,
+            format.extension()
+        )))
+        .body(bytes))
+}
+
+/// The exam copies, or with `key` the answer key, of a quiz as a PDF.
+async fn exam_pdf_response(
+    db: web::Data<Database>,
+    path: web::Path<(String, String)>,
+    query: web::Query<ExamPdf>,
+    key: bool,
+) -> Result<HttpResponse, ApiError> {
+    let (quiz_id, faculty_id) = path.into_inner();
+
+    let quiz = get_quiz(&quiz_id, quiz_collection(&db)).await?;
+
+    if quiz.faculty_id != faculty_id {
+        return Err(ApiError::Forbidden(
+            "Only the quiz's faculty can print it".to_string(),
+        ));
+    }
+
+    let query = query.into_inner();
+    let copies = paper_copies(
+        &quiz,
+        query.variants.unwrap_or(PAPER_VARIANTS.len()),
+        query.per_student.unwrap_or(true),
+    );
+    let bubble_sheet = query.bubble_sheet.unwrap_or(false);
+    if bubble_sheet && !key {
+        if let Some(error) = bubble_sheet_error(&quiz, &copies) {
+            return Err(ApiError::BadRequest(error));
+        }
+    }
+
+    let header = ExamHeader {
+        course: query.course.unwrap_or_else(|| format!("Quiz {}", quiz._id)),
+        date: query
+            .date
+            .unwrap_or_else(|| quiz.from.format("%Y-%m-%d").to_string()),
+    };
+    let file_name = format!(
+        "quiz-{}{}.pdf",
+        quiz._id,
+        if key { "-answer-key" } else { "" }
+    );
+
+    let rendered = web::block(move || {
+        if key {
+            render_answer_key(&quiz, &copies, &header)
+        } else {
+            render_exam(&quiz, &copies, &header, bubble_sheet)
+        }
+    })
+    .await;
+    let bytes = match rendered {
+        Ok(Ok(s)) => s,
+        Ok(Err(error)) => {
+            return Err(ApiError::Internal(error));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error.to_string()));
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .insert_header(ContentDisposition::attachment(file_name))
+        .body(bytes))
+}
+
+#[get("/exam_pdf/{quiz_id}/{faculty_id}")]
+async fn exam_pdf(
+    db: web::Data<Database>,
+    caller: Caller,
+    path: web::Path<(String, String)>,
+    query: web::Query<ExamPdf>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&path.1)?;
+    exam_pdf_response(db, path, query, false).await
+}
+
+#[get("/exam_key_pdf/{quiz_id}/{faculty_id}")]
+async fn exam_key_pdf(
+    db: web::Data<Database>,
+    caller: Caller,
+    path: web::Path<(String, String)>,
+    query: web::Query<ExamPdf>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&path.1)?;
+    exam_pdf_response(db, path, query, true).await
+}
+
+#[post("/import_questions")]
+async fn import_questions_handler(
+    db: web::Data<Database>,
+    caller: Caller,
+    MultipartForm(form): MultipartForm<ImportQuestions>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.faculty_id)?;
+    let file_name = form.file.file_name.clone().unwrap_or_default();
+    let extension = std::path::Path::new(&file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    let format = match QuizFormat::parse(form.format.as_deref().map_or(extension, |f| f.as_str())) {
+        Some(s) => s,
+        None => {
+            return Err(ApiError::BadRequest(
+                "format must be moodle, gift, qti or csv".to_string(),
+            ));
+        }
+    };
+
+    let faculty_id = form.faculty_id.into_inner();
+    let path = form.file.file.path().to_owned();
+    let parsed = web::block(move || {
+        std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| import_questions(&bytes, format))
+    })
+    .await;
+    let parsed = match parsed {
+        Ok(Ok(s)) => s,
+        Ok(Err(error)) => {
+            return Err(ApiError::Validation(vec![FieldError {
+                field: "file".to_string(),
+                message: error,
+            }]));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error.to_string()));
+        }
+    };
+
+    let mut known: HashSet<String> = match get_owned_questions(&faculty_id, &db).await {
+        Ok(s) => s.iter().map(|q| question_key(q)).collect(),
+        Err(error) => {
+            return Err(ApiError::Internal(error));
+        }
+    };
+
+    let mut duplicates = Vec::new();
+    let mut questions = Vec::new();
+    for imported in parsed.questions {
+        if !known.insert(question_key(&imported.question)) {
+            duplicates.push(imported.item);
+            continue;
+        }
+        questions.push(BankQuestion {
+            _id: Uuid::new_v4().to_string(),
+            faculty_id: faculty_id.clone(),
+            question: imported.question,
+            answers: imported.answers,
+            feedback: imported.feedback,
+            created_at: Utc::now(),
+        });
+    }
+
+    if !questions.is_empty() {
+        if let Err(error) = db
+            .collection::<BankQuestion>("question_bank")
+            .insert_many(&questions, None)
+            .await
+        {
+            return Err(ApiError::from(error));
+        }
+    }
+
+    let response_json = &ImportQuestionsResponse {
+        status: "success".to_string(),
+        imported: questions.len(),
+        duplicates,
+        errors: parsed.errors,
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[get("/questions/{faculty_id}")]
+async fn question_bank(
+    db: web::Data<Database>,
+    caller: Caller,
+    faculty_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&faculty_id)?;
+    let questions = get_bank_questions(&faculty_id, db.collection("question_bank"))
+        .await
+        .map_err(ApiError::Internal)?;
+
+    let response_json = &QuestionBankResponse {
+        status: "success".to_string(),
+        questions,
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/scan_sheets")]
+async fn scan_sheets(
+    db: web::Data<Database>,
+    caller: Caller,
+    MultipartForm(form): MultipartForm<ScanSheets>,
+) -> Result<HttpResponse, ApiError> {
+    let quiz_id = form.quiz_id.into_inner();
+    let quiz = get_quiz(&quiz_id, quiz_collection(&db)).await?;
+    caller.check(&quiz.faculty_id)?;
+
+    let paths: Vec<_> = form
+        .files
+        .iter()
+        .map(|f| f.file.path().to_owned())
+        .collect();
+    let scanned = web::block(move || {
+        let mut pages = Vec::new();
+        for path in paths {
+            match std::fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| load_pages(&bytes))
+            {
+                Ok(images) => pages.extend(images.iter().map(read_sheet)),
+                Err(error) => pages.push(Err(error)),
+            }
+        }
+        pages
+    })
+    .await;
+    let scanned = scanned.map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let fallback_student = form.student_id.map(|s| s.into_inner());
+    let mut errors = Vec::new();
+    // Sheets by student and variant, with the page each was on.
+    type Copy = ((String, usize), Vec<(usize, ScannedSheet)>);
+    let mut copies: Vec<Copy> = Vec::new();
+    for (i, sheet) in scanned.into_iter().enumerate() {
+        let page = i + 1;
+        let sheet = match sheet {
+            Ok(s) => s,
+            Err(message) => {
+                errors.push(ItemError {
+                    item: page,
+                    message,
+                });
+                continue;
+            }
+        };
+
+        let student_id = match sheet.code.student {
+            0 => match &fallback_student {
+                Some(s) => s.clone(),
+                None => {
+                    errors.push(ItemError {
+                        item: page,
+                        message: "student_id is required for sheets printed without a student"
+                            .to_string(),
+                    });
+                    continue;
+                }
+            },
+            n => match quiz.student_id.get(n - 1) {
+                Some(s) => s.clone(),
+                None => {
+                    errors.push(ItemError {
+                        item: page,
+                        message: format!("Sheet is for unknown student number {}", n),
+                    });
+                    continue;
+                }
+            },
+        };
+        if !quiz.student_id.is_empty() && !quiz.student_id.contains(&student_id) {
+            errors.push(ItemError {
+                item: page,
+                message: "Quiz is not assigned to this student".to_string(),
+            });
+            continue;
+        }
+
+        let key = (student_id, sheet.code.variant);
+        match copies.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, sheets)) => sheets.push((page, sheet)),
+            None => copies.push((key, vec![(page, sheet)])),
+        }
+    }
+
+    let mut recorded = Vec::new();
+    for ((student_id, variant), sheets) in copies {
+        let first_page = sheets[0].0;
+        let layout = build_variant(&quiz, paper_seed(&quiz._id, variant));
+        let needed = layout.question_order.len().div_ceil(QUESTIONS_PER_SHEET);
+        let missing: Vec<String> = (0..needed)
+            .filter(|n| !sheets.iter().any(|(_, s)| s.code.sheet == *n))
+            .map(|n| (n + 1).to_string())
+            .collect();
+        if !missing.is_empty() {
+            errors.push(ItemError {
+                item: first_page,
+                message: format!("Sheets {} of {} are missing", missing.join(", "), needed),
+            });
+            continue;
+        }
+        if let Some((page, _)) = sheets
+            .iter()
+            .enumerate()
+            .find(|(i, (_, s))| {
+                sheets[..*i]
+                    .iter()
+                    .any(|(_, t)| t.code.sheet == s.code.sheet)
+            })
+            .map(|(_, s)| s)
+        {
+            errors.push(ItemError {
+                item: *page,
+                message: "Sheet was scanned twice".to_string(),
+            });
+            continue;
+        }
+
+        let multi_select: Vec<bool> = layout
+            .question_order
+            .iter()
+            .map(|q| quiz.multi_select.contains_key(&q.to_string()))
+            .collect();
+        let sheets: Vec<&ScannedSheet> = sheets.iter().map(|(_, s)| s).collect();
+        let (ans, unclear) = marked_answers(&layout, &multi_select, &sheets);
+
+        // One attempt per student and printed variant, so scanning the same
+        // sheets again records nothing new.
+        let attempts = db.collection::<Attempt>("attempts");
+        let attempt_id = format!("paper:{}:{}:{}", quiz._id, student_id, variant);
+        let already_recorded = ItemError {
+            item: first_page,
+            message: "These sheets are already recorded".to_string(),
+        };
+        if attempts
+            .find_one(doc! { "_id": &attempt_id }, None)
+            .await?
+            .is_some()
+        {
+            errors.push(already_recorded);
+            continue;
+        }
+        let open = get_open_attempt(&quiz._id, &student_id, attempts.clone())
+            .await
+            .map_err(ApiError::Internal)?;
+        if open.is_some() {
+            errors.push(ItemError {
+                item: first_page,
+                message: "Student has an attempt in progress".to_string(),
+            });
+            continue;
+        }
+        let history = get_marks(&quiz._id, &student_id, db.collection("marks"))
+            .await
+            .map_err(ApiError::Internal)?;
+        if let Err(error) = attempt_allowed(&quiz, &history, Utc::now()) {
+            errors.push(ItemError {
+                item: first_page,
+                message: error.message().to_string(),
+            });
+            continue;
+        }
+
+        let attempt = Attempt {
+            _id: attempt_id,
+            quiz_id: quiz._id.clone(),
+            student_id: student_id.clone(),
+            seed: paper_seed(&quiz._id, variant),
+            created_at: Utc::now(),
+            submitted: false,
+            deadline: None,
+            ans: ans.clone(),
+        };
+        match attempts.insert_one(&attempt, None).await {
+            Ok(_) => {}
+            Err(error) if is_duplicate_key(&error) => {
+                errors.push(already_recorded);
+                continue;
+            }
+            Err(error) => {
+                return Err(ApiError::from(error));
+            }
+        }
+
+        match finalise_attempt(&db, &quiz, &attempt, &ans).await {
+            Ok(marks) => recorded.push(ScannedCopy {
+                student_id,
+                variant: PAPER_VARIANTS[variant],
+                attempt_id: attempt._id,
+                marks,
+                unclear,
+            }),
+            Err(error) => {
+                return Err(ApiError::Internal(error));
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(&ScanSheetsResponse {
+        status: "success".to_string(),
+        recorded,
+        errors,
+    }))
+}
+
+/// Refuses another attempt once the student has used them all or is still
+/// cooling down from the last one. `history` is their graded attempts.
+fn attempt_allowed(
+    quiz: &QuizTable,
+    history: &[QuizMarks],
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    if matches!(quiz.max_attempts, Some(max) if history.len() as u32 >= max) {
+        return Err(ApiError::Forbidden("No attempts left".to_string()));
+    }
+
+    if let (Some(cooldown), Some(last)) = (quiz.cooldown_minutes, history.last()) {
+        let available = last.submitted_at.to_chrono() + chrono::Duration::minutes(cooldown);
+        if now < available {
+            return Err(ApiError::RateLimited {
+                message: format!("Next attempt available at {}", available.to_rfc3339()),
+                retry_after: (available - now).num_seconds().max(1),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[post("/start_quiz")]
+async fn start_quiz(
+    db: web::Data<Database>,
+    form: web::Form<StartQuiz>,
+) -> Result<HttpResponse, ApiError> {
+    let quiz = get_quiz(&form.quiz_id, quiz_collection(&db)).await?;
+
+    if !quiz.student_id.is_empty() && !quiz.student_id.contains(&form.student_id) {
+        return Err(ApiError::Forbidden(
+            "Quiz is not assigned to this student".to_string(),
+        ));
+    }
+
+    let now = Utc::now();
+    if now < quiz.from || now > quiz.to {
+        return Err(ApiError::Forbidden("Quiz is not open".to_string()));
+    }
+
+    let attempts = db.collection::<Attempt>("attempts");
+    let open = get_open_attempt(&quiz._id, &form.student_id, attempts.clone())
+        .await
+        .map_err(ApiError::Internal)?;
+
+    // Resume the attempt already in progress, e.g. from another device.
+    if let Some(attempt) = open {
+        if !attempt_expired(&attempt) {
+            let response_json = &AttemptResponse {
+                status: "success".to_string(),
+                attempt_id: attempt._id.clone(),
+                questions: build_variant(&quiz, attempt.seed).render(&quiz),
+                ans: attempt.ans.clone(),
+                remaining_seconds: remaining_seconds(&attempt),
+            };
+            return Ok(HttpResponse::Ok().json(response_json));
+        }
+
+        if let Err(error) = finalise_attempt(&db, &quiz, &attempt, &attempt.ans).await {
+            return Err(ApiError::Internal(error));
+        }
+    }
+
+    let history = get_marks(&quiz._id, &form.student_id, db.collection("marks"))
+        .await
+        .map_err(ApiError::Internal)?;
+
+    attempt_allowed(&quiz, &history, now)?;
+
+    let seed = rand::random();
+    let variant = build_variant(&quiz, seed);
+    let attempt = Attempt {
+        _id: Uuid::new_v4().to_string(),
+        quiz_id: quiz._id.clone(),
+        student_id: form.student_id.clone(),
+        seed,
+        created_at: now,
+        submitted: false,
+        deadline: attempt_deadline(&quiz, &form.student_id, now).map(bson::DateTime::from_chrono),
+        ans: vec![Answer::default(); variant.question_order.len()],
+    };
+
+    match attempts.insert_one(&attempt, None).await {
+        Ok(_) => {}
+        // A concurrent start opened one first; resume that attempt instead.
+        Err(error) if is_duplicate_key(&error) => {
+            let attempt = get_open_attempt(&quiz._id, &form.student_id, attempts)
+                .await
+                .map_err(ApiError::Internal)?
+                .ok_or_else(|| ApiError::Conflict("Attempt already started".to_string()))?;
+            let response_json = &AttemptResponse {
+                status: "success".to_string(),
+                attempt_id: attempt._id.clone(),
+                questions: build_variant(&quiz, attempt.seed).render(&quiz),
+                ans: attempt.ans.clone(),
+                remaining_seconds: remaining_seconds(&attempt),
+            };
+            return Ok(HttpResponse::Ok().json(response_json));
+        }
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    }
+
+    let response_json = &AttemptResponse {
+        status: "success".to_string(),
+        attempt_id: attempt._id.clone(),
+        questions: variant.render(&quiz),
+        ans: attempt.ans.clone(),
+        remaining_seconds: remaining_seconds(&attempt),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[get("/attempt/{attempt_id}")]
+async fn get_attempt_handler(
+    db: web::Data<Database>,
+    attempt_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let attempt = get_attempt(&attempt_id, db.collection::<Attempt>("attempts")).await?;
+
+    if attempt.submitted {
+        return Err(ApiError::Conflict("Attempt already submitted".to_string()));
+    }
+
+    let quiz = get_quiz(&attempt.quiz_id, quiz_collection(&db)).await?;
+
+    if attempt_expired(&attempt) {
+        return Err(
+            match finalise_attempt(&db, &quiz, &attempt, &attempt.ans).await {
+                Ok(marks) => ApiError::Gone(format!(
+                    "Time is up, attempt submitted with {} marks",
+                    marks
+                )),
+                Err(error) => ApiError::Internal(error),
+            },
+        );
+    }
+
+    let response_json = &AttemptResponse {
+        status: "success".to_string(),
+        attempt_id: attempt._id.clone(),
+        questions: build_variant(&quiz, attempt.seed).render(&quiz),
+        ans: attempt.ans.clone(),
+        remaining_seconds: remaining_seconds(&attempt),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/save_answer")]
+async fn save_answer(
+    db: web::Data<Database>,
+    body: web::Json<SaveAnswer>,
+) -> Result<HttpResponse, ApiError> {
+    let attempts = db.collection::<Attempt>("attempts");
+
+    let attempt = get_attempt(&body.attempt_id, attempts.clone()).await?;
+
+    if attempt.submitted {
+        return Err(ApiError::Conflict("Attempt already submitted".to_string()));
+    }
+
+    if attempt_expired(&attempt) {
+        let quiz = get_quiz(&attempt.quiz_id, quiz_collection(&db)).await?;
+        return Err(
+            match finalise_attempt(&db, &quiz, &attempt, &attempt.ans).await {
+                Ok(marks) => ApiError::Gone(format!(
+                    "Time is up, attempt submitted with {} marks",
+                    marks
+                )),
+                Err(error) => ApiError::Internal(error),
+            },
+        );
+    }
+
+    if body.question >= attempt.ans.len() {
+        return Err(ApiError::BadRequest("No such question".to_string()));
+    }
+
+    let answer =
+        bson::to_bson(&body.answer).map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let filter = doc! { "_id": &attempt._id, "submitted": false };
+    let update = doc! { "$set": { format!("ans.{}", body.question): answer } };
+    match attempts.update_one(filter, update, None).await {
+        Ok(s) if s.matched_count == 0 => {
+            Err(ApiError::Conflict("Attempt already submitted".to_string()))
+        }
+        Ok(_) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Answer saved".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+#[post("/submit_quiz")]
+async fn submit_quiz(
+    db: web::Data<Database>,
+    body: web::Json<SubmitQuiz>,
+) -> Result<HttpResponse, ApiError> {
+    let attempt = get_attempt(&body.attempt_id, db.collection::<Attempt>("attempts")).await?;
+
+    if attempt.submitted {
+        return Err(ApiError::Conflict("Attempt already submitted".to_string()));
+    }
+
+    let quiz = get_quiz(&attempt.quiz_id, quiz_collection(&db)).await?;
+
+    // Answers arriving after the deadline are ignored; the attempt is graded
+    // as it stood when time ran out.
+    let ans: &[Answer] = match &body.ans {
+        Some(ans) if !attempt_expired(&attempt) => ans,
+        _ => &attempt.ans,
+    };
+
+    let marks = finalise_attempt(&db, &quiz, &attempt, ans)
+        .await
+        .map_err(ApiError::Internal)?;
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: marks.to_string(),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[get("/attempts/{quiz_id}/{student_id}")]
+async fn attempt_history(
+    db: web::Data<Database>,
+    caller: Caller,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (quiz_id, student_id) = path.into_inner();
+
+    let quiz = get_quiz(&quiz_id, quiz_collection(&db)).await?;
+    // The quiz's faculty may read any student's attempts.
+    if caller.user_id() != quiz.faculty_id {
+        caller.check(&student_id)?;
+    }
+
+    let attempts = get_marks(&quiz_id, &student_id, db.collection("marks"))
+        .await
+        .map_err(ApiError::Internal)?;
+
+    let response_json = &AttemptHistoryResponse {
+        status: "success".to_string(),
+        grade: quiz.student_marks.get(&student_id).copied(),
+        attempts,
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/grant_extra_time")]
+async fn grant_extra_time(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<GrantExtraTime>,
+) -> Result<HttpResponse, ApiError> {
+    if form.minutes <= 0 {
+        return Err(ApiError::BadRequest("minutes must be positive".to_string()));
+    }
+
+    let quizzes = quiz_collection(&db);
+    let quiz = get_quiz(&form.quiz_id, quizzes.clone()).await?;
+    let update = extra_time_update(&caller, &quiz, &form)?;
+
+    let filter = doc! { "_id": &form.quiz_id };
+    match quizzes.update_one(filter, update, None).await {
+        Ok(s) if s.matched_count == 0 => {
+            return Err(ApiError::NotFound("Quiz not found".to_string()));
+        }
+        Ok(_) => {}
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    // Read it back with the new extra time.
+    let quiz = get_quiz(&form.quiz_id, quizzes).await?;
+
+    // Stretch any attempt the student already has open.
+    let attempts = db.collection::<Attempt>("attempts");
+    let filter =
+        doc! { "quiz_id": &form.quiz_id, "student_id": &form.student_id, "submitted": false };
+    let open: Vec<Attempt> = match attempts.find(filter, None).await {
+        Ok(cursor) => cursor.try_collect().await?,
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    for attempt in open {
+        let deadline = attempt_deadline(&quiz, &attempt.student_id, attempt.created_at)
+            .map(bson::DateTime::from_chrono);
+        let filter = doc! { "_id": &attempt._id };
+        let update = doc! { "$set": { "deadline": deadline } };
+        if let Err(error) = attempts.update_one(filter, update, None).await {
+            return Err(ApiError::from(error));
+        }
+    }
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: "Extra time granted".to_string(),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+/// The update granting `form`'s extra time, if the caller set the quiz.
+fn extra_time_update(
+    caller: &Caller,
+    quiz: &QuizTable,
+    form: &GrantExtraTime,
+) -> Result<Document, ApiError> {
+    caller.check(&quiz.faculty_id)?;
+    Ok(doc! { "$set": { format!("extra_time.{}", form.student_id): form.minutes } })
+}
+
+#[get("/due_cards/{student_id}")]
+async fn due_cards(
+    db: web::Data<Database>,
+    caller: Caller,
+    student_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&student_id)?;
+    let cards = get_study_cards(&student_id, &db).await?;
+
+    let filter = doc! { "student_id": student_id.as_str() };
+    let states: Vec<ReviewState> = match db
+        .collection::<ReviewState>("reviews")
+        .find(filter, None)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await?,
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+    let due: HashMap<String, bson::DateTime> =
+        states.into_iter().map(|s| (s.card_id, s.due)).collect();
+
+    // Overdue cards first, most overdue at the front, then cards never seen.
+    let now = bson::DateTime::now();
+    let mut cards: Vec<(Option<bson::DateTime>, Flashcard)> = cards
+        .into_iter()
+        .map(|card| (due.get(&card._id).copied(), card))
+        .filter(|(due, _)| due.is_none_or(|d| d <= now))
+        .collect();
+    cards.sort_by_key(|(due, _)| due.map_or(i64::MAX, |d| d.timestamp_millis()));
+
+    let response_json = &DueCardsResponse {
+        status: "success".to_string(),
+        cards: cards
+            .into_iter()
+            .map(|(_, card)| {
+                let (front, back) = cloze::card_sides(&card);
+                StudyCard { card, front, back }
+            })
+            .collect(),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/review_card")]
+async fn review_card(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<ReviewCard>,
+) -> Result<HttpResponse, ApiError> {
+    if form.grade > 5 {
+        return Err(ApiError::BadRequest(
+            "grade must be between 0 and 5".to_string(),
+        ));
+    }
+
+    let student_id = caller.user_id();
+    let cards = get_study_cards(student_id, &db).await?;
+
+    if !cards.iter().any(|card| card._id == form.card_id) {
+        return Err(ApiError::NotFound("Flashcard not found".to_string()));
+    }
+
+    let coll = db.collection::<ReviewState>("reviews");
+    let id = format!("{}:{}", student_id, form.card_id);
+    let mut state = match coll.find_one(doc! { "_id": &id }, None).await {
+        Ok(Some(s)) => s,
+        Ok(None) => ReviewState {
+            _id: id,
+            student_id: student_id.to_string(),
+            card_id: form.card_id.clone(),
+            ease: START_EASE,
+            interval_days: 0,
+            repetitions: 0,
+            lapses: 0,
+            due: bson::DateTime::now(),
+            last_reviewed: None,
+        },
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    review(&mut state, form.grade, Utc::now());
+
+    let options = ReplaceOptions::builder().upsert(true).build();
+    if let Err(error) = coll
+        .replace_one(doc! { "_id": &state._id }, &state, options)
+        .await
+    {
+        return Err(ApiError::from(error));
+    }
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: state.due.to_chrono().to_rfc3339(),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/create_deck")]
+async fn create_deck(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<CreateDeck>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.owner_id)?;
+    let deck = Deck {
+        _id: Uuid::new_v4().to_string(),
+        owner_id: form.owner_id.clone(),
+        name: form.name.clone(),
+        cards: Vec::new(),
+        shared_with: HashMap::new(),
+        class_shares: HashMap::new(),
+        forked_from: None,
+        created_at: Utc::now(),
+    };
+
+    let resp = db
+        .collection::<Deck>("decks")
+        .insert_one(&deck, None)
+        .await?;
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: resp.inserted_id.to_string(),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[get("/decks/{user_id}")]
+async fn list_decks(
+    db: web::Data<Database>,
+    caller: Caller,
+    user_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&user_id)?;
+    let decks = get_user_decks(&user_id, db.collection("decks"))
+        .await
+        .map_err(ApiError::Internal)?;
+
+    let response_json = &DecksResponse {
+        status: "success".to_string(),
+        decks,
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[post("/edit_deck_card")]
+async fn edit_deck_card(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<EditDeckCard>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.user_id)?;
+    let coll = db.collection::<Deck>("decks");
+
+    if let Some(number) = form.cloze {
+        if !cloze::numbers(&form.content).contains(&number) {
+            return Err(ApiError::BadRequest(format!(
+                "content has no {{{{c{}::...}}}} deletion",
+                number
+            )));
+        }
+    }
+
+    let card = Flashcard {
+        _id: form
+            .card_id
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string()),
+        topic: form.topic.clone(),
+        content: form.content.clone(),
+        tags: Vec::new(),
+        cloze: form.cloze,
+    };
+
+    let bson_card = to_document(&card).map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let (filter, update) = match &form.card_id {
+        Some(card_id) => (
+            doc! { "_id": &form.deck_id, "owner_id": &form.user_id, "cards._id": card_id },
+            doc! { "$set": { "cards.$": bson_card } },
+        ),
+        None => (
+            doc! { "_id": &form.deck_id, "owner_id": &form.user_id },
+            doc! { "$push": { "cards": bson_card } },
+        ),
+    };
+
+    match coll.update_one(filter, update, None).await {
+        Ok(s) if s.matched_count == 0 => Err(ApiError::NotFound(
+            "Deck or card not found for this owner".to_string(),
+        )),
+        Ok(_) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: card._id,
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+#[post("/remove_deck_card")]
+async fn remove_deck_card(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<RemoveDeckCard>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.user_id)?;
+    let filter = doc! { "_id": &form.deck_id, "owner_id": &form.user_id };
+    let update = doc! { "$pull": { "cards": { "_id": &form.card_id } } };
+
+    match db
+        .collection::<Deck>("decks")
+        .update_one(filter, update, None)
+        .await
+    {
+        Ok(s) if s.modified_count == 0 => Err(ApiError::NotFound(
+            "Deck or card not found for this owner".to_string(),
+        )),
+        Ok(_) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Card removed".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+#[post("/share_deck")]
+async fn share_deck(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<ShareDeck>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.owner_id)?;
+    let access =
+        bson::to_bson(&form.access).map_err(|error| ApiError::Internal(error.to_string()))?;
+
+    let filter = doc! { "_id": &form.deck_id, "owner_id": &form.owner_id };
+    let update = doc! { "$set": { format!("shared_with.{}", form.user_id): access } };
+
+    match db
+        .collection::<Deck>("decks")
+        .update_one(filter, update, None)
+        .await
+    {
+        Ok(s) if s.matched_count == 0 => Err(ApiError::NotFound(
+            "Deck not found for this owner".to_string(),
+        )),
+        Ok(_) => {
+            let response_json = &GenericResponse {
+                status: "success".to_string(),
+                message: "Deck shared".to_string(),
+            };
+            Ok(HttpResponse::Ok().json(response_json))
+        }
+        Err(error) => Err(ApiError::from(error)),
+    }
+}
+
+#[post("/fork_deck")]
+async fn fork_deck(
+    db: web::Data<Database>,
+    caller: Caller,
+    form: web::Form<ForkDeck>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.user_id)?;
+    let coll = db.collection::<Deck>("decks");
+
+    let deck = get_deck(&form.deck_id, coll.clone()).await?;
+
+    let can_copy = deck.owner_id == form.user_id
+        || deck.shared_with.get(&form.user_id) == Some(&DeckAccess::Copyable);
+    if !can_copy {
+        return Err(ApiError::Forbidden(
+            "Deck is not shared with this user as copyable".to_string(),
+        ));
+    }
+
+    // Cards get fresh ids so review progress on the copy starts over.
+    let fork = Deck {
+        _id: Uuid::new_v4().to_string(),
+        owner_id: form.user_id.clone(),
+        name: deck.name,
+        cards: deck
+            .cards
+            .into_iter()
+            .map(|card| Flashcard {
+                _id: Uuid::new_v4().to_string(),
+                ..card
+            })
+            .collect(),
+        shared_with: HashMap::new(),
+        class_shares: HashMap::new(),
+        forked_from: Some(deck._id),
+        created_at: Utc::now(),
+    };
+
+    let resp = coll.insert_one(&fork, None).await?;
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: resp.inserted_id.to_string(),
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+#[get("/export_deck/{deck_id}/{user_id}")]
+async fn export_deck_handler(
+    db: web::Data<Database>,
+    caller: Caller,
+    path: web::Path<(String, String)>,
+    query: web::Query<ExportDeck>,
+) -> Result<HttpResponse, ApiError> {
+    let (deck_id, user_id) = path.into_inner();
+    caller.check(&user_id)?;
+
+    let format = match DeckFormat::parse(query.format.as_deref().unwrap_or("apkg")) {
+        Some(s) => s,
+        None => {
+            return Err(ApiError::BadRequest(
+                "format must be apkg, csv or tsv".to_string(),
+            ));
+        }
+    };
+
+    let deck = get_deck(&deck_id, db.collection("decks")).await?;
+
+    if !deck.can_read(&user_id) {
+        return Err(ApiError::Forbidden(
+            "Deck is not shared with this user".to_string(),
+        ));
+    }
+
+    let card_ids: Vec<&str> = deck.cards.iter().map(|card| card._id.as_str()).collect();
+    let filter = doc! { "student_id": &user_id, "card_id": { "$in": card_ids } };
+    let reviews: HashMap<String, ReviewState> = match db
+        .collection::<ReviewState>("reviews")
+        .find(filter, None)
+        .await
+    {
+        Ok(cursor) => cursor
+            .try_collect::<Vec<ReviewState>>()
+            .await?
+            .into_iter()
+            .map(|review| (review.card_id.clone(), review))
+            .collect(),
+        Err(error) => {
+            return Err(ApiError::from(error));
+        }
+    };
+
+    let file_name = format!("{}.{}", deck.name, format.extension());
+    let bytes = match web::block(move || export_deck(&deck, &reviews, format)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(error)) => {
+            return Err(ApiError::Internal(error));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error.to_string()));
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition::attachment(file_name))
+        .body(bytes))
+}
+
+#[post("/import_deck")]
+async fn import_deck_handler(
+    db: web::Data<Database>,
+    caller: Caller,
+    MultipartForm(form): MultipartForm<ImportDeck>,
+) -> Result<HttpResponse, ApiError> {
+    caller.check(&form.owner_id)?;
+    let file_name = form.file.file_name.clone().unwrap_or_default();
+    let extension = std::path::Path::new(&file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    let format = match DeckFormat::parse(form.format.as_deref().map_or(extension, |f| f.as_str())) {
+        Some(s) => s,
+        None => {
+            return Err(ApiError::BadRequest(
+                "format must be apkg, csv or tsv".to_string(),
+            ));
+        }
+    };
+
+    let owner_id = form.owner_id.into_inner();
+    let name = form.name.map(|n| n.into_inner()).unwrap_or_else(|| {
+        std::path::Path::new(&file_name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("Imported deck")
+            .to_string()
+    });
+
+    let path = form.file.file.path().to_owned();
+    let student_id = owner_id.clone();
+    let imported = web::block(move || {
+        std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| import_deck(&bytes, format, &student_id))
+    })
+    .await;
+    let imported = match imported {
+        Ok(Ok(s)) => s,
+        Ok(Err(error)) => {
+            return Err(ApiError::Validation(vec![FieldError {
+                field: "file".to_string(),
+                message: error,
+            }]));
+        }
+        Err(error) => {
+            return Err(ApiError::Internal(error.to_string()));
+        }
+    };
+
+    let (cards, reviews): (Vec<Flashcard>, Vec<Option<ReviewState>>) = imported
+        .into_iter()
+        .map(|imported| (imported.card, imported.review))
+        .unzip();
+    let reviews: Vec<ReviewState> = reviews.into_iter().flatten().collect();
+    let card_ids: Vec<String> = reviews.iter().map(|r| r.card_id.clone()).collect();
+
+    let deck = Deck {
+        _id: Uuid::new_v4().to_string(),
+        owner_id,
+        name,
+        cards,
+        shared_with: HashMap::new(),
+        class_shares: HashMap::new(),
+        forked_from: None,
+        created_at: Utc::now(),
+    };
+
+    if let Err(error) = db.collection::<Deck>("decks").insert_one(&deck, None).await {
+        return Err(ApiError::from(error));
+    }
+
+    if !reviews.is_empty() {
+        if let Err(error) = db
+            .collection::<ReviewState>("reviews")
+            .insert_many(reviews, None)
+            .await
+        {
+            // Don't leave a deck whose progress was lost; the import can be
+            // retried as a whole.
+            let filter = doc! { "_id": &deck._id };
+            if let Err(error) = db
+                .collection::<Deck>("decks")
+                .delete_one(filter, None)
+                .await
+            {
+                log::error!("Could not remove partly imported deck: {}", error);
+            }
+            let filter = doc! { "student_id": &deck.owner_id, "card_id": { "$in": card_ids } };
+            if let Err(error) = db
+                .collection::<ReviewState>("reviews")
+                .delete_many(filter, None)
+                .await
+            {
+                log::error!("Could not remove partly imported reviews: {}", error);
+            }
+            return Err(ApiError::from(error));
+        }
+    }
+
+    let response_json = &GenericResponse {
+        status: "success".to_string(),
+        message: deck._id,
+    };
+
+    Ok(HttpResponse::Ok().json(response_json))
+}
+
+pub fn config(conf: &mut web::ServiceConfig) {
+    // Requests that cannot be parsed get the same JSON errors as the rest.
+    let scope = web::scope("/api")
+        .app_data(
+            web::FormConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            web::JsonConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            MultipartFormConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            web::PathConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            web::QueryConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .wrap(ApiKeyAuth)
+        .service(health_checker_handler)
+        .service(generate_flashcard)
         .service(generate_quiz)
         .service(add_student)
         .service(add_faculty)
+        .service(import_roster)
+        .service(create_class)
+        .service(list_classes)
+        .service(enroll)
+        .service(unenroll)
+        .service(assign_to_class)
         .service(create_flash)
         .service(create_quiz)
-        .service(login_user);
+        .service(publish_quiz)
+        .service(export_quiz_handler)
+        .service(exam_pdf)
+        .service(exam_key_pdf)
+        .service(import_questions_handler)
+        .service(question_bank)
+        .service(scan_sheets)
+        .service(start_quiz)
+        .service(get_attempt_handler)
+        .service(save_answer)
+        .service(submit_quiz)
+        .service(attempt_history)
+        .service(grant_extra_time)
+        .service(due_cards)
+        .service(review_card)
+        .service(create_deck)
+        .service(list_decks)
+        .service(edit_deck_card)
+        .service(remove_deck_card)
+        .service(share_deck)
+        .service(fork_deck)
+        .service(export_deck_handler)
+        .service(import_deck_handler)
+        .service(list_users_handler)
+        .service(get_user_handler)
+        .service(update_username)
+        .service(set_user_active)
+        .service(delete_user)
+        .service(force_password_reset)
+        .service(unlock_user)
+        .service(change_password)
+        .service(setup_two_factor)
+        .service(enable_two_factor)
+        .service(disable_two_factor)
+        .service(regenerate_recovery_codes)
+        .service(set_two_factor_policy)
+        .service(reset_two_factor)
+        .service(create_api_key)
+        .service(list_api_keys)
+        .service(revoke_api_key)
+        .service(request_email_verification)
+        .service(verify_email)
+        .service(forgot_password)
+        .service(reset_password)
+        .service(login_user)
+        .service(oidc_login)
+        .service(oidc_callback)
+        .service(lti_login_get)
+        .service(lti_login_post)
+        .service(lti_jwks)
+        .service(lti_launch)
+        .service(lti_deep_link)
+        .service(lti_push_grades);
 
     conf.service(scope);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_quiz_faculty_grants_extra_time() {
+        let quiz = QuizTable {
+            _id: "q1".to_string(),
+            faculty_id: "f1".to_string(),
+            ..Default::default()
+        };
+        let form = GrantExtraTime {
+            quiz_id: "q1".to_string(),
+            student_id: "s1".to_string(),
+            minutes: 15,
+        };
+        assert!(matches!(
+            extra_time_update(&Caller::new("f2"), &quiz, &form),
+            Err(ApiError::Forbidden(_))
+        ));
+        assert!(matches!(
+            extra_time_update(&Caller::new("s1"), &quiz, &form),
+            Err(ApiError::Forbidden(_))
+        ));
+        assert_eq!(
+            extra_time_update(&Caller::new("f1"), &quiz, &form).unwrap(),
+            doc! { "$set": { "extra_time.s1": 15_i64 } }
+        );
+    }
+
+    #[test]
+    fn test_students_do_not_see_classmates() {
+        let class = Class {
+            _id: "c1".to_string(),
+            faculty_id: "f1".to_string(),
+            course: "CS101".to_string(),
+            section: "A".to_string(),
+            term: "Fall".to_string(),
+            students: vec!["s1".to_string(), "s2".to_string()],
+            quizzes: Vec::new(),
+            decks: Vec::new(),
+            created_at: Utc::now(),
+        };
+        let students = |user_id: &str| roster_for(class.clone(), user_id).students;
+        assert_eq!(students("f1"), ["s1", "s2"]);
+        assert_eq!(students("s2"), ["s2"]);
+    }
+}
@@ -1,43 +1,51 @@
-use std::env::{self};
-
+use crate::config::{BootstrapAdmin, GeminiConfig};
+use crate::error::ApiError;
 use crate::initialiser::Argon;
 
+use crate::grading::{recorded_grade, score};
 use crate::model::{
-    Content, GenerateContentRequest, GenerateContentResponse, GenerationConfig, Part,
+    AccountToken, Admin, Answer, ApiKey, Attempt, AuditEvent, BankQuestion, Class, Content, Deck,
+    Faculty, Flashcard, GenerateContentRequest, GenerateContentResponse, GenerationConfig,
+    LoginFailures, LoginRecord, Part, QuizMarks, QuizTable, SsoIdentity, Student, TokenPurpose,
+    TwoFactorPolicy, UserSummary, UserType,
 };
-use bson::{doc, document, to_document};
+use crate::oidc::{random_secret, username_for, IdClaims};
+use crate::throttle::{failure_update, forget_filter, lock_filter, lock_update};
+use crate::tokens::{hash_token, new_token};
+use crate::two_factor::{code_step, is_totp_code, normalise_recovery_code};
+use crate::validation::{credential_errors, password_errors};
+use crate::variant::build_variant;
+use bson::{doc, to_document, Bson};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::TryStreamExt;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::de::DeserializeOwned;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
-};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
 use gcp_auth::AuthenticationManager;
 
 extern crate mongodb;
 // use chrono::prelude::*;
-use mongodb::{bson::Document, Database};
-use uuid::Uuid;
-
-static MODEL_NAME: &str = "gemini-pro";
-
-pub async fn generate_ai_content(input_str: String) -> Result<GenerateContentResponse, String> {
-    let api_endpoint: String = match env::var("API_ENDPOINT") {
-        Ok(s) => s,
-        Err(err) => return Err(err.to_string()),
-    };
-    let project_id: String = match env::var("PROJECT_ID") {
-        Ok(s) => s,
-        Err(err) => return Err(err.to_string()),
-    };
-    let location_id: String = match env::var("LOCATION_ID") {
-        Ok(s) => s,
-        Err(err) => return Err(err.to_string()),
-    }; // Sometimes called "region" in gCloud docs.
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{
+    Collation, CollationStrength, FindOneAndUpdateOptions, FindOneOptions, FindOptions,
+    IndexOptions, ReturnDocument,
+};
+use mongodb::{bson::Document, Database, IndexModel};
 
-    println!("Endpoint : {:?}", api_endpoint);
+/// How long after the deadline a submission is still accepted, to absorb
+/// network latency between the student pressing submit and it arriving.
+const SUBMIT_GRACE_SECS: i64 = 30;
 
+pub async fn generate_ai_content(
+    gemini: &GeminiConfig,
+    input_str: String,
+) -> Result<GenerateContentResponse, String> {
     let endpoint_url = format!(
-        "https://{api_endpoint}/v1beta1/projects/{project_id}/locations/{location_id}/publishers/google/models/{MODEL_NAME}:generateContent"
+        "https://{}/v1beta1/projects/{}/locations/{}/publishers/google/models/{}:generateContent",
+        gemini.api_endpoint, gemini.project_id, gemini.location_id, gemini.model
     );
 
     let authentication_manager: AuthenticationManager = match AuthenticationManager::new().await {
@@ -99,8 +107,17 @@ pub fn hasher(password: String, argon: Argon) -> Result<String, String> {
     Ok(password_hash)
 }
 
+/// Hashes a recovery code with a salt of its own, so users with the same
+/// code do not get the same hash.
+pub fn hash_recovery_code(code: &str, argon: &Argon) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    match argon.argon.hash_password(code.as_bytes(), &salt) {
+        Ok(s) => Ok(s.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
 pub fn verify(inp_password: String, user_password: String, argon: Argon) -> Result<bool, String> {
-    println!("{:#?}", inp_password);
     // Hash password to PHC string ($argon2id$v=19$...)
     let parsed_hash = match PasswordHash::new(&user_password) {
         Ok(s) => s,
@@ -109,32 +126,658 @@ pub fn verify(inp_password: String, user_password: String, argon: Argon) -> Resu
         }
     };
 
-    Ok(Argon2::default()
+    Ok(argon
+        .argon
         .verify_password(inp_password.as_bytes(), &parsed_hash)
         .is_ok())
 }
 
-pub async fn get_user_pwd(
-    username: String,
-    coll: mongodb::Collection<Document>,
+/// Usernames compare without regard to case, so `Ada` and `ada` are the
+/// same user.
+pub fn username_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+/// Options for looking a user up by username.
+pub fn by_username() -> FindOneOptions {
+    FindOneOptions::builder()
+        .collation(username_collation())
+        .build()
+}
+
+/// Creates the indexes the handlers rely on, such as unique usernames.
+pub async fn ensure_indexes(db: &Database) -> Result<(), String> {
+    let index = IndexModel::builder()
+        .keys(doc! { "username": 1 })
+        .options(
+            IndexOptions::builder()
+                .name("username_ci".to_string())
+                .unique(true)
+                .collation(username_collation())
+                .build(),
+        )
+        .build();
+    if let Err(err) = db
+        .collection::<Document>("users")
+        .create_index(index, None)
+        .await
+    {
+        return Err(err.to_string());
+    }
+
+    // Each identity-provider account belongs to at most one user.
+    let index = IndexModel::builder()
+        .keys(doc! { "sso.issuer": 1, "sso.subject": 1 })
+        .options(
+            IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "sso.subject": { "$type": "string" } })
+                .build(),
+        )
+        .build();
+    if let Err(err) = db
+        .collection::<Document>("users")
+        .create_index(index, None)
+        .await
+    {
+        return Err(err.to_string());
+    }
+
+    let index = IndexModel::builder()
+        .keys(doc! { "hash": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    if let Err(err) = db
+        .collection::<Document>("api_keys")
+        .create_index(index, None)
+        .await
+    {
+        return Err(err.to_string());
+    }
+
+    // A student has at most one open attempt at a quiz, even when two starts
+    // race.
+    let index = IndexModel::builder()
+        .keys(doc! { "quiz_id": 1, "student_id": 1 })
+        .options(
+            IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "submitted": false })
+                .build(),
+        )
+        .build();
+    if let Err(err) = db
+        .collection::<Document>("attempts")
+        .create_index(index, None)
+        .await
+    {
+        return Err(err.to_string());
+    }
+
+    // Mongo removes account tokens, old sign-in failures and abandoned sign-in
+    // steps once they expire.
+    for name in [
+        "account_tokens",
+        "login_failures",
+        "oidc_logins",
+        "lti_logins",
+        "lti_deep_links",
+    ] {
+        let index = IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+        if let Err(err) = db
+            .collection::<Document>(name)
+            .create_index(index, None)
+            .await
+        {
+            return Err(err.to_string());
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_login_failures(
+    keys: &[String],
+    coll: mongodb::Collection<LoginFailures>,
+) -> Result<Vec<LoginFailures>, String> {
+    match coll.find(doc! { "_id": { "$in": keys } }, None).await {
+        Ok(cursor) => cursor.try_collect().await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Counts a failed sign-in against `key` in one atomic update, so that
+/// concurrent failures are all counted. Returns the updated record and
+/// whether this failure is the one that locked it.
+pub async fn count_login_failure(
+    key: &str,
+    threshold: u32,
+    coll: mongodb::Collection<LoginFailures>,
+) -> Result<(LoginFailures, bool), String> {
+    let now = Utc::now();
+    if let Err(err) = coll.delete_one(forget_filter(key, now), None).await {
+        return Err(err.to_string());
+    }
+
+    let options = FindOneAndUpdateOptions::builder()
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .build();
+    let mut record = match coll
+        .find_one_and_update(doc! { "_id": key }, failure_update(now), options)
+        .await
+    {
+        Ok(Some(s)) => s,
+        Ok(None) => return Err("Failed sign-in was not recorded".to_string()),
+        Err(err) => return Err(err.to_string()),
+    };
+    if !record.should_lock(threshold) {
+        return Ok((record, false));
+    }
+
+    let (update, until) = lock_update(now);
+    match coll.update_one(lock_filter(key), update, None).await {
+        Ok(s) if s.modified_count == 1 => {
+            record.locked_until = Some(until);
+            Ok((record, true))
+        }
+        Ok(_) => Ok((record, false)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub async fn record_audit(
+    db: &Database,
+    kind: &str,
+    username: Option<&str>,
+    ip: Option<&str>,
+    actor_id: Option<&str>,
+    detail: String,
+) -> Result<(), String> {
+    let event = AuditEvent {
+        _id: uuid::Uuid::new_v4().to_string(),
+        kind: kind.to_string(),
+        username: username.map(str::to_string),
+        ip: ip.map(str::to_string),
+        actor_id: actor_id.map(str::to_string),
+        detail,
+        at: bson::DateTime::now(),
+    };
+    match db
+        .collection::<AuditEvent>("audit_events")
+        .insert_one(&event, None)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// The unrevoked key `key`, if its owner is still active, noting that it
+/// was used.
+pub async fn authenticate_api_key(db: &Database, key: &str) -> Result<Option<ApiKey>, String> {
+    let filter = doc! { "hash": hash_token(key), "revoked_at": null };
+    let update = doc! { "$set": { "last_used_at": bson::DateTime::now() } };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    let api_key = match db
+        .collection::<ApiKey>("api_keys")
+        .find_one_and_update(filter, update, options)
+        .await
+    {
+        Ok(Some(s)) => s,
+        Ok(None) => return Ok(None),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let owner = doc! { "_id": &api_key.user_id, "active": { "$ne": false } };
+    match db
+        .collection::<UserSummary>("users")
+        .find_one(owner, None)
+        .await
+    {
+        Ok(s) => Ok(s.map(|_| api_key)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub const TWO_FACTOR_POLICY_ID: &str = "two_factor";
+
+/// The sign-in details of an active user.
+pub async fn get_login_record(
+    username: &str,
+    coll: mongodb::Collection<LoginRecord>,
+) -> Result<LoginRecord, ApiError> {
+    let filter = doc! { "username": username, "active": { "$ne": false } };
+    match coll.find_one(filter, by_username()).await? {
+        Some(s) => Ok(s),
+        None => Err(ApiError::NotFound("User not found".to_string())),
+    }
+}
+
+/// Whether users of `role` must sign in with a second factor.
+pub async fn two_factor_required(
+    role: Option<UserType>,
+    coll: mongodb::Collection<TwoFactorPolicy>,
+) -> Result<bool, String> {
+    let Some(role) = role else {
+        return Ok(false);
+    };
+    match coll
+        .find_one(doc! { "_id": TWO_FACTOR_POLICY_ID }, None)
+        .await
+    {
+        Ok(s) => Ok(s.is_some_and(|policy| policy.required_roles.contains(&role))),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Checks a one-time password or recovery code for `user` and uses it up,
+/// so it cannot be accepted again. Returns whether it was accepted.
+pub async fn redeem_second_factor(
+    user: &LoginRecord,
+    code: &str,
+    argon: Argon,
+    coll: mongodb::Collection<LoginRecord>,
+) -> Result<bool, String> {
+    let Some(two_factor) = user.two_factor.as_ref().filter(|t| t.enabled) else {
+        return Ok(false);
+    };
+
+    let (filter, update) = if is_totp_code(code) {
+        let now = Utc::now().timestamp() as u64;
+        let last_step = two_factor.last_step.map(|s| s as u64);
+        let Some(step) = code_step(&two_factor.secret, code, now, last_step) else {
+            return Ok(false);
+        };
+        let step = step as i64;
+        // Matching on the stored step means a code sent twice at once is
+        // only accepted by one of the requests.
+        let filter = doc! {
+            "_id": &user._id,
+            "two_factor.enabled": true,
+            "$or": [
+                { "two_factor.last_step": null },
+                { "two_factor.last_step": { "$lt": step } },
+            ],
+        };
+        (filter, doc! { "$set": { "two_factor.last_step": step } })
+    } else {
+        let code = normalise_recovery_code(code);
+        let mut matching = None;
+        for hash in &two_factor.recovery_codes {
+            if verify(code.clone(), hash.clone(), argon.clone())? {
+                matching = Some(hash);
+                break;
+            }
+        }
+        let Some(hash) = matching else {
+            return Ok(false);
+        };
+        let filter = doc! { "_id": &user._id, "two_factor.recovery_codes": hash };
+        (
+            filter,
+            doc! { "$pull": { "two_factor.recovery_codes": hash } },
+        )
+    };
+
+    match coll.update_one(filter, update, None).await {
+        Ok(s) => Ok(s.modified_count == 1),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Stores a new token for `user_id` and returns it, to be sent by email.
+pub async fn issue_token(
+    db: &Database,
+    user_id: &str,
+    purpose: TokenPurpose,
+    email: Option<String>,
+    valid_for: Duration,
 ) -> Result<String, String> {
-    let res = match coll.find_one(doc! { "username": username }, None).await {
-        Ok(s) => s,
+    let (token, hash) = new_token();
+    let record = AccountToken {
+        _id: hash,
+        user_id: user_id.to_string(),
+        purpose,
+        email,
+        expires_at: bson::DateTime::from_chrono(Utc::now() + valid_for),
+    };
+    match db
+        .collection::<AccountToken>("account_tokens")
+        .insert_one(&record, None)
+        .await
+    {
+        Ok(_) => Ok(token),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn token_filter(token: &str, purpose: TokenPurpose) -> Result<Document, ApiError> {
+    let purpose = bson::to_bson(&purpose).map_err(|err| ApiError::Internal(err.to_string()))?;
+    Ok(doc! {
+        "_id": hash_token(token),
+        "purpose": purpose,
+        "expires_at": { "$gt": bson::DateTime::now() },
+    })
+}
+
+/// A link that has expired, was used already, or never existed.
+fn link_gone() -> ApiError {
+    ApiError::Gone("Link is invalid or has expired".to_string())
+}
+
+/// The unexpired token, left in place.
+pub async fn find_token(
+    db: &Database,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<AccountToken, ApiError> {
+    let coll = db.collection::<AccountToken>("account_tokens");
+    match coll.find_one(token_filter(token, purpose)?, None).await? {
+        Some(s) => Ok(s),
+        None => Err(link_gone()),
+    }
+}
+
+/// Uses up the token. Deleting it in the same operation that finds it
+/// stops two requests from both redeeming it.
+pub async fn redeem_token(
+    db: &Database,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<AccountToken, ApiError> {
+    let coll = db.collection::<AccountToken>("account_tokens");
+    match coll
+        .find_one_and_delete(token_filter(token, purpose)?, None)
+        .await?
+    {
+        Some(s) => Ok(s),
+        None => Err(link_gone()),
+    }
+}
+
+/// Whether a write failed because it broke a unique index.
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    match err.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::BulkWrite(e) => e
+            .write_errors
+            .iter()
+            .flatten()
+            .any(|e| e.code == DUPLICATE_KEY),
+        _ => false,
+    }
+}
+
+/// Whether `user_id` is an active user with one of `roles`.
+pub async fn has_role(
+    user_id: &str,
+    roles: &[UserType],
+    coll: mongodb::Collection<UserSummary>,
+) -> Result<bool, String> {
+    let roles = bson::to_bson(roles).map_err(|err| err.to_string())?;
+    let filter = doc! { "_id": user_id, "user_type": { "$in": roles }, "active": { "$ne": false } };
+    match coll.find_one(filter, None).await {
+        Ok(s) => Ok(s.is_some()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub async fn is_admin(
+    admin_id: &str,
+    coll: mongodb::Collection<UserSummary>,
+) -> Result<bool, String> {
+    has_role(admin_id, &[UserType::Admin], coll).await
+}
+
+pub async fn get_user(
+    user_id: &str,
+    coll: mongodb::Collection<UserSummary>,
+) -> Result<UserSummary, ApiError> {
+    match coll.find_one(doc! { "_id": user_id }, None).await? {
+        Some(s) => Ok(s),
+        None => Err(ApiError::NotFound("User not found".to_string())),
+    }
+}
+
+/// One page of users ordered by username, with the number of users matching
+/// `role` across all pages.
+pub async fn list_users(
+    role: Option<UserType>,
+    page: u64,
+    per_page: i64,
+    coll: mongodb::Collection<UserSummary>,
+) -> Result<(Vec<UserSummary>, u64), String> {
+    let filter = match role {
+        Some(role) => doc! { "user_type": bson::to_bson(&role).map_err(|err| err.to_string())? },
+        None => doc! {},
+    };
+    let total = coll
+        .count_documents(filter.clone(), None)
+        .await
+        .map_err(|err| err.to_string())?;
+    // Pages past any the database could skip to are simply empty.
+    let skip = (page - 1)
+        .checked_mul(per_page as u64)
+        .filter(|s| *s <= i64::MAX as u64);
+    let Some(skip) = skip else {
+        return Ok((Vec::new(), total));
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "username": 1 })
+        .skip(skip)
+        .limit(per_page)
+        .build();
+    match coll.find(filter, options).await {
+        Ok(cursor) => Ok((
+            cursor.try_collect().await.map_err(|err| err.to_string())?,
+            total,
+        )),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// A random password handed out by an admin-forced reset.
+pub fn temporary_password() -> String {
+    loop {
+        let password: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        if password_errors(&password, "").is_empty() {
+            return password;
+        }
+    }
+}
+
+/// Creates the first admin from `admin` when no admin exists yet. Returns
+/// the new admin's id, or `None` if nothing was created.
+pub async fn bootstrap_admin(
+    db: &Database,
+    argon: Argon,
+    admin: &BootstrapAdmin,
+) -> Result<Option<String>, String> {
+    let BootstrapAdmin { username, password } = admin.clone();
+
+    if let Some(error) = credential_errors(&username, &password).first() {
+        return Err(error.message.clone());
+    }
+
+    let coll = db.collection::<Admin>("users");
+    match coll.find_one(doc! { "user_type": "Admin" }, None).await {
+        Ok(Some(_)) => return Ok(None),
+        Ok(None) => {}
         Err(err) => return Err(err.to_string()),
+    }
+
+    let admin = Admin {
+        _id: uuid::Uuid::new_v4().to_string(),
+        username,
+        password: hasher(password, argon)?,
+        user_type: UserType::Admin,
+        active: true,
+        must_reset_password: false,
+        email: None,
+        email_verified: false,
+    };
+    match coll.insert_one(&admin, None).await {
+        Ok(_) => Ok(Some(admin._id)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Removes and returns the unexpired sign-in step stored under the hash of
+/// `state`, so it can only be completed once.
+pub async fn take_sign_in<T: DeserializeOwned + Send + Sync + Unpin>(
+    state: &str,
+    coll: mongodb::Collection<T>,
+) -> Result<T, String> {
+    let filter = doc! {
+        "_id": hash_token(state),
+        "expires_at": { "$gt": bson::DateTime::now() },
     };
+    match coll.find_one_and_delete(filter, None).await {
+        Ok(Some(s)) => Ok(s),
+        Ok(None) => Err("Sign-in has expired; please start again".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub async fn get_sso_user(
+    identity: &SsoIdentity,
+    coll: mongodb::Collection<UserSummary>,
+) -> Result<Option<UserSummary>, String> {
+    let filter = doc! { "sso.issuer": &identity.issuer, "sso.subject": &identity.subject };
+    coll.find_one(filter, None)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Brings a single sign-on user's role and profile up to date with what the
+/// identity provider says about them.
+pub async fn sync_sso_user(
+    user_id: &str,
+    role: UserType,
+    claims: &IdClaims,
+    coll: mongodb::Collection<UserSummary>,
+) -> Result<UserSummary, String> {
+    let mut set = doc! { "user_type": bson::to_bson(&role).map_err(|err| err.to_string())? };
+    if let Some(email) = &claims.email {
+        set.insert("email", email);
+        set.insert("email_verified", claims.email_verified);
+    }
+    if let Some(name) = &claims.name {
+        set.insert("display_name", name);
+    }
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    match coll
+        .find_one_and_update(doc! { "_id": user_id }, doc! { "$set": set }, options)
+        .await
+    {
+        Ok(Some(s)) => Ok(s),
+        Ok(None) => Err("User not found".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
 
-    // println!("{:#?}", res);
+/// Creates the user for a first single sign-on. Their password is random,
+/// so they can only sign in through the identity provider unless they reset
+/// it. A taken username gets a numeric suffix.
+pub async fn provision_sso_user(
+    identity: SsoIdentity,
+    role: UserType,
+    claims: &IdClaims,
+    argon: Argon,
+    coll: mongodb::Collection<Document>,
+) -> Result<UserSummary, String> {
+    let base = username_for(claims);
+    for attempt in 0..5 {
+        let username = match attempt {
+            0 => base.clone(),
+            _ => format!("{}-{}", base, rand::thread_rng().gen_range(1000..10000)),
+        };
+        let email_verified = claims.email.is_some() && claims.email_verified;
+        let _id = uuid::Uuid::new_v4().to_string();
+        let password = hasher(random_secret(), argon.clone())?;
+        let user = match role {
+            UserType::Student => to_document(&Student {
+                _id,
+                username,
+                password,
+                user_type: role,
+                active: true,
+                must_reset_password: false,
+                display_name: claims.name.clone(),
+                email: claims.email.clone(),
+                email_verified,
+                class: None,
+                quiz: None,
+                flashes: None,
+            }),
+            UserType::Faculty => to_document(&Faculty {
+                _id,
+                username,
+                password,
+                user_type: role,
+                active: true,
+                must_reset_password: false,
+                email: claims.email.clone(),
+                email_verified,
+                quiz: None,
+                flashes: None,
+            }),
+            UserType::Admin => to_document(&Admin {
+                _id,
+                username,
+                password,
+                user_type: role,
+                active: true,
+                must_reset_password: false,
+                email: claims.email.clone(),
+                email_verified,
+            }),
+        };
+        let mut user = user.map_err(|err| err.to_string())?;
+        user.insert(
+            "sso",
+            to_document(&identity).map_err(|err| err.to_string())?,
+        );
 
-    match res {
-        Some(ref document) => match document.get_str("password") {
-            Ok(s) => Ok(s.to_string()),
-            Err(err) => Err(err.to_string()),
-        },
-        None => Err("Password not found".to_string()),
+        match coll.insert_one(&user, None).await {
+            Ok(_) => return bson::from_document(user).map_err(|err| err.to_string()),
+            Err(err) if is_duplicate_key(&err) => {
+                // Either the username is taken, or a concurrent sign-in
+                // already created the user.
+                if let Some(existing) = get_sso_user(&identity, coll.clone_with_type()).await? {
+                    return Ok(existing);
+                }
+            }
+            Err(err) => return Err(err.to_string()),
+        }
     }
+    Err("Could not find a free username".to_string())
 }
 
-pub async fn make_flashcards(topic: String, count: i8) -> Result<String, String> {
+pub async fn make_flashcards(
+    gemini: &GeminiConfig,
+    topic: String,
+    count: i8,
+) -> Result<String, String> {
     let prompt = format!(
         "Extract {:?} key points from the text. Present the information in a JSON format with two fields:
 * key_points_array: An array containing each key point as a string.
@@ -158,7 +801,7 @@ Text: {:?}
         count, topic
     );
 
-    let gen_response: GenerateContentResponse = match generate_ai_content(prompt).await {
+    let gen_response: GenerateContentResponse = match generate_ai_content(gemini, prompt).await {
         Ok(s) => s,
         Err(error) => return Err(error),
     };
@@ -166,12 +809,48 @@ Text: {:?}
     let part = &gen_response.candidates[0].content.parts[0];
 
     match part {
-        Part::Text(t) => return Ok(t.to_string()),
-        _ => return Err("not the same type".to_string()),
+        Part::Text(t) => Ok(t.to_string()),
+        _ => Err("not the same type".to_string()),
+    }
+}
+
+pub async fn make_cloze_flashcards(
+    gemini: &GeminiConfig,
+    topic: String,
+    count: i8,
+) -> Result<String, String> {
+    let prompt = format!(
+        "Write {:?} sentences stating the most important facts in the text. In each sentence, hide the key terms as cloze deletions using the syntax {{{{c1::term}}}}, numbering the deletions of a sentence c1, c2, ... and reusing a number only for terms that should be hidden together. A hint may follow the term as {{{{c1::term::hint}}}}. Present the information in a JSON format with one field:
+* cloze_sentences: An array containing each sentence as a string.
+Use only this valid field.
+
+Text: {:?}
+
+**Example:**
+{{
+    \"cloze_sentences\": [
+        \"{{{{c1::Mitochondria}}}} produce {{{{c2::ATP::a molecule}}}} through cellular respiration.\",
+        \"The {{{{c1::nucleus}}}} stores the cell's {{{{c2::DNA}}}}.\"
+    ]
+}}
+",
+        count, topic
+    );
+
+    let gen_response: GenerateContentResponse = match generate_ai_content(gemini, prompt).await {
+        Ok(s) => s,
+        Err(error) => return Err(error),
     };
+
+    let part = &gen_response.candidates[0].content.parts[0];
+
+    match part {
+        Part::Text(t) => Ok(t.to_string()),
+        _ => Err("not the same type".to_string()),
+    }
 }
 
-pub async fn make_quiz(topic: String, count: i8) -> Result<String, String> {
+pub async fn make_quiz(gemini: &GeminiConfig, topic: String, count: i8) -> Result<String, String> {
     let prompt = format!("**Prompt:**
 
 Given a passage of text `{:?}` and an integer {:?}, generate a JSON object containing {:?} multiple choice questions (MCQs) based on the text. Each MCQ should have the following structure:
@@ -207,7 +886,7 @@ Given a passage of text `{:?}` and an integer {:?}, generate a JSON object conta
 ]
 }}", topic, count, count, count );
 
-    let gen_response: GenerateContentResponse = match generate_ai_content(prompt).await {
+    let gen_response: GenerateContentResponse = match generate_ai_content(gemini, prompt).await {
         Ok(s) => s,
         Err(error) => return Err(error),
     };
@@ -215,7 +894,388 @@ Given a passage of text `{:?}` and an integer {:?}, generate a JSON object conta
     let part = &gen_response.candidates[0].content.parts[0];
 
     match part {
-        Part::Text(t) => return Ok(t.to_string()),
-        _ => return Err("not the same type".to_string()),
+        Part::Text(t) => Ok(t.to_string()),
+        _ => Err("not the same type".to_string()),
+    }
+}
+
+/// Where quizzes are kept. Every quiz path goes through this so none can
+/// look in a different collection.
+pub const QUIZZES: &str = "quizzes";
+
+pub fn quiz_collection(db: &Database) -> mongodb::Collection<QuizTable> {
+    db.collection(QUIZZES)
+}
+
+pub async fn get_quiz(
+    quiz_id: &str,
+    coll: mongodb::Collection<QuizTable>,
+) -> Result<QuizTable, ApiError> {
+    match coll.find_one(doc! { "_id": quiz_id }, None).await? {
+        Some(quiz) => Ok(quiz),
+        None => Err(ApiError::NotFound("Quiz not found".to_string())),
+    }
+}
+
+pub async fn get_attempt(
+    attempt_id: &str,
+    coll: mongodb::Collection<Attempt>,
+) -> Result<Attempt, ApiError> {
+    match coll.find_one(doc! { "_id": attempt_id }, None).await? {
+        Some(attempt) => Ok(attempt),
+        None => Err(ApiError::NotFound("Attempt not found".to_string())),
+    }
+}
+
+/// The flashcards held on a user's document.
+pub async fn get_flashcards(
+    user_id: &str,
+    coll: mongodb::Collection<Document>,
+) -> Result<Vec<Flashcard>, ApiError> {
+    let Some(user) = coll.find_one(doc! { "_id": user_id }, None).await? else {
+        return Err(ApiError::NotFound("User not found".to_string()));
+    };
+
+    match user.get("flashes") {
+        Some(Bson::Null) | None => Ok(Vec::new()),
+        Some(flashes) => {
+            bson::from_bson(flashes.clone()).map_err(|err| ApiError::Internal(err.to_string()))
+        }
+    }
+}
+
+pub async fn get_deck(deck_id: &str, coll: mongodb::Collection<Deck>) -> Result<Deck, ApiError> {
+    match coll.find_one(doc! { "_id": deck_id }, None).await? {
+        Some(deck) => Ok(deck),
+        None => Err(ApiError::NotFound("Deck not found".to_string())),
+    }
+}
+
+pub async fn get_class(
+    class_id: &str,
+    coll: mongodb::Collection<Class>,
+) -> Result<Class, ApiError> {
+    match coll.find_one(doc! { "_id": class_id }, None).await? {
+        Some(class) => Ok(class),
+        None => Err(ApiError::NotFound("Class not found".to_string())),
+    }
+}
+
+/// Classes the user teaches or is enrolled in.
+pub async fn get_user_classes(
+    user_id: &str,
+    coll: mongodb::Collection<Class>,
+) -> Result<Vec<Class>, String> {
+    let filter = doc! { "$or": [{ "faculty_id": user_id }, { "students": user_id }] };
+    match coll.find(filter, None).await {
+        Ok(cursor) => cursor.try_collect().await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Adds a student to a class and gives them everything assigned to it.
+pub async fn enroll_in_class(
+    db: &Database,
+    class_id: &str,
+    student_id: &str,
+) -> Result<(), String> {
+    let filter = doc! { "_id": class_id };
+    let update = doc! { "$addToSet": { "students": student_id } };
+    // Share what is assigned as of this update, so something assigned at the
+    // same time is covered either here or by the assignment.
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    let class = match db
+        .collection::<Class>("classes")
+        .find_one_and_update(filter, update, options)
+        .await
+    {
+        Ok(Some(class)) => class,
+        Ok(None) => return Err("Class not found".to_string()),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let students = [student_id.to_string()];
+    share_with_students(db, &class._id, &class.quizzes, &class.decks, &students).await
+}
+
+/// Assigns `quizzes` to `students` and lets them read `decks`, recording
+/// that the access came through `class_id`.
+pub async fn share_with_students(
+    db: &Database,
+    class_id: &str,
+    quizzes: &[String],
+    decks: &[String],
+    students: &[String],
+) -> Result<(), String> {
+    if students.is_empty() {
+        return Ok(());
+    }
+
+    if !quizzes.is_empty() {
+        let filter = doc! { "_id": { "$in": quizzes } };
+        let update = doc! { "$addToSet": { "student_id": { "$each": students } } };
+        if let Err(err) = quiz_collection(db).update_many(filter, update, None).await {
+            return Err(err.to_string());
+        }
+    }
+
+    if !decks.is_empty() {
+        let mut update = Document::new();
+        for student in students {
+            update.insert(format!("class_shares.{}", student), class_id);
+        }
+        let filter = doc! { "_id": { "$in": decks } };
+        if let Err(err) = db
+            .collection::<Deck>("decks")
+            .update_many(filter, doc! { "$addToSet": update }, None)
+            .await
+        {
+            return Err(err.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Decks the user owns or that have been shared with them, directly or
+/// through a class.
+pub async fn get_user_decks(
+    user_id: &str,
+    coll: mongodb::Collection<Deck>,
+) -> Result<Vec<Deck>, String> {
+    let filter = doc! { "$or": [
+        { "owner_id": user_id },
+        { format!("shared_with.{}", user_id): { "$exists": true } },
+        { format!("class_shares.{}", user_id): { "$exists": true } },
+    ] };
+    match coll.find(filter, None).await {
+        Ok(cursor) => cursor.try_collect().await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub async fn get_bank_questions(
+    faculty_id: &str,
+    coll: mongodb::Collection<BankQuestion>,
+) -> Result<Vec<BankQuestion>, String> {
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": 1 })
+        .build();
+    match coll.find(doc! { "faculty_id": faculty_id }, options).await {
+        Ok(cursor) => cursor.try_collect().await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Every question the faculty member owns, in their bank or their quizzes.
+pub async fn get_owned_questions(
+    faculty_id: &str,
+    db: &Database,
+) -> Result<Vec<Vec<String>>, String> {
+    let mut questions: Vec<Vec<String>> =
+        get_bank_questions(faculty_id, db.collection("question_bank"))
+            .await?
+            .into_iter()
+            .map(|q| q.question)
+            .collect();
+
+    let quizzes: Vec<QuizTable> = match quiz_collection(db)
+        .find(doc! { "faculty_id": faculty_id }, None)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await.map_err(|err| err.to_string())?,
+        Err(err) => return Err(err.to_string()),
+    };
+    questions.extend(quizzes.into_iter().flat_map(|quiz| quiz.questions));
+
+    Ok(questions)
+}
+
+/// Every card a user studies: their own flashcards and those of their decks.
+pub async fn get_study_cards(user_id: &str, db: &Database) -> Result<Vec<Flashcard>, ApiError> {
+    let mut cards = get_flashcards(user_id, db.collection("users")).await?;
+    let decks = get_user_decks(user_id, db.collection("decks"))
+        .await
+        .map_err(ApiError::Database)?;
+    for deck in decks {
+        cards.extend(deck.cards);
+    }
+    Ok(cards)
+}
+
+/// Every graded attempt of a student on a quiz, oldest first.
+pub async fn get_marks(
+    quiz_id: &str,
+    student_id: &str,
+    coll: mongodb::Collection<QuizMarks>,
+) -> Result<Vec<QuizMarks>, String> {
+    let filter = doc! { "quiz_id": quiz_id, "student_id": student_id };
+    let options = FindOptions::builder()
+        .sort(doc! { "submitted_at": 1 })
+        .build();
+    match coll.find(filter, options).await {
+        Ok(cursor) => cursor.try_collect().await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// The attempt the student has not submitted yet, if any.
+pub async fn get_open_attempt(
+    quiz_id: &str,
+    student_id: &str,
+    coll: mongodb::Collection<Attempt>,
+) -> Result<Option<Attempt>, String> {
+    let filter = doc! { "quiz_id": quiz_id, "student_id": student_id, "submitted": false };
+    coll.find_one(filter, None)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Deadline for an attempt started at `started`, including any extra time
+/// granted to the student. Never later than the quiz closing plus that extra.
+pub fn attempt_deadline(
+    quiz: &QuizTable,
+    student_id: &str,
+    started: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let minutes = quiz.duration_minutes?;
+    let extra = Duration::minutes(quiz.extra_time.get(student_id).copied().unwrap_or(0));
+    Some((started + Duration::minutes(minutes)).min(quiz.to) + extra)
+}
+
+pub fn remaining_seconds(attempt: &Attempt) -> Option<i64> {
+    attempt
+        .deadline
+        .map(|d| (d.to_chrono() - Utc::now()).num_seconds().max(0))
+}
+
+/// Whether the attempt is past its deadline, allowing for the submit grace.
+pub fn attempt_expired(attempt: &Attempt) -> bool {
+    match attempt.deadline {
+        Some(d) => Utc::now() > d.to_chrono() + Duration::seconds(SUBMIT_GRACE_SECS),
+        None => false,
+    }
+}
+
+/// Grades `ans` (in the attempt's variant order), records the marks and
+/// closes the attempt. Fails if the attempt was already closed.
+pub async fn finalise_attempt(
+    db: &Database,
+    quiz: &QuizTable,
+    attempt: &Attempt,
+    ans: &[Answer],
+) -> Result<f64, String> {
+    let filter = doc! { "_id": &attempt._id, "submitted": false };
+    let update = doc! { "$set": { "submitted": true } };
+    match db
+        .collection::<Attempt>("attempts")
+        .update_one(filter, update, None)
+        .await
+    {
+        Ok(s) if s.modified_count == 0 => return Err("Attempt already submitted".to_string()),
+        Ok(_) => {}
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let variant = build_variant(quiz, attempt.seed);
+    let ans = variant.to_original(ans, quiz.questions.len());
+    let marks = score(quiz, &variant.question_order, &ans);
+
+    let coll = db.collection::<QuizMarks>("marks");
+    let mut history = match get_marks(&quiz._id, &attempt.student_id, coll.clone()).await {
+        Ok(s) => s,
+        Err(err) => return Err(err),
+    };
+
+    let result = QuizMarks {
+        _id: attempt._id.clone(),
+        quiz_id: quiz._id.clone(),
+        student_id: attempt.student_id.clone(),
+        ans,
+        marks,
+        attempt: history.len() as u32 + 1,
+        submitted_at: bson::DateTime::now(),
     };
+
+    if let Err(err) = coll.insert_one(&result, None).await {
+        return Err(err.to_string());
+    }
+    history.push(result);
+
+    let grade = recorded_grade(&quiz.grading, &history).unwrap_or(marks);
+    let filter = doc! { "_id": &quiz._id };
+    let update = doc! { "$set": { format!("student_marks.{}", attempt.student_id): grade } };
+    if let Err(err) = quiz_collection(db).update_one(filter, update, None).await {
+        return Err(err.to_string());
+    }
+
+    Ok(marks)
+}
+
+/// Closes every open attempt whose deadline has passed. Runs for the life
+/// of the server so timed attempts end even if the student never returns.
+pub async fn sweep_expired_attempts(db: Database) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+
+        let cutoff = bson::DateTime::from_chrono(Utc::now() - Duration::seconds(SUBMIT_GRACE_SECS));
+        let filter = doc! { "submitted": false, "deadline": { "$lt": cutoff } };
+        let expired: Vec<Attempt> = match db
+            .collection::<Attempt>("attempts")
+            .find(filter, None)
+            .await
+        {
+            Ok(cursor) => match cursor.try_collect().await {
+                Ok(s) => s,
+                Err(err) => {
+                    log::error!("failed to read expired attempts: {}", err);
+                    continue;
+                }
+            },
+            Err(err) => {
+                log::error!("failed to look up expired attempts: {}", err);
+                continue;
+            }
+        };
+
+        for attempt in expired {
+            let quiz = match get_quiz(&attempt.quiz_id, quiz_collection(&db)).await {
+                Ok(s) => s,
+                Err(err) => {
+                    log::error!("attempt {}: {}", attempt._id, err);
+                    continue;
+                }
+            };
+            if let Err(err) = finalise_attempt(&db, &quiz, &attempt, &attempt.ans).await {
+                log::error!("attempt {}: {}", attempt._id, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::options::ClientOptions;
+    use mongodb::Client;
+
+    #[actix_web::test]
+    async fn test_quiz_paths_share_a_collection() {
+        let client = Client::with_options(ClientOptions::default()).unwrap();
+        let db = client.database("quizzes");
+        assert_eq!(quiz_collection(&db).name(), "quizzes");
+        assert_eq!(quiz_collection(&db).namespace().db, "quizzes");
+    }
+
+    #[test]
+    fn test_recovery_codes_get_their_own_salt() {
+        let argon = crate::initialiser::initialise_argon();
+        let first = hash_recovery_code("abcd-efgh", &argon).unwrap();
+        let second = hash_recovery_code("abcd-efgh", &argon).unwrap();
+        assert_ne!(first, second);
+        assert!(verify("abcd-efgh".to_string(), first, argon.clone()).unwrap());
+        assert!(verify("abcd-efgh".to_string(), second, argon).unwrap());
+    }
 }
@@ -1,8 +1,13 @@
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    password_hash::{rand_core::OsRng, SaltString},
     Argon2,
 };
 
+use crate::config::{Config, GeminiConfig};
+use crate::lti::Lti;
+use crate::mailer::Mailer;
+use crate::oidc::Oidc;
+
 #[derive(Clone)]
 pub struct Argon {
     pub salt: SaltString,
@@ -11,7 +16,17 @@ pub struct Argon {
 
 #[derive(Clone)]
 pub struct Util {
-    pub Argon: Argon,
+    pub argon: Argon,
+    /// `None` when Gemini is not configured.
+    pub gemini: Option<GeminiConfig>,
+    /// `None` when no SMTP server is configured.
+    pub mailer: Option<Mailer>,
+    /// `None` when single sign-on is not configured.
+    pub oidc: Option<Oidc>,
+    /// `None` when the LTI tool is not configured.
+    pub lti: Option<Lti>,
+    /// Signs session tokens.
+    pub jwt_secret: Vec<u8>,
 }
 
 pub fn initialise_argon() -> Argon {
@@ -21,10 +36,30 @@ pub fn initialise_argon() -> Argon {
     }
 }
 
-pub fn initialise() -> Util {
-    let a = Util {
-        Argon: initialise_argon(),
-    };
-
-    a
+/// The services `config` describes. Fails if one that is configured cannot
+/// be set up, such as an unreadable LTI key.
+pub fn initialise(config: &Config) -> Result<Util, String> {
+    let mailer = config
+        .smtp
+        .as_ref()
+        .map(|smtp| Mailer::new(smtp, &config.server.app_base_url))
+        .transpose()
+        .map_err(|error| format!("Email: {}", error))?;
+    let lti = config
+        .lti
+        .as_ref()
+        .map(Lti::from_config)
+        .transpose()
+        .map_err(|error| format!("LTI: {}", error))?;
+    Ok(Util {
+        argon: initialise_argon(),
+        gemini: config.gemini.clone(),
+        mailer,
+        oidc: config.oidc.as_ref().map(Oidc::new),
+        lti,
+        jwt_secret: match &config.server.jwt_secret {
+            Some(secret) => secret.clone().into_bytes(),
+            None => rand::random::<[u8; 32]>().to_vec(),
+        },
+    })
 }
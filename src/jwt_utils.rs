@@ -1,527 +1,150 @@
-// https://dev.to/behainguyen/rust-actix-web-json-web-token-authentication-2f1b
-// https://github.com/behai-nguyen/rust_web_01/blob/4b7c3acf8af1f18f99553e7a728f05d9493fb885/src/helper/jwt_utils.rs
+//! Session tokens, issued at sign-in and sent back as `Authorization: Bearer
+//! <token>`. They are JSON Web Tokens signed with HS256 and the server's
+//! `JWT_SECRET`, naming the user and their role.
 
-/* Date Created: 19/02/2024. */
+use std::future::{ready, Ready};
 
-//! JSON Web Token utilities using [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html) crate.
-//! Utilities in this module are implemented based on discussion in this [example 2](https://behai-nguyen.github.io/2023/11/19/rust-14-jsonwebtoken.html#the-second-example)
-//! of the above post.
-//!
-//! See also [Rust: JSON Web Token -- some investigative studies on crate jwt-simple](https://behai-nguyen.github.io/2023/11/17/rust-13-jwt-simple.html).
-//!
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpRequest};
 use jsonwebtoken::{
     decode, encode, errors::ErrorKind, get_current_timestamp, Algorithm, DecodingKey, EncodingKey,
     Header, Validation,
 };
 use serde::{Deserialize, Serialize};
 
-use actix_web::http::StatusCode;
+use crate::error::ApiError;
+use crate::initialiser::Util;
+use crate::model::UserType;
 
-use crate::bh_libs::api_status::ApiStatus;
-use crate::helper::messages::{TOKEN_EXPIRED_MSG, TOKEN_INVALID_MSG, TOKEN_OTHER_ERR_MSG};
+/// How long a session lasts before the user has to sign in again.
+pub const SESSION_SECS: u64 = 12 * 60 * 60;
 
-use crate::helper::constants::BEARER_TOKEN;
-
-/// This implementation JSON Web Token payload.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct JWTPayload {
-    /// Custom field. The logged in user email.
-    email: String,
-    /// Standard field. Its value stays fixed.
-    iat: u64,
-    /// Standard field. A required field. For this implementation, its value gets
-    /// updated to a new expiry everytime a user makes a request.
-    exp: u64,
-    /// Custom field. The last time this token was used for authentication. It's
-    /// seconds since epoch.
-    last_active: u64,
-}
-
-/// See [Rust: seconds since epoch -- “1970-01-01 00:00:00 UTC”](https://behai-nguyen.github.io/2023/11/12/rust-12-epoch-time.html)
-/// for discussion on seconds since epoch.
-///
-/// Without using [get_current_timestamp()](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/fn.get_current_timestamp.html),
-/// the equivalent implementation should be:
-///
-/// ```
-/// use std::time::{SystemTime, UNIX_EPOCH};
-/// use time::OffsetDateTime;
-///
-/// fn seconds_since_epoch() -> u64 {
-///     let offset_dt = OffsetDateTime::now_utc();
-///
-///     // This call will never fail!
-///     match SystemTime::from(offset_dt).duration_since(UNIX_EPOCH) {
-///         Ok(n) => n.as_secs(),
-///         Err(_) => unreachable!(), // ! Coerced to u64.
-///     }
-/// }
-/// ```
-fn seconds_since_epoch() -> u64 {
-    get_current_timestamp()
+    /// The signed-in user's id.
+    pub sub: String,
+    pub role: Option<UserType>,
+    pub iat: u64,
+    pub exp: u64,
 }
 
 impl JWTPayload {
-    /// Creates and returns a [`JWTPayload`] instance.
-    ///
-    /// # Arguments
-    ///
-    /// * `email` - email of the logged in user.
-    ///
-    /// * `secs_valid_for` - the duration in seconds in which this token is
-    ///   valid for.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance.
-    ///
-    pub fn new(email: &str, secs_valid_for: u64) -> Self {
-        let iat = seconds_since_epoch();
-
+    pub fn new(user_id: &str, role: Option<UserType>, secs_valid_for: u64) -> Self {
+        let iat = get_current_timestamp();
         Self {
-            email: String::from(email),
+            sub: user_id.to_string(),
+            role,
             iat,
             exp: iat + secs_valid_for,
-            last_active: iat,
         }
     }
-
-    /// Updates a [`JWTPayload`] instance expiry date and last active values.
-    ///
-    /// # Arguments
-    ///
-    ///   * `seconds` - the number of seconds to move the expiry date to since
-    ///      epoch.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance with expiry date and last active values updated.
-    ///
-    pub fn update_expiry_secs(mut self, seconds: u64) -> Self {
-        let sse = seconds_since_epoch();
-
-        self.exp = sse + seconds;
-        self.last_active = sse;
-        self
-    }
-
-    /// Updates a [`JWTPayload`] instance expiry date and last active values.
-    ///
-    /// # Arguments
-    ///
-    ///   * `mins` - the number of minutes to move the expiry date to since
-    ///      epoch.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance with expiry date and last active values updated.
-    ///
-    pub fn update_expiry_mins(mut self, mins: u64) -> Self {
-        let sse = seconds_since_epoch();
-
-        self.exp = sse + (mins * 60);
-        self.last_active = sse;
-        self
-    }
-
-    /// Updates a [`JWTPayload`] instance expiry date and last active values.
-    ///
-    /// # Arguments
-    ///
-    ///   * `hours` - the number of hours to move the expiry date to since
-    ///      epoch.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance with expiry date and last active values updated.
-    ///
-    pub fn update_expiry_hours(mut self, hours: u64) -> Self {
-        let sse = seconds_since_epoch();
-
-        self.exp = sse + (hours * 60 * 60);
-        self.last_active = sse;
-        self
-    }
-
-    /// Gets a [`JWTPayload`] instance email.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance email.
-    ///
-    pub fn email(&self) -> String {
-        self.email.clone()
-    }
-
-    /// Gets a [`JWTPayload`] instance issued at.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance issued at.
-    ///
-    pub fn issued_at(&self) -> u64 {
-        self.iat
-    }
-
-    /// Gets a [`JWTPayload`] instance expiry.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance expiry.
-    ///
-    pub fn expiry(&self) -> u64 {
-        self.exp
-    }
-
-    /// Gets a [`JWTPayload`] instance last active.
-    ///
-    /// # Return
-    ///
-    /// * [`JWTPayload`] instance last active.
-    ///
-    pub fn last_active(&self) -> u64 {
-        self.last_active
-    }
 }
 
-/// Create a [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html) token.
-/// For detail discussion, see [Rust: JSON Web Token -- some investigative studies on crate jsonwebtoken](https://behai-nguyen.github.io/2023/11/19/rust-14-jsonwebtoken.html).
-///
-/// # Arguments
-///
-/// * `email` - email of the logged in user.
-///
-/// * `secret_key` - [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html)
-///    secret key used to encode the token.
-///
-/// * `secs_valid_for` - the duration in seconds in which this token is valid for.
-///
-/// # Return
-///
-/// * A JSON Web Token.
-///
-pub fn make_token(email: &str, secret_key: &[u8], secs_valid_for: u64) -> String {
-    let payload = JWTPayload::new(email, secs_valid_for);
-
-    // This will create a JWT using HS256 as algorithm
+pub fn make_token(payload: &JWTPayload, secret_key: &[u8]) -> Result<String, String> {
     encode(
-        &Header::default(),
-        &payload,
+        &Header::new(Algorithm::HS256),
+        payload,
         &EncodingKey::from_secret(secret_key),
     )
-    .unwrap()
+    .map_err(|err| err.to_string())
 }
 
-/// Create a [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html) token.
-/// For detail discussion, see [Rust: JSON Web Token -- some investigative studies on crate jsonwebtoken](https://behai-nguyen.github.io/2023/11/19/rust-14-jsonwebtoken.html).
-///
-/// # Arguments
-///
-/// * `payload` - a valid [`JWTPayload`] instance which is the token payload.
-///
-/// * `secret_key` - [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html)
-///     secret key used to encode the token.
-///
-/// # Return
-///
-/// * A JSON Web Token.
-///
-pub fn make_token_from_payload(payload: &JWTPayload, secret_key: &[u8]) -> String {
-    // This will create a JWT using HS256 as algorithm
-    encode(
-        &Header::default(),
-        &payload,
-        &EncodingKey::from_secret(secret_key),
-    )
-    .unwrap()
-}
-
-/// Prepends [BEARER_TOKEN](`crate::helper::constants::BEARER_TOKEN`), i.e., ``Bearer.``,
-/// to an existing JSON Web Token.
-///
-/// # Reference
-///
-/// * <https://datatracker.ietf.org/doc/html/rfc6750>, section
-///   [2.1.  Authorization Request Header Field](https://datatracker.ietf.org/doc/html/rfc6750#page-5).
-///
-/// # Arguments
-///
-/// * `token` - a (valid) JSON Web Token.
-///
-/// # Return
-///
-///    * ``Bearer.`` + token
-///
-pub fn make_bearer_token(token: &str) -> String {
-    BEARER_TOKEN.to_owned() + token
-}
-
-/// Decodes a [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html) token.
-/// On successful, returns token payload [`JWTPayload`]. On failure, returns an instance of
-/// [ApiStatus](`crate::bh_libs::api_status::ApiStatus`).
-///
-/// # Arguments
-///
-/// * `token` - a JSON Web Token.
-///
-/// * `secret_key` - [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html)
-///     secret key used to encode the token.
-///
-/// # Return
-///
-/// * On successful, token payload [`JWTPayload`].
-///
-/// * On failure, an instance of [ApiStatus](`crate::bh_libs::api_status::ApiStatus`), field
-///   ``code`` set to [UNAUTHORIZED](https://docs.rs/actix-web/latest/actix_web/http/struct.StatusCode.html#associatedconstant.UNAUTHORIZED).
-///   Detects two specific [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html) errors
-///   [InvalidToken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/errors/enum.ErrorKind.html#variant.InvalidToken)
-///   and [ExpiredSignature](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/errors/enum.ErrorKind.html#variant.ExpiredSignature).
-///   All other errors are handled generically.
-///
-pub fn decode_token(token: &str, secret_key: &[u8]) -> Result<JWTPayload, ApiStatus> {
+/// The payload of `token` if this server signed it and it has not expired.
+pub fn decode_token(token: &str, secret_key: &[u8]) -> Result<JWTPayload, ApiError> {
     let mut validation = Validation::new(Algorithm::HS256);
-    // For the shake of simplicity, 0 would make leeway not having any effect
-    // on expiration calculations.
     validation.leeway = 0;
 
     match decode::<JWTPayload>(token, &DecodingKey::from_secret(secret_key), &validation) {
-        Ok(x) => Ok(x.claims),
-
-        Err(err) => {
-            match *err.kind() {
-                ErrorKind::InvalidToken => Err(ApiStatus::new(StatusCode::UNAUTHORIZED.as_u16())
-                    .set_message(TOKEN_INVALID_MSG)),
-                ErrorKind::ExpiredSignature => {
-                    Err(ApiStatus::new(StatusCode::UNAUTHORIZED.as_u16())
-                        .set_message(TOKEN_EXPIRED_MSG))
-                }
-                _ => Err(ApiStatus::new(StatusCode::UNAUTHORIZED.as_u16())
-                    .set_message(TOKEN_OTHER_ERR_MSG)),
-            }
-        }
+        Ok(s) => Ok(s.claims),
+        Err(err) => match err.kind() {
+            ErrorKind::ExpiredSignature => Err(ApiError::Unauthorized(
+                "Session has expired; sign in again".to_string(),
+            )),
+            _ => Err(ApiError::Unauthorized(
+                "Session token is invalid".to_string(),
+            )),
+        },
     }
 }
 
-/// Decode a token with a prepended [BEARER_TOKEN](`crate::helper::constants::BEARER_TOKEN`),
-/// i.e., ``Bearer.``, prefix. For example,
-/// ``Bearer.eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzI1NiJ9.e ... 0.5t1Gayvk74defCHrHkjJP-mFsNgFdY6yka9j6NZOm3g``.
-///
-/// # Reference
-///
-/// * <https://datatracker.ietf.org/doc/html/rfc6750>, section
-///   [2.1.  Authorization Request Header Field](https://datatracker.ietf.org/doc/html/rfc6750#page-5).
-///
-/// # Arguments
-///
-/// * `token` - a (valid) JSON Web Token with prefix ``Bearer.``.
-///
-/// * `secret_key` - [jsonwebtoken](https://docs.rs/jsonwebtoken/latest/jsonwebtoken/index.html)
-///    secret key used to encode the token.
-///
-/// # How It Works and Return Value
-///
-/// * Remove prefix ``Bearer.`` from token.
-///
-///  * Calls [decode_token()](`decode_token`) to do the work.
-///
-pub fn decode_bearer_token(token: &str, secret_key: &[u8]) -> Result<JWTPayload, ApiStatus> {
-    decode_token(&token.split_at(7).1.to_string(), secret_key)
+/// The token in an `Authorization: Bearer <token>` header, if it carries one.
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then_some(credentials.trim())
+        .filter(|s| !s.is_empty())
 }
 
-/// To run these tests below:
-///
-///    * cargo test helper::jwt_utils::tests
-///
-/// To run a specific test method:
-///
-///    * cargo test helper::jwt_utils::tests::test_update_expiry_secs -- --exact
-///    * cargo test helper::jwt_utils::tests::test_update_expiry_mins -- --exact
-///    * cargo test helper::jwt_utils::tests::test_update_expiry_hours -- --exact
-///    * cargo test helper::jwt_utils::tests::test_make_token -- --exact
-///    * cargo test helper::jwt_utils::tests::test_make_token_from_payload -- --exact
-///    * cargo test helper::jwt_utils::tests::test_decode_token_valid -- --exact
-///    * cargo test helper::jwt_utils::tests::test_decode_bearer_token_valid -- --exact
-///    * cargo test helper::jwt_utils::tests::test_decode_token_expired -- --exact
-///    * cargo test helper::jwt_utils::tests::test_decode_token_invalid -- --exact
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::Config;
-    use dotenv::dotenv;
-    use std;
-
-    #[test]
-    fn test_update_expiry_secs() {
-        let mut jwt_pay_load = JWTPayload::new("behai_nguyen@hotmail.com", 45);
-
-        // Wait for two seconds.
-        let sleep_time = std::time::Duration::from_secs(2);
-        std::thread::sleep(sleep_time);
+/// The signed-in user making a request, from their session token.
+pub struct Session(pub JWTPayload);
 
-        let sec_since_epoch = seconds_since_epoch();
-
-        jwt_pay_load = jwt_pay_load.update_expiry_secs(30);
-
-        assert_eq!((jwt_pay_load.expiry() - sec_since_epoch) <= 30, true);
-        assert_eq!(jwt_pay_load.last_active() >= sec_since_epoch, true);
+impl Session {
+    pub fn user_id(&self) -> &str {
+        &self.0.sub
     }
+}
 
-    #[test]
-    fn test_update_expiry_mins() {
-        let mut jwt_pay_load = JWTPayload::new("behai_nguyen@hotmail.com", 45);
-
-        // Wait for two seconds.
-        let sleep_time = std::time::Duration::from_secs(2);
-        std::thread::sleep(sleep_time);
-
-        let sec_since_epoch = seconds_since_epoch();
-
-        jwt_pay_load = jwt_pay_load.update_expiry_mins(1);
+impl FromRequest for Session {
+    type Error = ApiError;
+    type Future = Ready<Result<Session, ApiError>>;
 
-        assert_eq!((jwt_pay_load.expiry() - sec_since_epoch) <= 60, true);
-        assert_eq!(jwt_pay_load.last_active() >= sec_since_epoch, true);
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(session(req))
     }
+}
 
-    #[test]
-    fn test_update_expiry_hours() {
-        let mut jwt_pay_load = JWTPayload::new("behai_nguyen@hotmail.com", 45);
-
-        // Wait for two seconds.
-        let sleep_time = std::time::Duration::from_secs(2);
-        std::thread::sleep(sleep_time);
-
-        let sec_since_epoch = seconds_since_epoch();
+fn session(req: &HttpRequest) -> Result<Session, ApiError> {
+    let Some(util) = req.app_data::<Data<Util>>() else {
+        return Err(ApiError::Internal("Server is not initialised".to_string()));
+    };
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(bearer_token)
+        .ok_or_else(|| ApiError::Unauthorized("Sign in first".to_string()))?;
+    decode_token(token, &util.jwt_secret).map(Session)
+}
 
-        jwt_pay_load = jwt_pay_load.update_expiry_hours(1);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        assert_eq!((jwt_pay_load.expiry() - sec_since_epoch) <= 3600, true);
-        assert_eq!(jwt_pay_load.last_active() >= sec_since_epoch, true);
-    }
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
 
     #[test]
-    fn test_make_token() {
-        dotenv().ok();
-        let config = Config::init();
-
-        let email = "behai_nguyen@hotmail.com";
-
-        let token = make_token(
-            email,
-            config.jwt_secret_key.as_ref(),
-            config.jwt_mins_valid_for * 60,
+    fn test_token_round_trip() {
+        let payload = JWTPayload::new("u1", Some(UserType::Faculty), SESSION_SECS);
+        let token = make_token(&payload, SECRET).unwrap();
+        assert_eq!(decode_token(&token, SECRET).unwrap(), payload);
+        assert!(decode_token(&token, b"another secret").is_err());
+        assert_eq!(
+            bearer_token(&format!("Bearer {}", token)),
+            Some(token.as_str())
         );
-        assert_eq!(token.len() > 0, true);
-
-        let jwt_pay_load = match decode::<JWTPayload>(
-            &token,
-            &DecodingKey::from_secret(config.jwt_secret_key.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        ) {
-            Ok(x) => x.claims,
-            Err(_) => panic!("Token decoded failed."),
-        };
-
-        assert_eq!(jwt_pay_load.email(), email);
-    }
-
-    #[test]
-    fn test_make_token_from_payload() {
-        dotenv().ok();
-        let config = Config::init();
-
-        let email = "behai_nguyen@hotmail.com";
-
-        let jwt_pay_load = JWTPayload::new(email, 45);
-
-        let token = make_token_from_payload(&jwt_pay_load, config.jwt_secret_key.as_ref());
-        assert_eq!(token.len() > 0, true);
-
-        let jwt_pay_load1 = match decode::<JWTPayload>(
-            &token,
-            &DecodingKey::from_secret(config.jwt_secret_key.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        ) {
-            Ok(x) => x.claims,
-            Err(_) => panic!("Token decoded failed."),
-        };
-
-        assert_eq!(jwt_pay_load1.email(), email);
-    }
-
-    #[test]
-    fn test_decode_token_valid() {
-        dotenv().ok();
-        let config = Config::init();
-
-        let email = "behai_nguyen@hotmail.com";
-
-        let token = make_token(email, config.jwt_secret_key.as_ref(), 5);
-        assert_eq!(token.len() > 0, true);
-
-        let res = decode_token(&token, config.jwt_secret_key.as_ref());
-        // Token should be decoded successfully.
-        assert_eq!(res.is_ok(), true);
-        let jwt_pay_load = res.unwrap();
-
-        assert_eq!(jwt_pay_load.email(), email);
-    }
-
-    #[test]
-    fn test_decode_bearer_token_valid() {
-        dotenv().ok();
-        let config = Config::init();
-
-        let email = "behai_nguyen@hotmail.com";
-
-        let token = make_bearer_token(&make_token(email, config.jwt_secret_key.as_ref(), 5));
-        assert_eq!(token.len() > 0, true);
-
-        let res = decode_bearer_token(&token, config.jwt_secret_key.as_ref());
-        // Token should be decoded successfully.
-        assert_eq!(res.is_ok(), true);
-        let jwt_pay_load = res.unwrap();
-
-        assert_eq!(jwt_pay_load.email(), email);
     }
 
     #[test]
-    fn test_decode_token_expired() {
-        dotenv().ok();
-        let config = Config::init();
-
-        let email = "behai_nguyen@hotmail.com";
-
-        let token = make_token(email, config.jwt_secret_key.as_ref(), 5);
-        assert_eq!(token.len() > 0, true);
-
-        // Wait until the token expired.
-        let sleep_time = std::time::Duration::from_secs(7);
-        std::thread::sleep(sleep_time);
-
-        let res = decode_token(&token, config.jwt_secret_key.as_ref());
-
-        // Token decoded results in error.
-        assert_eq!(res.is_err(), true);
-
-        let api_status = res.err().unwrap();
-        assert_eq!(api_status.get_code(), StatusCode::UNAUTHORIZED.as_u16());
-        assert_eq!(api_status.get_message().unwrap(), TOKEN_EXPIRED_MSG);
+    fn test_expired_token() {
+        let mut payload = JWTPayload::new("u1", None, SESSION_SECS);
+        payload.exp = payload.iat - 1;
+        let token = make_token(&payload, SECRET).unwrap();
+        let error = decode_token(&token, SECRET).unwrap_err();
+        assert_eq!(error.message(), "Session has expired; sign in again");
     }
 
     #[test]
-    fn test_decode_token_invalid() {
-        dotenv().ok();
-        let config = Config::init();
-
-        let token = "behai_nguyen@hotmail.com";
-
-        let res = decode_token(&token, config.jwt_secret_key.as_ref());
-
-        // Token decoded results in error.
-        assert_eq!(res.is_err(), true);
-
-        let api_status = res.err().unwrap();
-        assert_eq!(api_status.get_code(), StatusCode::UNAUTHORIZED.as_u16());
-        assert_eq!(api_status.get_message().unwrap(), TOKEN_INVALID_MSG);
+    fn test_other_algorithms_are_refused() {
+        let payload = JWTPayload::new("u1", Some(UserType::Admin), SESSION_SECS);
+        let token = encode(
+            &Header::new(Algorithm::HS512),
+            &payload,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap();
+        assert!(decode_token(&token, SECRET).is_err());
     }
 }
@@ -0,0 +1,647 @@
+//! LTI 1.3 tool for launching quizzes from an LMS. The platform starts an
+//! OIDC third-party login, the launch's `id_token` is checked against the
+//! platform's JWKS, deep linking lets instructors place a quiz in their
+//! course, and grades go back through Assignment and Grade Services.
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::LtiConfig;
+use crate::model::UserType;
+use crate::oidc::{fresh, get_json, random_secret, verify_jwt, IdClaims, UNKNOWN_KEY};
+
+const LTI_VERSION: &str = "1.3.0";
+pub const RESOURCE_LINK_REQUEST: &str = "LtiResourceLinkRequest";
+pub const DEEP_LINKING_REQUEST: &str = "LtiDeepLinkingRequest";
+const SCORE_SCOPE: &str = "https://purl.imsglobal.org/spec/lti-ags/scope/score";
+
+/// The LMS the tool is registered with.
+#[derive(Clone)]
+struct Platform {
+    issuer: String,
+    client_id: String,
+    deployment_ids: Vec<String>,
+    auth_login_url: String,
+    auth_token_url: String,
+    jwks_url: String,
+}
+
+#[derive(Clone)]
+pub struct Lti {
+    platform: Platform,
+    /// Where the tool's `/api` routes are served, as the platform reaches them.
+    tool_url: String,
+    key: EncodingKey,
+    key_id: String,
+    public_jwk: Value,
+    http: reqwest::Client,
+    /// The platform's keys and when they were fetched.
+    platform_jwks: Arc<Mutex<Option<(JwkSet, Instant)>>>,
+}
+
+/// The platform's request to start a launch, by query or form.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoginInitiation {
+    pub iss: String,
+    pub login_hint: String,
+    pub target_link_uri: String,
+    pub lti_message_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub lti_deployment_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResourceLinkClaim {
+    pub id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ContextClaim {
+    pub id: String,
+}
+
+/// Where the platform's gradebook accepts scores for the launch.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AgsEndpoint {
+    #[serde(default)]
+    pub scope: Vec<String>,
+    #[serde(default)]
+    pub lineitem: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeepLinkingSettings {
+    pub deep_link_return_url: String,
+    #[serde(default)]
+    pub data: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LaunchClaims {
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/message_type")]
+    pub message_type: String,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/version")]
+    pub version: String,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/deployment_id")]
+    pub deployment_id: String,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/roles", default)]
+    pub roles: Vec<String>,
+    #[serde(
+        rename = "https://purl.imsglobal.org/spec/lti/claim/resource_link",
+        default
+    )]
+    pub resource_link: Option<ResourceLinkClaim>,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/context", default)]
+    pub context: Option<ContextClaim>,
+    #[serde(rename = "https://purl.imsglobal.org/spec/lti/claim/custom", default)]
+    pub custom: serde_json::Map<String, Value>,
+    #[serde(
+        rename = "https://purl.imsglobal.org/spec/lti-ags/claim/endpoint",
+        default
+    )]
+    pub ags: Option<AgsEndpoint>,
+    #[serde(
+        rename = "https://purl.imsglobal.org/spec/lti-dl/claim/deep_linking_settings",
+        default
+    )]
+    pub deep_linking: Option<DeepLinkingSettings>,
+    /// `sub`, `nonce`, name and email, as for single sign-on.
+    #[serde(flatten)]
+    pub user: IdClaims,
+}
+
+impl LaunchClaims {
+    /// The quiz a resource link was created for by deep linking.
+    pub fn quiz_id(&self) -> Option<&str> {
+        self.custom.get("quiz_id").and_then(Value::as_str)
+    }
+
+    /// The gradebook column scores for this link go to, if the platform
+    /// lets the tool post scores.
+    pub fn score_lineitem(&self) -> Option<&str> {
+        self.ags
+            .as_ref()
+            .filter(|ags| ags.scope.iter().any(|s| s == SCORE_SCOPE))
+            .and_then(|ags| ags.lineitem.as_deref())
+    }
+
+    /// Instructors and course designers manage quizzes; everyone else, like
+    /// teaching assistants and learners, takes them.
+    pub fn role(&self) -> UserType {
+        let faculty = self.roles.iter().any(|role| {
+            let name = role.rsplit(['#', '/']).next().unwrap_or(role);
+            matches!(
+                name,
+                "Instructor" | "ContentDeveloper" | "Administrator" | "Faculty"
+            )
+        });
+        match faculty {
+            true => UserType::Faculty,
+            false => UserType::Student,
+        }
+    }
+}
+
+/// A score for one user, in the AGS score format.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Score {
+    pub user_id: String,
+    pub score_given: f64,
+    pub score_maximum: f64,
+    pub activity_progress: &'static str,
+    pub grading_progress: &'static str,
+    pub timestamp: String,
+}
+
+impl Score {
+    pub fn final_grade(user_id: String, given: f64, maximum: f64) -> Score {
+        Score {
+            user_id,
+            score_given: given.clamp(0.0, maximum),
+            score_maximum: maximum,
+            activity_progress: "Completed",
+            grading_progress: "FullyGraded",
+            timestamp: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+}
+
+/// The public half of the tool's RSA key as a JWK.
+fn public_jwk(pem: &str, key_id: &str) -> Result<Value, String> {
+    let key = RsaPrivateKey::from_pkcs8_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+        .map_err(|err| format!("LTI private key is not an RSA key in PEM: {}", err))?;
+    Ok(json!({
+        "kty": "RSA",
+        "alg": "RS256",
+        "use": "sig",
+        "kid": key_id,
+        "n": URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+        "e": URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+    }))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl Lti {
+    /// The tool for `config`, signing with the key in its private key file.
+    pub fn from_config(config: &LtiConfig) -> Result<Lti, String> {
+        let pem = fs::read_to_string(&config.private_key_file)
+            .map_err(|err| format!("Could not read {}: {}", config.private_key_file, err))?;
+        let platform = Platform {
+            issuer: config.issuer.clone(),
+            client_id: config.client_id.clone(),
+            deployment_ids: config.deployment_ids.clone(),
+            auth_login_url: config.auth_login_url.clone(),
+            auth_token_url: config.auth_token_url.clone(),
+            jwks_url: config.jwks_url.clone(),
+        };
+        Lti::new(platform, &config.tool_url, &pem, config.key_id.clone())
+    }
+
+    fn new(platform: Platform, tool_url: &str, pem: &str, key_id: String) -> Result<Lti, String> {
+        Ok(Lti {
+            platform,
+            tool_url: tool_url.trim_end_matches('/').to_string(),
+            key: EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|err| err.to_string())?,
+            public_jwk: public_jwk(pem, &key_id)?,
+            key_id,
+            http: reqwest::Client::new(),
+            platform_jwks: Arc::default(),
+        })
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.platform.issuer
+    }
+
+    pub fn launch_url(&self) -> String {
+        format!("{}/lti/launch", self.tool_url)
+    }
+
+    /// The tool's keys, which the platform checks deep linking responses
+    /// and grade service requests against.
+    pub fn jwks(&self) -> Value {
+        json!({ "keys": [self.public_jwk] })
+    }
+
+    /// Where to send the browser to finish the platform's login, after
+    /// checking the request comes from the registered platform.
+    pub fn login_redirect(
+        &self,
+        init: &LoginInitiation,
+        state: &str,
+        nonce: &str,
+    ) -> Result<String, String> {
+        if init.iss != self.platform.issuer {
+            return Err(format!("Unknown platform {}", init.iss));
+        }
+        if init
+            .client_id
+            .as_ref()
+            .is_some_and(|c| *c != self.platform.client_id)
+        {
+            return Err("Unknown client id".to_string());
+        }
+        if init
+            .lti_deployment_id
+            .as_ref()
+            .is_some_and(|d| !self.platform.deployment_ids.contains(d))
+        {
+            return Err("Unknown deployment".to_string());
+        }
+        if !init.target_link_uri.starts_with(&self.tool_url) {
+            return Err("Launch is not for this tool".to_string());
+        }
+        let launch_url = self.launch_url();
+        let mut params = vec![
+            ("scope", "openid"),
+            ("response_type", "id_token"),
+            ("response_mode", "form_post"),
+            ("prompt", "none"),
+            ("client_id", &self.platform.client_id),
+            ("redirect_uri", &launch_url),
+            ("login_hint", &init.login_hint),
+            ("state", state),
+            ("nonce", nonce),
+        ];
+        if let Some(hint) = &init.lti_message_hint {
+            params.push(("lti_message_hint", hint));
+        }
+        reqwest::Url::parse_with_params(&self.platform.auth_login_url, &params)
+            .map(|url| url.to_string())
+            .map_err(|err| err.to_string())
+    }
+
+    /// The verified claims of a launch started with `nonce`.
+    pub async fn validate_launch(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<LaunchClaims, String> {
+        match self.check_launch(id_token, &self.platform_keys(false).await?, nonce) {
+            Err(error) if error == UNKNOWN_KEY => {
+                self.check_launch(id_token, &self.platform_keys(true).await?, nonce)
+            }
+            checked => checked,
+        }
+    }
+
+    /// The platform's signing keys, fetched again if `refresh` or stale.
+    async fn platform_keys(&self, refresh: bool) -> Result<JwkSet, String> {
+        if !refresh {
+            if let Some(jwks) = fresh(&self.platform_jwks.lock().unwrap()) {
+                return Ok(jwks);
+            }
+        }
+        let jwks: JwkSet = get_json(&self.http, &self.platform.jwks_url).await?;
+        *self.platform_jwks.lock().unwrap() = Some((jwks.clone(), Instant::now()));
+        Ok(jwks)
+    }
+
+    fn check_launch(
+        &self,
+        id_token: &str,
+        jwks: &JwkSet,
+        nonce: &str,
+    ) -> Result<LaunchClaims, String> {
+        let claims: LaunchClaims = verify_jwt(
+            id_token,
+            jwks,
+            &self.platform.issuer,
+            &self.platform.client_id,
+        )?;
+        if claims.user.nonce.as_deref() != Some(nonce) {
+            return Err("Launch nonce does not match the login".to_string());
+        }
+        if claims.version != LTI_VERSION {
+            return Err(format!("Unsupported LTI version {}", claims.version));
+        }
+        if !self.platform.deployment_ids.contains(&claims.deployment_id) {
+            return Err(format!("Unknown deployment {}", claims.deployment_id));
+        }
+        Ok(claims)
+    }
+
+    fn sign(&self, claims: &Value) -> Result<String, String> {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.key_id.clone());
+        encode(&header, claims, &self.key).map_err(|err| err.to_string())
+    }
+
+    /// A page that posts a resource link for `quiz_id` back to the platform,
+    /// with a gradebook column out of `max_score`.
+    pub fn deep_linking_response(
+        &self,
+        settings: &DeepLinkingSettings,
+        deployment_id: &str,
+        quiz_id: &str,
+        title: &str,
+        max_score: f64,
+    ) -> Result<String, String> {
+        let now = Utc::now().timestamp();
+        let mut claims = json!({
+            "iss": self.platform.client_id,
+            "aud": self.platform.issuer,
+            "iat": now,
+            "exp": now + 300,
+            "nonce": random_secret(),
+            "https://purl.imsglobal.org/spec/lti/claim/message_type": "LtiDeepLinkingResponse",
+            "https://purl.imsglobal.org/spec/lti/claim/version": LTI_VERSION,
+            "https://purl.imsglobal.org/spec/lti/claim/deployment_id": deployment_id,
+            "https://purl.imsglobal.org/spec/lti-dl/claim/content_items": [{
+                "type": "ltiResourceLink",
+                "title": title,
+                "url": self.launch_url(),
+                "custom": { "quiz_id": quiz_id },
+                "lineItem": {
+                    "scoreMaximum": max_score,
+                    "label": title,
+                    "resourceId": quiz_id,
+                },
+            }],
+        });
+        if let Some(data) = &settings.data {
+            claims["https://purl.imsglobal.org/spec/lti-dl/claim/data"] = json!(data);
+        }
+        let jwt = self.sign(&claims)?;
+        Ok(format!(
+            "<!DOCTYPE html>\n<html><body onload=\"document.forms[0].submit()\">\
+             <form method=\"post\" action=\"{}\">\
+             <input type=\"hidden\" name=\"JWT\" value=\"{}\">\
+             <noscript><button type=\"submit\">Continue</button></noscript>\
+             </form></body></html>\n",
+            escape_html(&settings.deep_link_return_url),
+            escape_html(&jwt)
+        ))
+    }
+
+    /// A grade service token, asked for with a JWT signed by the tool.
+    pub async fn score_token(&self) -> Result<String, String> {
+        let now = Utc::now().timestamp();
+        let assertion = self.sign(&json!({
+            "iss": self.platform.client_id,
+            "sub": self.platform.client_id,
+            "aud": self.platform.auth_token_url,
+            "iat": now,
+            "exp": now + 300,
+            "jti": random_secret(),
+        }))?;
+        let form = [
+            ("grant_type", "client_credentials"),
+            (
+                "client_assertion_type",
+                "urn:ietf:params:oauth:client-assertion-type:jwt-bearer",
+            ),
+            ("client_assertion", &assertion),
+            ("scope", SCORE_SCOPE),
+        ];
+        let resp = self
+            .http
+            .post(&self.platform.auth_token_url)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("Token request failed with {}", resp.status()));
+        }
+        let token: AccessToken = resp.json().await.map_err(|err| err.to_string())?;
+        Ok(token.access_token)
+    }
+
+    /// Posts a score to a line item, i.e. a gradebook column.
+    pub async fn post_score(
+        &self,
+        token: &str,
+        lineitem: &str,
+        score: &Score,
+    ) -> Result<(), String> {
+        // Scores go to the line item's `/scores`, ahead of any query string.
+        let url = match lineitem.split_once('?') {
+            Some((path, query)) => format!("{}/scores?{}", path, query),
+            None => format!("{}/scores", lineitem),
+        };
+        let resp = self
+            .http
+            .post(url)
+            .bearer_auth(token)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/vnd.ims.lis.v1.score+json",
+            )
+            .json(score)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        match resp.status().is_success() {
+            true => Ok(()),
+            false => Err(format!("Gradebook answered {}", resp.status())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+    use std::sync::OnceLock;
+
+    /// A throwaway key, made once per test run so none is kept in the repo.
+    fn test_key() -> &'static str {
+        static KEY: OnceLock<String> = OnceLock::new();
+        KEY.get_or_init(|| {
+            let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+            key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string()
+        })
+    }
+
+    fn tool() -> Lti {
+        let platform = Platform {
+            issuer: "https://lms.example.edu".to_string(),
+            client_id: "quiz-tool".to_string(),
+            deployment_ids: vec!["1".to_string()],
+            auth_login_url: "https://lms.example.edu/auth".to_string(),
+            auth_token_url: "https://lms.example.edu/token".to_string(),
+            jwks_url: "https://lms.example.edu/jwks".to_string(),
+        };
+        Lti::new(
+            platform,
+            "http://localhost:8000/api/",
+            test_key(),
+            "k1".to_string(),
+        )
+        .unwrap()
+    }
+
+    fn jwks(lti: &Lti) -> JwkSet {
+        serde_json::from_value(lti.jwks()).unwrap()
+    }
+
+    /// A launch as the platform would sign it; the tests sign it with the
+    /// tool's own key and so check it against the tool's JWKS.
+    fn launch(lti: &Lti, overrides: Value) -> String {
+        let now = Utc::now().timestamp();
+        let mut claims = json!({
+            "iss": "https://lms.example.edu",
+            "aud": "quiz-tool",
+            "iat": now,
+            "exp": now + 60,
+            "sub": "lms-user-7",
+            "nonce": "n1",
+            "name": "Ada Lovelace",
+            "https://purl.imsglobal.org/spec/lti/claim/message_type": RESOURCE_LINK_REQUEST,
+            "https://purl.imsglobal.org/spec/lti/claim/version": "1.3.0",
+            "https://purl.imsglobal.org/spec/lti/claim/deployment_id": "1",
+            "https://purl.imsglobal.org/spec/lti/claim/roles": [
+                "http://purl.imsglobal.org/vocab/lis/v2/membership#Learner"
+            ],
+            "https://purl.imsglobal.org/spec/lti/claim/resource_link": { "id": "rl-1" },
+            "https://purl.imsglobal.org/spec/lti/claim/custom": { "quiz_id": "q-1" },
+        });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(overrides.as_object().unwrap().clone());
+        lti.sign(&claims).unwrap()
+    }
+
+    #[test]
+    fn test_login_redirect() {
+        let lti = tool();
+        let mut init = LoginInitiation {
+            iss: "https://lms.example.edu".to_string(),
+            login_hint: "7".to_string(),
+            target_link_uri: lti.launch_url(),
+            lti_message_hint: Some("hint".to_string()),
+            client_id: Some("quiz-tool".to_string()),
+            lti_deployment_id: None,
+        };
+        let url = lti.login_redirect(&init, "s1", "n1").unwrap();
+        assert!(url.starts_with("https://lms.example.edu/auth?scope=openid&"));
+        assert!(url.contains("response_mode=form_post"));
+        assert!(url.contains("redirect_uri=http%3A%2F%2Flocalhost%3A8000%2Fapi%2Flti%2Flaunch"));
+        assert!(url.contains("lti_message_hint=hint"));
+
+        init.target_link_uri = "https://elsewhere.example.edu/launch".to_string();
+        assert!(lti.login_redirect(&init, "s1", "n1").is_err());
+        init.iss = "https://elsewhere.example.edu".to_string();
+        assert!(lti.login_redirect(&init, "s1", "n1").is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_launch_uses_cached_platform_keys() {
+        let lti = tool();
+        *lti.platform_jwks.lock().unwrap() = Some((jwks(&lti), Instant::now()));
+        let claims = lti
+            .validate_launch(&launch(&lti, json!({})), "n1")
+            .await
+            .unwrap();
+        assert_eq!(claims.user.sub, "lms-user-7");
+    }
+
+    #[test]
+    fn test_check_launch() {
+        let lti = tool();
+        let claims = lti
+            .check_launch(&launch(&lti, json!({})), &jwks(&lti), "n1")
+            .unwrap();
+        assert_eq!(claims.user.sub, "lms-user-7");
+        assert_eq!(claims.message_type, RESOURCE_LINK_REQUEST);
+        assert_eq!(claims.quiz_id(), Some("q-1"));
+        assert_eq!(claims.role(), UserType::Student);
+        assert_eq!(claims.resource_link.unwrap().id, "rl-1");
+
+        assert!(lti
+            .check_launch(&launch(&lti, json!({})), &jwks(&lti), "n2")
+            .is_err());
+        let other_deployment = json!({
+            "https://purl.imsglobal.org/spec/lti/claim/deployment_id": "2"
+        });
+        let token = launch(&lti, other_deployment);
+        assert!(lti.check_launch(&token, &jwks(&lti), "n1").is_err());
+    }
+
+    #[test]
+    fn test_instructor_role() {
+        let lti = tool();
+        let roles = json!({ "https://purl.imsglobal.org/spec/lti/claim/roles": [
+            "http://purl.imsglobal.org/vocab/lis/v2/membership#Instructor"
+        ] });
+        let claims = lti
+            .check_launch(&launch(&lti, roles), &jwks(&lti), "n1")
+            .unwrap();
+        assert_eq!(claims.role(), UserType::Faculty);
+
+        let assistant = json!({ "https://purl.imsglobal.org/spec/lti/claim/roles": [
+            "http://purl.imsglobal.org/vocab/lis/v2/membership/Instructor#TeachingAssistant"
+        ] });
+        let claims = lti
+            .check_launch(&launch(&lti, assistant), &jwks(&lti), "n1")
+            .unwrap();
+        assert_eq!(claims.role(), UserType::Student);
+    }
+
+    #[test]
+    fn test_deep_linking_response() {
+        let lti = tool();
+        let settings = DeepLinkingSettings {
+            deep_link_return_url: "https://lms.example.edu/dl?a=1&b=2".to_string(),
+            data: Some("opaque".to_string()),
+        };
+        let page = lti
+            .deep_linking_response(&settings, "1", "q-1", "Week 1", 10.0)
+            .unwrap();
+        assert!(page.contains("action=\"https://lms.example.edu/dl?a=1&amp;b=2\""));
+
+        let jwt = page.split("name=\"JWT\" value=\"").nth(1).unwrap();
+        let jwt = &jwt[..jwt.find('"').unwrap()];
+        let jwk = &jwks(&lti).keys[0];
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&["https://lms.example.edu"]);
+        let claims = decode::<Value>(jwt, &DecodingKey::from_jwk(jwk).unwrap(), &validation)
+            .unwrap()
+            .claims;
+        let item = &claims["https://purl.imsglobal.org/spec/lti-dl/claim/content_items"][0];
+        assert_eq!(item["custom"]["quiz_id"], "q-1");
+        assert_eq!(item["lineItem"]["scoreMaximum"], 10.0);
+        assert_eq!(
+            claims["https://purl.imsglobal.org/spec/lti-dl/claim/data"],
+            "opaque"
+        );
+    }
+
+    #[test]
+    fn test_score_is_clamped() {
+        let score = Score::final_grade("u".to_string(), -2.0, 10.0);
+        assert_eq!(score.score_given, 0.0);
+        let score = serde_json::to_value(Score::final_grade("u".to_string(), 7.5, 10.0)).unwrap();
+        assert_eq!(score["scoreGiven"], 7.5);
+        assert_eq!(score["gradingProgress"], "FullyGraded");
+    }
+}
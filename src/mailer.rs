@@ -0,0 +1,119 @@
+//! Outgoing account email over SMTP. A local catcher such as MailHog works
+//! with `SMTP_HOST=localhost SMTP_PORT=1025 SMTP_TLS=none`.
+
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::{SmtpConfig, SmtpTls};
+
+#[derive(Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    /// Where the frontend is served; links in emails point there.
+    base_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mailer {
+    /// The mailer for `smtp`, with links pointing at the frontend at
+    /// `base_url`.
+    pub fn new(smtp: &SmtpConfig, base_url: &str) -> Result<Mailer, String> {
+        let mut builder = match smtp.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)
+                .map_err(|err| err.to_string())?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)
+                .map_err(|err| err.to_string())?,
+        };
+        if let Some(port) = smtp.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        let from = smtp
+            .from
+            .parse()
+            .map_err(|err: lettre::address::AddressError| format!("MAIL_FROM: {}", err))?;
+
+        Ok(Mailer {
+            transport: builder.build(),
+            from,
+            base_url: base_url.to_string(),
+        })
+    }
+
+    /// A frontend link carrying `token`, e.g. `reset-password`.
+    pub fn link(&self, page: &str, token: &str) -> String {
+        format!("{}/{}?token={}", self.base_url, page, token)
+    }
+
+    pub async fn send(&self, to: &str, email: Email) -> Result<(), String> {
+        let to: Mailbox = to
+            .parse()
+            .map_err(|err: lettre::address::AddressError| err.to_string())?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body)
+            .map_err(|err| err.to_string())?;
+        match self.transport.send(message).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+pub fn password_reset_email(username: &str, link: &str, minutes: i64) -> Email {
+    Email {
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+             Someone asked to reset the password for your account. To choose a new one, open\n\n\
+             {}\n\n\
+             The link works once and expires in {} minutes. If you did not ask for this, you can \
+             ignore this email.\n",
+            username, link, minutes
+        ),
+    }
+}
+
+pub fn verification_email(username: &str, link: &str, hours: i64) -> Email {
+    Email {
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+             Please confirm this is your email address by opening\n\n\
+             {}\n\n\
+             The link works once and expires in {} hours.\n",
+            username, link, hours
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emails_carry_link() {
+        let link = "http://localhost:3000/reset-password?token=abc";
+        let email = password_reset_email("ada", link, 60);
+        assert!(email.body.contains(link));
+        assert!(email.body.contains("60 minutes"));
+
+        let email = verification_email("ada", link, 24);
+        assert!(email.body.starts_with("Hello ada,"));
+        assert!(email.body.contains("24 hours"));
+    }
+}
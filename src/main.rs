@@ -1,8 +1,32 @@
+mod api_keys;
+mod bubble_sheet;
+mod cloze;
+mod config;
+mod deck_io;
+mod error;
+mod exam_pdf;
+mod grading;
 mod handler;
 mod helpers;
 mod initialiser;
+mod jwt_utils;
+mod lti;
+mod mailer;
 mod model;
+mod oidc;
+mod omr;
+mod quiz_export;
+mod quiz_import;
+mod roster;
+mod scheduler;
+mod throttle;
+mod tokens;
+mod two_factor;
+mod validation;
+mod variant;
 
+use crate::config::Config;
+use crate::helpers::{bootstrap_admin, ensure_indexes, sweep_expired_attempts};
 use crate::initialiser::initialise;
 
 use actix_cors::Cors;
@@ -11,25 +35,61 @@ use actix_web::{http::header, middleware::Logger, web, web::Data, App, HttpServe
 extern crate mongodb;
 use mongodb::{options::ClientOptions, Client};
 
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let util = Data::new(initialise());
-    let uri = std::env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".into());
-    let client_options = ClientOptions::parse(uri).await.unwrap();
-    let client = Client::with_options(client_options).expect("failed to connect");
-    let db = client.database("LEANLEARN");
+    let config = match Config::load() {
+        Ok(s) => s,
+        Err(errors) => {
+            let lines: Vec<String> = errors.iter().map(|e| format!("  - {}", e)).collect();
+            exit_with(&format!("Invalid configuration:\n{}", lines.join("\n")))
+        }
+    };
+    let util = match initialise(&config) {
+        Ok(s) => Data::new(s),
+        Err(error) => exit_with(&format!("Could not start: {}", error)),
+    };
+    let client = match ClientOptions::parse(&config.database.uri)
+        .await
+        .and_then(Client::with_options)
+    {
+        Ok(s) => s,
+        Err(error) => exit_with(&format!("Could not connect to MongoDB: {}", error)),
+    };
+    let db = client.database(&config.database.name);
 
     if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var("RUST_LOG", "actix_web=info");
+        std::env::set_var("RUST_LOG", "actix_web=info,quiz_api=info");
     }
     env_logger::init();
 
+    // Uniqueness the handlers rely on is only guaranteed by these indexes,
+    // e.g. they cannot be built while duplicate usernames exist.
+    if let Err(error) = ensure_indexes(&db).await {
+        exit_with(&format!("Could not create indexes: {}", error));
+    }
+
+    if let Some(admin) = &config.bootstrap_admin {
+        match bootstrap_admin(&db, util.argon.clone(), admin).await {
+            Ok(Some(id)) => log::info!("Created first admin {}", id),
+            Ok(None) => {}
+            Err(error) => log::error!("Could not create first admin: {}", error),
+        }
+    }
+
+    actix_web::rt::spawn(sweep_expired_attempts(db.clone()));
+
     println!("🚀 Server started successfully");
 
+    let cors_origins = config.server.cors_origins.clone();
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:3000")
-            .allowed_origin("http://localhost:3000/")
+        let cors = cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
             .allowed_methods(vec!["GET", "POST"])
             .allowed_headers(vec![
                 header::CONTENT_TYPE,
@@ -44,7 +104,7 @@ async fn main() -> std::io::Result<()> {
             .wrap(cors)
             .wrap(Logger::default())
     })
-    .bind(("127.0.0.1", 8000))?
+    .bind((config.server.host.as_str(), config.server.port))?
     .run()
     .await
 }
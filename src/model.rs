@@ -8,13 +8,32 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 // REQUESTS
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-enum UserType {
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum UserType {
     Student,
     Faculty,
     Admin,
 }
 
+/// What an API key may do. Routes are grouped under these in
+/// `api_keys::ApiScope::for_path`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum ApiScope {
+    #[serde(rename = "quiz:generate")]
+    QuizGenerate,
+    #[serde(rename = "quiz:read")]
+    QuizRead,
+    #[serde(rename = "quiz:write")]
+    QuizWrite,
+    #[serde(rename = "deck:generate")]
+    DeckGenerate,
+    #[serde(rename = "deck:read")]
+    DeckRead,
+    #[serde(rename = "deck:write")]
+    DeckWrite,
+}
+
+#[allow(dead_code)]
 #[derive(Debug, MultipartForm)]
 pub struct RequestAIQuery {
     pub id: Option<Text<String>>,
@@ -41,6 +60,256 @@ pub struct GenericResponse {
     pub message: String,
 }
 
+#[derive(Serialize)]
+pub struct AttemptResponse {
+    pub status: String,
+    pub attempt_id: String,
+    pub questions: Vec<Vec<String>>,
+    /// Answers saved so far, in the same order as `questions`.
+    pub ans: Vec<Answer>,
+    /// Seconds left before the attempt is submitted automatically. `None`
+    /// when the quiz is untimed.
+    pub remaining_seconds: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct DecksResponse {
+    pub status: String,
+    pub decks: Vec<Deck>,
+}
+
+/// Outcome of a question import. Items are numbered from 1 in file order.
+#[derive(Serialize)]
+pub struct ImportQuestionsResponse {
+    pub status: String,
+    pub imported: usize,
+    /// Items already in the faculty member's bank or quizzes, or repeated
+    /// earlier in the file.
+    pub duplicates: Vec<usize>,
+    pub errors: Vec<ItemError>,
+}
+
+/// A failed request. `code` names the kind of failure and `errors` lists
+/// what is wrong with each rejected field.
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ItemError {
+    pub item: usize,
+    pub message: String,
+}
+
+/// Outcome of ingesting scanned bubble sheets. Pages are numbered from 1
+/// across all uploaded files.
+#[derive(Serialize)]
+pub struct ScanSheetsResponse {
+    pub status: String,
+    pub recorded: Vec<ScannedCopy>,
+    pub errors: Vec<ItemError>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ScannedCopy {
+    pub student_id: String,
+    pub variant: char,
+    pub attempt_id: String,
+    pub marks: f64,
+    /// Single-answer questions, numbered as printed, marked more than once.
+    pub unclear: Vec<usize>,
+}
+
+/// Outcome of a roster import. Rows are numbered from 1 after the header.
+#[derive(Serialize)]
+pub struct ImportRosterResponse {
+    pub status: String,
+    pub created: usize,
+    pub updated: usize,
+    pub rows: Vec<RosterRowResult>,
+    pub errors: Vec<ItemError>,
+}
+
+/// A student created or updated from a roster row. New students get a
+/// `temporary_password` they must change at first sign-in.
+#[derive(Serialize, Debug, Clone)]
+pub struct RosterRowResult {
+    pub row: usize,
+    pub student_id: String,
+    pub username: String,
+    pub created: bool,
+    pub temporary_password: Option<String>,
+    /// The class the student was enrolled in, when the row's `class` names
+    /// one of the uploader's classes.
+    pub class_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct QuestionBankResponse {
+    pub status: String,
+    pub questions: Vec<BankQuestion>,
+}
+
+#[derive(Serialize)]
+pub struct UsersResponse {
+    pub status: String,
+    pub users: Vec<UserSummary>,
+    pub page: u64,
+    pub per_page: i64,
+    pub total: u64,
+}
+
+#[derive(Serialize)]
+pub struct UserResponse {
+    pub status: String,
+    pub user: UserSummary,
+}
+
+/// A successful sign-in. `token` is sent back as `Authorization: Bearer
+/// <token>`.
+#[derive(Serialize)]
+pub struct LoginResponse {
+    pub status: String,
+    pub message: String,
+    pub token: String,
+    pub user_id: String,
+}
+
+/// A forced reset replaces the password with `temporary_password`, to be
+/// changed at the user's next sign-in.
+#[derive(Serialize)]
+pub struct PasswordResetResponse {
+    pub status: String,
+    pub message: String,
+    pub temporary_password: String,
+}
+
+/// The secret to enrol in an authenticator app, also as an `otpauth://`
+/// URI and a QR code of it.
+#[derive(Serialize)]
+pub struct TwoFactorSetupResponse {
+    pub status: String,
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub qr_svg: String,
+}
+
+/// Recovery codes in plain text. They are only stored hashed, so this is the
+/// only time they are shown.
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub status: String,
+    pub recovery_codes: Vec<String>,
+}
+
+/// What the frontend should show after an LTI launch: `take_quiz`,
+/// `manage_quiz`, `create_quiz` or `pick_quiz` for deep linking.
+#[derive(Serialize)]
+pub struct LtiLaunchResponse {
+    pub status: String,
+    pub action: String,
+    pub user: UserSummary,
+    /// Session token for the launched user, as `/login` returns.
+    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiz_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deep_link_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quizzes: Option<Vec<LtiQuizChoice>>,
+}
+
+#[derive(Serialize)]
+pub struct LtiQuizChoice {
+    pub quiz_id: String,
+    pub questions: usize,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct PushGradesResponse {
+    pub status: String,
+    pub pushed: usize,
+    pub failed: Vec<GradePushFailure>,
+}
+
+#[derive(Serialize)]
+pub struct GradePushFailure {
+    pub student_id: String,
+    pub message: String,
+}
+
+/// A new API key. Only its hash is stored, so this is the only time the
+/// key is shown.
+#[derive(Serialize)]
+pub struct ApiKeyCreatedResponse {
+    pub status: String,
+    pub key: String,
+    pub api_key: ApiKeySummary,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeysResponse {
+    pub status: String,
+    pub api_keys: Vec<ApiKeySummary>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ApiKeySummary {
+    pub _id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: bson::DateTime,
+    pub last_used_at: Option<bson::DateTime>,
+    pub revoked_at: Option<bson::DateTime>,
+}
+
+impl From<ApiKey> for ApiKeySummary {
+    fn from(key: ApiKey) -> ApiKeySummary {
+        ApiKeySummary {
+            _id: key._id,
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            last_used_at: key.last_used_at,
+            revoked_at: key.revoked_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ClassesResponse {
+    pub status: String,
+    pub classes: Vec<Class>,
+}
+
+#[derive(Serialize)]
+pub struct DueCardsResponse {
+    pub status: String,
+    pub cards: Vec<StudyCard>,
+}
+
+#[derive(Serialize)]
+pub struct AttemptHistoryResponse {
+    pub status: String,
+    pub grade: Option<f64>,
+    pub attempts: Vec<QuizMarks>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AIResponse {
@@ -63,6 +332,15 @@ pub struct Faculty {
     pub _id: String,
     pub username: String,
     pub password: String,
+    pub user_type: UserType,
+    #[serde(default = "active")]
+    pub active: bool,
+    #[serde(default)]
+    pub must_reset_password: bool,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
     pub quiz: Option<Vec<String>>,
     pub flashes: Option<Vec<Flashcard>>,
 }
@@ -73,6 +351,20 @@ pub struct Student {
     pub _id: String,
     pub username: String,
     pub password: String,
+    pub user_type: UserType,
+    #[serde(default = "active")]
+    pub active: bool,
+    #[serde(default)]
+    pub must_reset_password: bool,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    /// Class label from the roster the student was imported from.
+    #[serde(default)]
+    pub class: Option<String>,
     pub quiz: Option<Vec<String>>,
     pub flashes: Option<Vec<Flashcard>>,
 }
@@ -81,6 +373,13 @@ pub struct Student {
 pub struct User {
     pub username: String,
     pub password: String,
+    /// Only read on registration, which sends it a verification link.
+    #[serde(default)]
+    pub email: Option<String>,
+    /// A one-time password or recovery code, for users with two-factor
+    /// authentication enabled.
+    #[serde(default)]
+    pub code: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -88,6 +387,113 @@ pub struct CreateFlash {
     pub user_id: String,
     pub topic: String,
     pub count: i8,
+    /// Add the card to this deck, owned by `user_id`, instead of the user's
+    /// own flashcards.
+    pub deck_id: Option<String>,
+    #[serde(default)]
+    pub mode: FlashMode,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashMode {
+    /// One card holding the key points of the topic.
+    #[default]
+    KeyPoints,
+    /// One card per cloze deletion in sentences generated from the topic.
+    Cloze,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateDeck {
+    pub owner_id: String,
+    pub name: String,
+}
+
+/// Adds a hand-written card to a deck, or replaces one when `card_id` is set.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EditDeckCard {
+    pub deck_id: String,
+    pub user_id: String,
+    pub card_id: Option<String>,
+    pub topic: String,
+    pub content: String,
+    /// Makes it a cloze card testing this deletion of `content`.
+    pub cloze: Option<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RemoveDeckCard {
+    pub deck_id: String,
+    pub user_id: String,
+    pub card_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShareDeck {
+    pub deck_id: String,
+    pub owner_id: String,
+    pub user_id: String,
+    pub access: DeckAccess,
+}
+
+/// `format` is `apkg`, `csv` or `tsv`; without it the file extension decides.
+#[derive(Debug, MultipartForm)]
+pub struct ImportDeck {
+    pub owner_id: Text<String>,
+    pub name: Option<Text<String>>,
+    pub format: Option<Text<String>>,
+    pub file: TempFile,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportDeck {
+    pub format: Option<String>,
+}
+
+/// `format` is `moodle`, `gift`, `qti` or `csv`; without it the file
+/// extension decides.
+#[derive(Debug, MultipartForm)]
+pub struct ImportQuestions {
+    pub faculty_id: Text<String>,
+    pub format: Option<Text<String>>,
+    pub file: TempFile,
+}
+
+/// Scans of filled-in bubble sheets as PNG, JPEG or PDF. `student_id` is
+/// whom sheets printed for no particular student belong to.
+#[derive(Debug, MultipartForm)]
+pub struct ScanSheets {
+    pub quiz_id: Text<String>,
+    pub student_id: Option<Text<String>>,
+    pub files: Vec<TempFile>,
+}
+
+#[derive(Debug, MultipartForm)]
+pub struct ImportRoster {
+    pub file: TempFile,
+}
+
+/// Printing options for paper exams. `variants` is how many of A/B/C/D to
+/// use; copies are printed one per student unless `per_student` is false.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExamPdf {
+    pub course: Option<String>,
+    pub date: Option<String>,
+    pub variants: Option<usize>,
+    pub per_student: Option<bool>,
+    pub bubble_sheet: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportQuiz {
+    pub format: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ForkDeck {
+    pub deck_id: String,
+    pub user_id: String,
 }
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CreateQuiz {
@@ -96,18 +502,340 @@ pub struct CreateQuiz {
     pub count: i8,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PublishQuiz {
+    pub faculty_id: String,
+    pub questions: Vec<Vec<String>>,
+    pub answers: Vec<i32>,
+    pub student_id: Vec<String>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(default)]
+    pub shuffle_questions: bool,
+    #[serde(default)]
+    pub shuffle_options: bool,
+    pub draw_count: Option<usize>,
+    pub duration_minutes: Option<i64>,
+    pub max_attempts: Option<u32>,
+    pub cooldown_minutes: Option<i64>,
+    #[serde(default)]
+    pub grading: GradingStrategy,
+    #[serde(default)]
+    pub multi_select: HashMap<String, Vec<i32>>,
+    #[serde(default)]
+    pub scoring: Scoring,
+    #[serde(default)]
+    pub feedback: Vec<String>,
+}
+
+/// `grade` is how well the card was recalled, from 0 (blackout) to 5
+/// (perfect).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReviewCard {
+    pub card_id: String,
+    pub grade: u8,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StartQuiz {
+    pub quiz_id: String,
+    pub student_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateClass {
+    pub course: String,
+    pub section: String,
+    pub term: String,
+}
+
+/// Enrolling a student in, or removing them from, a class.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Enrollment {
+    pub class_id: String,
+    pub student_id: String,
+}
+
+/// Assigns a quiz, a deck or both to a class.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AssignToClass {
+    pub class_id: String,
+    pub quiz_id: Option<String>,
+    pub deck_id: Option<String>,
+}
+
+/// Paging for the admin user list. `page` counts from 1.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ListUsers {
+    pub role: Option<UserType>,
+    pub page: Option<u64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UnlockUser {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateUsername {
+    pub user_id: String,
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetUserActive {
+    pub user_id: String,
+    pub active: bool,
+}
+
+/// Deleting a user or forcing them to pick a new password.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdminUserAction {
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ForgotPassword {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ResetPassword {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// An LTI launch, posted by the browser from the platform.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LtiLaunch {
+    pub id_token: String,
+    pub state: String,
+}
+
+/// An instructor's choice of quiz for an LTI deep linking request.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PickLtiQuiz {
+    pub deep_link_id: String,
+    pub faculty_id: String,
+    pub quiz_id: String,
+    /// The link's title in the course; defaults to "Quiz".
+    pub title: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PushGrades {
+    pub quiz_id: String,
+}
+
+/// Where the identity provider sends the browser back to, with either a
+/// `code` or an `error`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OidcCallback {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RequestVerification {
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateApiKey {
+    pub username: String,
+    pub password: String,
+    /// Needed when the user has two-factor authentication enabled.
+    #[serde(default)]
+    pub code: Option<String>,
+    pub name: String,
+    /// Comma separated, e.g. `quiz:generate,quiz:read`.
+    pub scopes: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RevokeApiKey {
+    pub user_id: String,
+    pub key_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TwoFactorSetup {
+    pub username: String,
+    pub password: String,
+}
+
+/// Confirms a two-factor change with a current one-time password, or for
+/// anything but enabling, a recovery code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TwoFactorConfirm {
+    pub username: String,
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetTwoFactorPolicy {
+    pub role: UserType,
+    pub required: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChangePassword {
+    pub username: String,
+    pub password: String,
+    /// Needed when the user has two-factor authentication enabled.
+    #[serde(default)]
+    pub code: Option<String>,
+    pub new_password: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GrantExtraTime {
+    pub quiz_id: String,
+    pub student_id: String,
+    pub minutes: i64,
+}
+
+/// Answers as shown to the student, i.e. indexed by the attempt's variant.
+/// Without `ans` the answers saved so far on the attempt are submitted.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SubmitQuiz {
+    pub attempt_id: String,
+    pub ans: Option<Vec<Answer>>,
+}
+
+/// A student's answer to one question: a single option index, or every
+/// option ticked for a multi-select question. `-1` marks it unanswered.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Answer {
+    Single(i8),
+    Multiple(Vec<i8>),
+}
+
+impl Default for Answer {
+    fn default() -> Self {
+        Answer::Single(-1)
+    }
+}
+
+impl Answer {
+    /// The options chosen, empty when unanswered.
+    pub fn selected(&self) -> Vec<i8> {
+        match self {
+            Answer::Single(a) if *a >= 0 => vec![*a],
+            Answer::Single(_) => Vec::new(),
+            Answer::Multiple(a) => {
+                let mut selected: Vec<i8> = a.iter().copied().filter(|a| *a >= 0).collect();
+                selected.sort_unstable();
+                selected.dedup();
+                selected
+            }
+        }
+    }
+}
+
+/// How an attempt's answers are turned into `QuizMarks.marks`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Scoring {
+    /// Points per question, by index. Questions without an entry are worth 1.
+    #[serde(default)]
+    pub weights: Vec<f64>,
+    /// Fraction of a question's points taken off for a wrong answer.
+    #[serde(default)]
+    pub negative_marking: f64,
+    /// Points for an unanswered question. Negative to penalise skipping.
+    #[serde(default)]
+    pub unanswered: f64,
+    /// Give multi-select questions a share of their points for each correct
+    /// option ticked, less each wrong one, instead of all or nothing.
+    #[serde(default)]
+    pub partial_credit: bool,
+}
+
+/// Saves the answer to one displayed question of an attempt in progress.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SaveAnswer {
+    pub attempt_id: String,
+    pub question: usize,
+    pub answer: Answer,
+}
+
+/// Graded submission. `ans` is stored against the original question and
+/// option order of the quiz so results from different variants compare.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct QuizMarks {
+    pub _id: String,
     pub quiz_id: String,
-    pub ans: Vec<i8>,
-    pub marks: i8,
+    pub student_id: String,
+    pub ans: Vec<Answer>,
+    pub marks: f64,
+    /// 1-based number of this attempt for the student.
+    #[serde(default)]
+    pub attempt: u32,
+    #[serde(default = "bson::DateTime::now")]
+    pub submitted_at: bson::DateTime,
+}
+
+/// Which of a student's attempts becomes their grade for the quiz.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GradingStrategy {
+    Highest,
+    #[default]
+    Latest,
+    Average,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Admin {
-    pub admin_id: String,
-    pub name: String,
+    pub _id: String,
+    pub username: String,
     pub password: String,
+    pub user_type: UserType,
+    #[serde(default = "active")]
+    pub active: bool,
+    #[serde(default)]
+    pub must_reset_password: bool,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+/// Any entry of `users` as admins see it. Users created before roles were
+/// recorded have no `user_type`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserSummary {
+    pub _id: String,
+    pub username: String,
+    #[serde(default)]
+    pub user_type: Option<UserType>,
+    #[serde(default = "active")]
+    pub active: bool,
+    #[serde(default)]
+    pub must_reset_password: bool,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    /// Set for users who sign in through an identity provider or LMS.
+    #[serde(default)]
+    pub sso: Option<SsoIdentity>,
+}
+
+fn active() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -115,19 +843,320 @@ pub struct Flashcard {
     pub _id: String,
     pub topic: String,
     pub content: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Set on cloze cards: `content` holds `{{c1::...}}` deletions and this
+    /// card tests the deletion with this number.
+    #[serde(default)]
+    pub cloze: Option<u8>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A card as shown for study, with its front and back already rendered.
+#[derive(Serialize, Debug, Clone)]
+pub struct StudyCard {
+    #[serde(flatten)]
+    pub card: Flashcard,
+    pub front: String,
+    pub back: String,
+}
+
+/// What a user a deck is shared with may do with it. Owners can always edit.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeckAccess {
+    ReadOnly,
+    Copyable,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+/// A single-use link sent by email. `_id` is the SHA-256 hash of the token,
+/// which itself is never stored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountToken {
+    pub _id: String,
+    pub user_id: String,
+    pub purpose: TokenPurpose,
+    /// The address being verified, for email verification.
+    pub email: Option<String>,
+    pub expires_at: bson::DateTime,
+}
+
+/// A single sign-on in progress, between leaving for the identity provider
+/// and coming back. `_id` is the SHA-256 hash of the `state` parameter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OidcLogin {
+    pub _id: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub expires_at: bson::DateTime,
+}
+
+/// An LTI login in progress, between the platform's login initiation and
+/// the launch. `_id` is the SHA-256 hash of the `state` parameter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LtiLogin {
+    pub _id: String,
+    pub nonce: String,
+    pub expires_at: bson::DateTime,
+}
+
+/// A placement of the tool in an LMS course, kept in `lti_links`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LtiResourceLink {
+    pub _id: String,
+    pub issuer: String,
+    pub deployment_id: String,
+    pub resource_link_id: String,
+    pub context_id: Option<String>,
+    pub quiz_id: Option<String>,
+    /// The gradebook column for the link, if scores may be posted to it.
+    pub lineitem: Option<String>,
+    /// Students who have launched the link, whose grades go to the column.
+    #[serde(default)]
+    pub students: Vec<String>,
+}
+
+/// A deep linking request waiting for the instructor to pick a quiz. `_id`
+/// is the SHA-256 hash of the id given to the frontend.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LtiDeepLink {
+    pub _id: String,
+    pub faculty_id: String,
+    pub deployment_id: String,
+    pub return_url: String,
+    pub data: Option<String>,
+    pub expires_at: bson::DateTime,
+}
+
+/// The identity-provider account a user signs in with, kept as `sso` on
+/// their entry in `users`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SsoIdentity {
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// Recent failed sign-ins for one username (`user:<name>`) or client
+/// address (`ip:<addr>`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoginFailures {
+    pub _id: String,
+    pub failures: u32,
+    pub last_failure: bson::DateTime,
+    pub locked_until: Option<bson::DateTime>,
+    /// When Mongo may drop the record.
+    pub expires_at: bson::DateTime,
+}
+
+/// An authenticator app enrolled by a user, kept as `two_factor` on their
+/// entry in `users`. It is pending until a first code confirms it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwoFactor {
+    pub secret: String,
+    pub enabled: bool,
+    /// Argon2 hashes of the unused recovery codes.
+    pub recovery_codes: Vec<String>,
+    /// The time step of the last accepted code, which may not be used again.
+    pub last_step: Option<i64>,
+    pub enabled_at: Option<bson::DateTime>,
+}
+
+/// What sign-in needs to know about an entry of `users`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginRecord {
+    pub _id: String,
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub user_type: Option<UserType>,
+    #[serde(default)]
+    pub must_reset_password: bool,
+    #[serde(default)]
+    pub two_factor: Option<TwoFactor>,
+}
+
+/// The roles that must use two-factor authentication, kept in `settings`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TwoFactorPolicy {
+    pub _id: String,
+    pub required_roles: Vec<UserType>,
+}
+
+/// A personal API key, kept in `api_keys`. `hash` is the SHA-256 hash of
+/// the key and `prefix` its first characters, which identify it to people.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub _id: String,
+    pub user_id: String,
+    pub name: String,
+    pub prefix: String,
+    pub hash: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: bson::DateTime,
+    pub last_used_at: Option<bson::DateTime>,
+    pub revoked_at: Option<bson::DateTime>,
+}
+
+/// A security-relevant event, such as an account being locked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEvent {
+    pub _id: String,
+    pub kind: String,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    /// The admin who caused the event, if any.
+    pub actor_id: Option<String>,
+    pub detail: String,
+    pub at: bson::DateTime,
+}
+
+/// A course section taught by one faculty member. Quizzes and decks assigned
+/// to the class reach every enrolled student, whenever they enrol.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Class {
+    pub _id: String,
+    pub faculty_id: String,
+    pub course: String,
+    pub section: String,
+    pub term: String,
+    #[serde(default)]
+    pub students: Vec<String>,
+    #[serde(default)]
+    pub quizzes: Vec<String>,
+    #[serde(default)]
+    pub decks: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A set of flashcards owned by one user and shared with others.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Deck {
+    pub _id: String,
+    pub owner_id: String,
+    pub name: String,
+    pub cards: Vec<Flashcard>,
+    /// Users the deck is shared with, keyed by user id.
+    #[serde(default)]
+    pub shared_with: HashMap<String, DeckAccess>,
+    /// Students who can read the deck because it is assigned to their class,
+    /// with the ids of those classes. Kept apart from `shared_with` so leaving
+    /// a class takes away only what the class gave.
+    #[serde(default)]
+    pub class_shares: HashMap<String, Vec<String>>,
+    /// The deck this one was copied from.
+    pub forked_from: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Deck {
+    pub fn can_read(&self, user_id: &str) -> bool {
+        self.owner_id == user_id
+            || self.shared_with.contains_key(user_id)
+            || self.class_shares.contains_key(user_id)
+    }
+}
+
+/// A student's spaced-repetition progress on one flashcard. `_id` is
+/// `student_id:card_id` so there is at most one per pair.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReviewState {
+    pub _id: String,
+    pub student_id: String,
+    pub card_id: String,
+    pub ease: f64,
+    pub interval_days: i64,
+    pub repetitions: u32,
+    pub lapses: u32,
+    pub due: bson::DateTime,
+    pub last_reviewed: Option<bson::DateTime>,
+}
+
+/// Options a question may have, so each can be lettered `A` to `Z`.
+pub const MAX_QUESTION_OPTIONS: usize = 26;
+
+/// Each entry of `questions` is `[question, option 0, option 1, ...]` and
+/// `answers` holds the 0-based index of the correct option for it.
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct QuizTable {
     pub _id: String,
     pub faculty_id: String,
     pub questions: Vec<Vec<String>>,
     pub answers: Vec<i32>,
     pub student_id: Vec<String>,
-    pub student_marks: HashMap<String, i32>,
+    pub student_marks: HashMap<String, f64>,
     pub from: DateTime<Utc>,
     pub to: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub shuffle_questions: bool,
+    #[serde(default)]
+    pub shuffle_options: bool,
+    /// Number of questions drawn from `questions` for each attempt. `None`
+    /// serves the whole pool.
+    #[serde(default)]
+    pub draw_count: Option<usize>,
+    /// Minutes a student has from starting an attempt. `None` leaves only the
+    /// `from`/`to` window.
+    #[serde(default)]
+    pub duration_minutes: Option<i64>,
+    /// Extra minutes granted to individual students, keyed by student id.
+    #[serde(default)]
+    pub extra_time: HashMap<String, i64>,
+    /// Attempts allowed per student. `None` allows any number.
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    /// Minutes a student must wait after submitting before starting again.
+    #[serde(default)]
+    pub cooldown_minutes: Option<i64>,
+    #[serde(default)]
+    pub grading: GradingStrategy,
+    /// Correct options of multi-select questions, keyed by question index.
+    /// `answers` is not used for these questions.
+    #[serde(default)]
+    pub multi_select: HashMap<String, Vec<i32>>,
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// Feedback shown after answering, one entry per question. May be
+    /// shorter than `questions`.
+    #[serde(default)]
+    pub feedback: Vec<String>,
+}
+
+/// A question in a faculty member's bank. `question` holds the text then the
+/// options, as in `QuizTable.questions`, and `answers` the correct options.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BankQuestion {
+    pub _id: String,
+    pub faculty_id: String,
+    pub question: Vec<String>,
+    pub answers: Vec<i32>,
+    #[serde(default)]
+    pub feedback: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One student's sitting of a quiz. The variant they see is derived from
+/// `seed`, so it can be rebuilt at any point instead of being stored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attempt {
+    pub _id: String,
+    pub quiz_id: String,
+    pub student_id: String,
+    pub seed: u32,
+    pub created_at: DateTime<Utc>,
+    pub submitted: bool,
+    /// When the attempt is closed and graded regardless of the client.
+    #[serde(default)]
+    pub deadline: Option<bson::DateTime>,
+    /// Answers saved while the attempt is open, in variant order.
+    #[serde(default)]
+    pub ans: Vec<Answer>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -138,11 +1167,13 @@ pub struct Quiz {
 
 // GEMINI VERTEX STRUCTS
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CountTokensRequest {
     pub contents: Content,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CountTokensResponse {
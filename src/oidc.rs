@@ -0,0 +1,473 @@
+//! Single sign-on through an OpenID Connect provider with the authorization
+//! code flow and PKCE. The provider is found by discovery, so a local
+//! stand-in such as mock-oauth2-server works as well as the university's.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::config::OidcConfig;
+use crate::model::UserType;
+
+#[derive(Clone)]
+pub struct Oidc {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    /// The claim listing the user's groups or roles.
+    role_claim: String,
+    admin_groups: Vec<String>,
+    faculty_groups: Vec<String>,
+    http: reqwest::Client,
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// How long the discovery document and keys are used before being fetched
+/// again. Keys are also fetched again when a token names one not yet seen,
+/// so a rotation does not have to wait for this.
+pub const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+struct Cache {
+    discovery: Option<(Discovery, Instant)>,
+    jwks: Option<(JwkSet, Instant)>,
+}
+
+pub fn fresh<T: Clone>(entry: &Option<(T, Instant)>) -> Option<T> {
+    entry
+        .as_ref()
+        .filter(|(_, fetched)| fetched.elapsed() < CACHE_TTL)
+        .map(|(value, _)| value.clone())
+}
+
+/// The parts of the provider's discovery document the flow uses.
+#[derive(Deserialize, Clone)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    /// Everything else, including the role claim.
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, Value>,
+}
+
+impl Oidc {
+    pub fn new(config: &OidcConfig) -> Oidc {
+        Oidc {
+            issuer: config.issuer.clone(),
+            client_id: config.client_id.clone(),
+            client_secret: config.client_secret.clone(),
+            redirect_uri: config.redirect_uri.clone(),
+            role_claim: config.role_claim.clone(),
+            admin_groups: config.admin_groups.clone(),
+            faculty_groups: config.faculty_groups.clone(),
+            http: reqwest::Client::new(),
+            cache: Arc::default(),
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    async fn discover(&self) -> Result<Discovery, String> {
+        if let Some(discovery) = fresh(&self.cache.lock().unwrap().discovery) {
+            return Ok(discovery);
+        }
+        let discovery: Discovery = get_json(&self.http, &discovery_url(&self.issuer)).await?;
+        // The issuer must match exactly, as it must in every ID token.
+        if discovery.issuer != self.issuer {
+            return Err(format!(
+                "Provider reports issuer {}, expected {}",
+                discovery.issuer, self.issuer
+            ));
+        }
+        self.cache.lock().unwrap().discovery = Some((discovery.clone(), Instant::now()));
+        Ok(discovery)
+    }
+
+    /// The provider's signing keys, fetched again if `refresh` or stale.
+    async fn jwks(&self, discovery: &Discovery, refresh: bool) -> Result<JwkSet, String> {
+        if !refresh {
+            if let Some(jwks) = fresh(&self.cache.lock().unwrap().jwks) {
+                return Ok(jwks);
+            }
+        }
+        let jwks: JwkSet = get_json(&self.http, &discovery.jwks_uri).await?;
+        self.cache.lock().unwrap().jwks = Some((jwks.clone(), Instant::now()));
+        Ok(jwks)
+    }
+
+    /// Where to send the browser to sign in.
+    pub async fn authorize_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        verifier: &str,
+    ) -> Result<String, String> {
+        let discovery = self.discover().await?;
+        let challenge = pkce_challenge(verifier);
+        let url = reqwest::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", "openid profile email"),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|err| err.to_string())?;
+        Ok(url.to_string())
+    }
+
+    /// Exchanges the code the provider redirected back with for the user's
+    /// verified identity.
+    pub async fn sign_in(
+        &self,
+        code: &str,
+        verifier: &str,
+        nonce: &str,
+    ) -> Result<IdClaims, String> {
+        let discovery = self.discover().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let resp = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!("Token request failed with {}: {}", status, body));
+        }
+        let tokens: TokenResponse = resp.json().await.map_err(|err| err.to_string())?;
+
+        let verify = |jwks: &JwkSet| {
+            verify_jwt::<IdClaims>(&tokens.id_token, jwks, &discovery.issuer, &self.client_id)
+        };
+        let claims = match verify(&self.jwks(&discovery, false).await?) {
+            Err(error) if error == UNKNOWN_KEY => verify(&self.jwks(&discovery, true).await?),
+            verified => verified,
+        }?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce does not match the sign-in".to_string());
+        }
+        Ok(claims)
+    }
+
+    /// The role the user's groups map to. Admin groups win over faculty
+    /// groups, and anyone in neither is a student.
+    pub fn role(&self, claims: &IdClaims) -> UserType {
+        let groups: Vec<&str> = match claims.other.get(&self.role_claim) {
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(value)) => value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .collect(),
+            _ => vec![],
+        };
+        let member = |wanted: &[String]| groups.iter().any(|g| wanted.iter().any(|w| w == g));
+        if member(&self.admin_groups) {
+            UserType::Admin
+        } else if member(&self.faculty_groups) {
+            UserType::Faculty
+        } else {
+            UserType::Student
+        }
+    }
+}
+
+pub async fn get_json<T: DeserializeOwned>(http: &reqwest::Client, url: &str) -> Result<T, String> {
+    let resp = http.get(url).send().await.map_err(|err| err.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("{} answered {}", url, resp.status()));
+    }
+    resp.json().await.map_err(|err| err.to_string())
+}
+
+/// Where `issuer` publishes its configuration. A trailing `/` on the issuer
+/// is not doubled.
+fn discovery_url(issuer: &str) -> String {
+    format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    )
+}
+
+pub const UNKNOWN_KEY: &str = "Token is not signed by a known key";
+
+/// The algorithm tokens signed with `jwk` must use. Only RS256 and ES256
+/// are accepted, whatever a token's own header claims.
+fn key_algorithm(jwk: &Jwk) -> Result<Algorithm, String> {
+    match (&jwk.algorithm, jwk.common.key_algorithm) {
+        (AlgorithmParameters::RSA(_), None | Some(KeyAlgorithm::RS256)) => Ok(Algorithm::RS256),
+        (AlgorithmParameters::EllipticCurve(key), None | Some(KeyAlgorithm::ES256))
+            if key.curve == EllipticCurve::P256 =>
+        {
+            Ok(Algorithm::ES256)
+        }
+        _ => Err("Token is signed with an unsupported key".to_string()),
+    }
+}
+
+/// Decodes a JWT signed by one of the keys in `jwks`, checking its issuer,
+/// audience and expiry.
+pub fn verify_jwt<T: DeserializeOwned>(
+    token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    audience: &str,
+) -> Result<T, String> {
+    let header = decode_header(token).map_err(|err| err.to_string())?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or(UNKNOWN_KEY)?;
+    let algorithm = key_algorithm(jwk)?;
+    if header.alg != algorithm {
+        return Err("Token algorithm does not match its key".to_string());
+    }
+
+    let key = DecodingKey::from_jwk(jwk).map_err(|err| err.to_string())?;
+    let mut validation = Validation::new(algorithm);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[audience]);
+    match decode::<T>(token, &key, &validation) {
+        Ok(data) => Ok(data.claims),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// A random string for `state`, `nonce` or the PKCE verifier.
+pub fn random_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
+
+/// The S256 code challenge for a PKCE verifier.
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// A username to provision for the user, made to pass validation. The
+/// caller adds a suffix if it is already taken.
+pub fn username_for(claims: &IdClaims) -> String {
+    let wanted = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref().and_then(|e| e.split('@').next()))
+        .unwrap_or(&claims.sub);
+    let mut username: String = wanted
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(26)
+        .collect();
+    if username.len() < 3 {
+        username.insert_str(0, "user");
+    }
+    username
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+    use serde_json::json;
+    use std::sync::OnceLock;
+
+    /// The stand-in provider's key, made once per test run.
+    fn provider_key() -> &'static RsaPrivateKey {
+        static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+        KEY.get_or_init(|| RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap())
+    }
+
+    fn jwks() -> JwkSet {
+        let key = provider_key();
+        serde_json::from_value(json!({ "keys": [{
+            "kty": "RSA",
+            "kid": "test",
+            "alg": "RS256",
+            "n": URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            "e": URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+        }] }))
+        .unwrap()
+    }
+
+    fn signed(header: Header, claims: Value) -> String {
+        let pem = provider_key().to_pkcs8_pem(LineEnding::LF).unwrap();
+        let key = EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap();
+        encode(&header, &claims, &key).unwrap()
+    }
+
+    fn id_token(claims: Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test".to_string());
+        signed(header, claims)
+    }
+
+    fn claims(other: Value) -> IdClaims {
+        let mut claims = json!({ "sub": "abc123" });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(other.as_object().unwrap().clone());
+        serde_json::from_value(claims).unwrap()
+    }
+
+    fn provider() -> Oidc {
+        Oidc {
+            issuer: "http://localhost:8080/default".to_string(),
+            client_id: "quiz".to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:8000/api/oidc/callback".to_string(),
+            role_claim: "groups".to_string(),
+            admin_groups: vec!["it-admins".to_string()],
+            faculty_groups: vec!["staff".to_string(), "faculty".to_string()],
+            http: reqwest::Client::new(),
+            cache: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // The example from RFC 7636 appendix B.
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_discovery_url() {
+        assert_eq!(
+            discovery_url("https://idp.example.edu/realms/uni"),
+            "https://idp.example.edu/realms/uni/.well-known/openid-configuration"
+        );
+        assert_eq!(
+            discovery_url("https://idp.example.edu/"),
+            "https://idp.example.edu/.well-known/openid-configuration"
+        );
+    }
+
+    #[test]
+    fn test_verify_jwt() {
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let token = id_token(json!({
+            "iss": "http://idp", "aud": "quiz", "exp": exp, "sub": "abc123", "nonce": "n",
+        }));
+        let claims: IdClaims = verify_jwt(&token, &jwks(), "http://idp", "quiz").unwrap();
+        assert_eq!(claims.sub, "abc123");
+        assert_eq!(claims.nonce.as_deref(), Some("n"));
+
+        assert!(verify_jwt::<IdClaims>(&token, &jwks(), "http://other", "quiz").is_err());
+        assert!(verify_jwt::<IdClaims>(&token, &jwks(), "http://idp", "other").is_err());
+        let expired = id_token(json!({
+            "iss": "http://idp", "aud": "quiz", "exp": exp - 3600, "sub": "abc123",
+        }));
+        assert!(verify_jwt::<IdClaims>(&expired, &jwks(), "http://idp", "quiz").is_err());
+    }
+
+    #[test]
+    fn test_algorithm_comes_from_the_key() {
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let claims = json!({ "iss": "http://idp", "aud": "quiz", "exp": exp, "sub": "abc123" });
+
+        let mut header = Header::new(Algorithm::RS512);
+        header.kid = Some("test".to_string());
+        let token = signed(header, claims.clone());
+        assert_eq!(
+            verify_jwt::<IdClaims>(&token, &jwks(), "http://idp", "quiz").unwrap_err(),
+            "Token algorithm does not match its key"
+        );
+
+        // A token signed with the public key as an HMAC secret.
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("test".to_string());
+        let public = provider_key().n().to_bytes_be();
+        let token = encode(&header, &claims, &EncodingKey::from_secret(&public)).unwrap();
+        assert!(verify_jwt::<IdClaims>(&token, &jwks(), "http://idp", "quiz").is_err());
+
+        let shared: JwkSet = serde_json::from_value(json!({ "keys": [{
+            "kty": "oct", "kid": "test", "alg": "HS256", "k": URL_SAFE_NO_PAD.encode(&public),
+        }] }))
+        .unwrap();
+        assert_eq!(
+            verify_jwt::<IdClaims>(&token, &shared, "http://idp", "quiz").unwrap_err(),
+            "Token is signed with an unsupported key"
+        );
+    }
+
+    #[test]
+    fn test_roles_from_groups() {
+        let oidc = provider();
+        assert_eq!(oidc.role(&claims(json!({}))), UserType::Student);
+        let staff = claims(json!({ "groups": ["students", "staff"] }));
+        assert_eq!(oidc.role(&staff), UserType::Faculty);
+        let admin = claims(json!({ "groups": "staff it-admins" }));
+        assert_eq!(oidc.role(&admin), UserType::Admin);
+    }
+
+    #[test]
+    fn test_username_for() {
+        let named = claims(json!({ "preferred_username": "ada.lovelace" }));
+        assert_eq!(username_for(&named), "ada.lovelace");
+        let mailed = claims(json!({ "email": "_al@example.edu" }));
+        assert_eq!(username_for(&mailed), "useral");
+        assert_eq!(username_for(&claims(json!({}))), "abc123");
+    }
+}
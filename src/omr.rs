@@ -0,0 +1,584 @@
+//! Reads scanned bubble sheets printed by `exam_pdf`: finds the corner
+//! marks, decodes the sheet code and measures how much of every bubble has
+//! been filled in.
+
+use image::GrayImage;
+use printpdf::lopdf::{Document, Object, Stream};
+
+use crate::bubble_sheet::{
+    self, SheetCode, BUBBLE_RADIUS, CODE_BITS, CODE_CELL, FIDUCIALS, FIDUCIAL_SIZE, MAX_OPTIONS,
+    ORIENTATION_MARK, PAGE_WIDTH, QUESTIONS_PER_SHEET,
+};
+use crate::model::Answer;
+use crate::variant::QuizVariant;
+
+/// Share of a bubble's inside that must be dark for it to count as marked.
+const MARK_THRESHOLD: f32 = 0.35;
+
+/// A scanned sheet. `fill[slot][option]` is how much of that bubble is dark.
+#[derive(Debug, Clone)]
+pub struct ScannedSheet {
+    pub code: SheetCode,
+    pub fill: Vec<Vec<f32>>,
+}
+
+/// The images in an upload: the image itself, or the largest image on every
+/// page of a scanned PDF.
+pub fn load_pages(bytes: &[u8]) -> Result<Vec<GrayImage>, String> {
+    if bytes.starts_with(b"%PDF") {
+        return pdf_pages(bytes);
+    }
+    image::load_from_memory(bytes)
+        .map(|image| vec![image.to_luma8()])
+        .map_err(|err| err.to_string())
+}
+
+fn pdf_pages(bytes: &[u8]) -> Result<Vec<GrayImage>, String> {
+    let doc = Document::load_mem(bytes).map_err(|err| err.to_string())?;
+
+    let mut pages = Vec::new();
+    for (number, page_id) in doc.get_pages() {
+        let (resources, resource_ids) = doc.get_page_resources(page_id);
+        let dictionaries = resources.into_iter().chain(
+            resource_ids
+                .into_iter()
+                .filter_map(|id| doc.get_dictionary(id).ok()),
+        );
+
+        let mut largest: Option<&Stream> = None;
+        for resources in dictionaries {
+            let Ok(xobjects) = resources.get(b"XObject").and_then(Object::as_dict) else {
+                continue;
+            };
+            for (_, object) in xobjects.iter() {
+                let Ok((_, Object::Stream(stream))) = doc.dereference(object) else {
+                    continue;
+                };
+                if !matches!(
+                    stream.dict.get(b"Subtype").and_then(Object::as_name),
+                    Ok(b"Image")
+                ) {
+                    continue;
+                }
+                if largest.is_none_or(|l| pixels(stream) > pixels(l)) {
+                    largest = Some(stream);
+                }
+            }
+        }
+
+        match largest {
+            Some(stream) => {
+                pages.push(pdf_image(stream).map_err(|err| format!("Page {}: {}", number, err))?)
+            }
+            None => return Err(format!("Page {} has no scanned image", number)),
+        }
+    }
+    Ok(pages)
+}
+
+fn dimension(stream: &Stream, key: &[u8]) -> i64 {
+    stream.dict.get(key).and_then(Object::as_i64).unwrap_or(0)
+}
+
+fn pixels(stream: &Stream) -> i64 {
+    dimension(stream, b"Width") * dimension(stream, b"Height")
+}
+
+fn pdf_image(stream: &Stream) -> Result<GrayImage, String> {
+    let filters = stream.filters().unwrap_or_default();
+    if filters.iter().any(|f| f == "DCTDecode") {
+        return image::load_from_memory(&stream.content)
+            .map(|image| image.to_luma8())
+            .map_err(|err| err.to_string());
+    }
+    if filters.iter().any(|f| f != "FlateDecode") {
+        return Err(format!("Unsupported image encoding {}", filters.join(", ")));
+    }
+
+    let data = if filters.is_empty() {
+        stream.content.clone()
+    } else {
+        // lopdf refuses to decompress image streams, which are otherwise
+        // ordinary Flate streams.
+        let mut plain = stream.clone();
+        plain.dict.remove(b"Subtype");
+        plain
+            .decompressed_content()
+            .map_err(|err| err.to_string())?
+    };
+
+    let width = dimension(stream, b"Width") as u32;
+    let height = dimension(stream, b"Height") as u32;
+    let bits = dimension(stream, b"BitsPerComponent");
+    let components = match stream.dict.get(b"ColorSpace").and_then(Object::as_name) {
+        Ok(b"DeviceRGB") => 3,
+        Ok(b"DeviceGray") => 1,
+        _ => return Err("Unsupported image colour space".to_string()),
+    };
+
+    let row = (width as usize * components * bits as usize).div_ceil(8);
+    if data.len() < row * height as usize {
+        return Err("Image data is truncated".to_string());
+    }
+    let gray = |x: u32, y: u32| -> u8 {
+        let line = &data[y as usize * row..];
+        match (bits, components) {
+            (1, 1) => {
+                let byte = line[x as usize / 8];
+                if byte & (0x80 >> (x % 8)) != 0 {
+                    255
+                } else {
+                    0
+                }
+            }
+            (8, 1) => line[x as usize],
+            (_, _) => {
+                let p = &line[x as usize * 3..];
+                ((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as u8
+            }
+        }
+    };
+    if !matches!((bits, components), (1, 1) | (8, 1) | (8, 3)) {
+        return Err(format!("Unsupported {}-bit image", bits));
+    }
+
+    Ok(GrayImage::from_fn(width, height, |x, y| {
+        image::Luma([gray(x, y)])
+    }))
+}
+
+/// Grey level separating ink from paper, by Otsu's method.
+fn threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total = image.pixels().len() as f64;
+    let sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, n)| i as f64 * *n as f64)
+        .sum();
+
+    let (mut best, mut best_variance) = (128, 0.0);
+    let (mut background, mut background_sum) = (0.0, 0.0);
+    for (level, count) in histogram.iter().enumerate() {
+        background += *count as f64;
+        if background == 0.0 {
+            continue;
+        }
+        let foreground = total - background;
+        if foreground == 0.0 {
+            break;
+        }
+        background_sum += level as f64 * *count as f64;
+        let mean_background = background_sum / background;
+        let mean_foreground = (sum - background_sum) / foreground;
+        let variance = background * foreground * (mean_background - mean_foreground).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = level as u8;
+        }
+    }
+    best
+}
+
+/// Centres of solid, roughly square blobs about the size of a corner mark.
+fn fiducial_candidates(dark: &[bool], width: usize, height: usize) -> Vec<(f64, f64)> {
+    let expected = FIDUCIAL_SIZE as f64 * width as f64 / PAGE_WIDTH as f64;
+    let mut seen = vec![false; dark.len()];
+    let mut candidates = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..dark.len() {
+        if !dark[start] || seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        stack.push(start);
+        let (mut count, mut sum_x, mut sum_y) = (0usize, 0.0, 0.0);
+        let (mut min_x, mut max_x, mut min_y, mut max_y) = (width, 0, height, 0);
+
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            count += 1;
+            sum_x += x as f64;
+            sum_y += y as f64;
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if dark[n] && !seen[n] {
+                    seen[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+
+        let (w, h) = ((max_x - min_x + 1) as f64, (max_y - min_y + 1) as f64);
+        let sized = (0.7..1.4).contains(&(w / expected)) && (0.7..1.4).contains(&(h / expected));
+        let square = (0.6..1.6).contains(&(w / h));
+        let solid = count as f64 / (w * h) > 0.75;
+        if sized && square && solid {
+            candidates.push((sum_x / count as f64, sum_y / count as f64));
+        }
+    }
+    candidates
+}
+
+/// Maps page millimetres to image pixels through four point pairs.
+struct Homography([f64; 8]);
+
+impl Homography {
+    fn from_points(from: &[(f64, f64); 4], to: &[(f64, f64); 4]) -> Option<Homography> {
+        let mut m = [[0.0f64; 9]; 8];
+        for (i, ((x, y), (u, v))) in from.iter().zip(to).enumerate() {
+            m[2 * i] = [*x, *y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, *u];
+            m[2 * i + 1] = [0.0, 0.0, 0.0, *x, *y, 1.0, -v * x, -v * y, *v];
+        }
+
+        // Gaussian elimination with partial pivoting.
+        for col in 0..8 {
+            let pivot = (col..8).max_by(|a, b| m[*a][col].abs().total_cmp(&m[*b][col].abs()))?;
+            if m[pivot][col].abs() < 1e-9 {
+                return None;
+            }
+            m.swap(col, pivot);
+            let pivot_row = m[col];
+            for (r, row) in m.iter_mut().enumerate() {
+                if r != col {
+                    let factor = row[col] / pivot_row[col];
+                    for (value, p) in row.iter_mut().zip(pivot_row).skip(col) {
+                        *value -= factor * p;
+                    }
+                }
+            }
+        }
+
+        let mut h = [0.0; 8];
+        for (i, value) in h.iter_mut().enumerate() {
+            *value = m[i][8] / m[i][i];
+        }
+        Some(Homography(h))
+    }
+
+    fn map(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let h = &self.0;
+        let w = h[6] * x + h[7] * y + 1.0;
+        (
+            (h[0] * x + h[1] * y + h[2]) / w,
+            (h[3] * x + h[4] * y + h[5]) / w,
+        )
+    }
+}
+
+struct Page<'a> {
+    dark: &'a [bool],
+    width: usize,
+    height: usize,
+    to_image: Homography,
+}
+
+impl Page<'_> {
+    /// Share of dark pixels in the square of `half` millimetres around
+    /// `center`, or within that radius when `round`.
+    fn fill(&self, (cx, cy): (f32, f32), half: f32, round: bool) -> f32 {
+        let step = 0.2;
+        let steps = (half / step) as i32;
+        let (mut dark, mut total) = (0, 0);
+        for i in -steps..=steps {
+            for j in -steps..=steps {
+                let (dx, dy) = (i as f32 * step, j as f32 * step);
+                if round && dx * dx + dy * dy > half * half {
+                    continue;
+                }
+                let (x, y) = self.to_image.map(((cx + dx) as f64, (cy + dy) as f64));
+                total += 1;
+                if x >= 0.0 && y >= 0.0 && (x as usize) < self.width && (y as usize) < self.height {
+                    dark += self.dark[y as usize * self.width + x as usize] as usize;
+                }
+            }
+        }
+        dark as f32 / total.max(1) as f32
+    }
+
+    fn upright(&self) -> bool {
+        self.fill(ORIENTATION_MARK, CODE_CELL * 0.3, false) > 0.5
+    }
+
+    fn code(&self) -> Option<SheetCode> {
+        let mut code = 0u16;
+        for bit in 0..CODE_BITS {
+            let center = bubble_sheet::code_cell_center(bit);
+            if self.fill(center, CODE_CELL * 0.3, false) > 0.5 {
+                code |= 1 << bit;
+            }
+        }
+        SheetCode::decode(code)
+    }
+}
+
+pub fn read_sheet(image: &GrayImage) -> Result<ScannedSheet, String> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let threshold = threshold(image);
+    let dark: Vec<bool> = image.pixels().map(|p| p[0] <= threshold).collect();
+
+    let candidates = fiducial_candidates(&dark, width, height);
+    let (w, h) = (width as f64, height as f64);
+    let corners = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)];
+    let mut found = [(0.0, 0.0); 4];
+    for (i, corner) in corners.iter().enumerate() {
+        let nearest = candidates.iter().min_by(|a, b| {
+            let da = (a.0 - corner.0).powi(2) + (a.1 - corner.1).powi(2);
+            let db = (b.0 - corner.0).powi(2) + (b.1 - corner.1).powi(2);
+            da.total_cmp(&db)
+        });
+        found[i] = *nearest.ok_or("No corner marks found")?;
+    }
+    if found
+        .iter()
+        .enumerate()
+        .any(|(i, a)| found[i + 1..].contains(a))
+    {
+        return Err("Could not find all four corner marks".to_string());
+    }
+
+    let fiducials = FIDUCIALS.map(|(x, y)| (x as f64, y as f64));
+    // The sheet may have been scanned upside down.
+    let upside_down = [found[3], found[2], found[1], found[0]];
+    for corners in [found, upside_down] {
+        let Some(to_image) = Homography::from_points(&fiducials, &corners) else {
+            continue;
+        };
+        let page = Page {
+            dark: &dark,
+            width,
+            height,
+            to_image,
+        };
+        if !page.upright() {
+            continue;
+        }
+        let Some(code) = page.code() else {
+            return Err("Could not read the sheet code".to_string());
+        };
+
+        let fill = (0..QUESTIONS_PER_SHEET)
+            .map(|slot| {
+                (0..MAX_OPTIONS)
+                    .map(|option| {
+                        let center = bubble_sheet::bubble_center(slot, option);
+                        page.fill(center, BUBBLE_RADIUS * 0.65, true)
+                    })
+                    .collect()
+            })
+            .collect();
+        return Ok(ScannedSheet { code, fill });
+    }
+
+    Err("Could not tell which way up the sheet is".to_string())
+}
+
+/// Answers marked on the sheets of one copy, in the copy's question order,
+/// and the numbers of single-answer questions marked more than once.
+/// `multi_select` says which displayed questions take several options.
+pub fn marked_answers(
+    layout: &QuizVariant,
+    multi_select: &[bool],
+    sheets: &[&ScannedSheet],
+) -> (Vec<Answer>, Vec<usize>) {
+    let mut ans = Vec::new();
+    let mut unclear = Vec::new();
+
+    for (i, options) in layout.option_orders.iter().enumerate() {
+        let sheet = sheets
+            .iter()
+            .find(|s| s.code.sheet == i / QUESTIONS_PER_SHEET);
+        let marked: Vec<i8> = match sheet {
+            Some(sheet) => sheet.fill[i % QUESTIONS_PER_SHEET]
+                .iter()
+                .take(options.len())
+                .enumerate()
+                .filter(|(_, fill)| **fill > MARK_THRESHOLD)
+                .map(|(o, _)| o as i8)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        ans.push(match marked.as_slice() {
+            _ if multi_select[i] => Answer::Multiple(marked),
+            [] => Answer::default(),
+            [one] => Answer::Single(*one),
+            _ => {
+                unclear.push(i + 1);
+                Answer::Multiple(marked)
+            }
+        });
+    }
+    (ans, unclear)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bubble_sheet::PAGE_HEIGHT;
+    use image::Luma;
+
+    const SCALE: f32 = 4.0;
+    const MARGIN: u32 = 30;
+
+    /// A sheet as a scanner would see it, with `marks` filled in.
+    fn scan(code: SheetCode, marks: &[(usize, usize)]) -> GrayImage {
+        let size = |mm: f32| (mm * SCALE) as u32 + 2 * MARGIN;
+        let mut image = GrayImage::from_pixel(size(PAGE_WIDTH), size(PAGE_HEIGHT), Luma([235]));
+        let to_px = |(x, y): (f32, f32)| {
+            (
+                x * SCALE + MARGIN as f32,
+                (PAGE_HEIGHT - y) * SCALE + MARGIN as f32,
+            )
+        };
+        let mut fill = |center: (f32, f32), half: f32, round: bool| {
+            let (cx, cy) = to_px(center);
+            let half = half * SCALE;
+            for y in (cy - half) as u32..=(cy + half) as u32 {
+                for x in (cx - half) as u32..=(cx + half) as u32 {
+                    let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+                    if !round || dx * dx + dy * dy <= half * half {
+                        image.put_pixel(x, y, Luma([30]));
+                    }
+                }
+            }
+        };
+
+        for fiducial in FIDUCIALS {
+            fill(fiducial, FIDUCIAL_SIZE / 2.0, false);
+        }
+        fill(ORIENTATION_MARK, CODE_CELL / 2.0, false);
+        let bits = code.encode();
+        for bit in 0..CODE_BITS {
+            if bits & (1 << bit) != 0 {
+                fill(bubble_sheet::code_cell_center(bit), CODE_CELL / 2.0, false);
+            }
+        }
+        for (slot, option) in marks {
+            fill(
+                bubble_sheet::bubble_center(*slot, *option),
+                BUBBLE_RADIUS,
+                true,
+            );
+        }
+        image
+    }
+
+    fn code() -> SheetCode {
+        SheetCode {
+            variant: 1,
+            sheet: 0,
+            student: 12,
+        }
+    }
+
+    fn marked(sheet: &ScannedSheet, slot: usize) -> Vec<usize> {
+        (0..MAX_OPTIONS)
+            .filter(|o| sheet.fill[slot][*o] > MARK_THRESHOLD)
+            .collect()
+    }
+
+    #[test]
+    fn test_reads_code_and_marks() {
+        let sheet = read_sheet(&scan(code(), &[(0, 1), (3, 0), (3, 2), (30, 3)])).unwrap();
+        assert_eq!(sheet.code, code());
+        assert_eq!(marked(&sheet, 0), vec![1]);
+        assert_eq!(marked(&sheet, 1), Vec::<usize>::new());
+        assert_eq!(marked(&sheet, 3), vec![0, 2]);
+        assert_eq!(marked(&sheet, 30), vec![3]);
+    }
+
+    #[test]
+    fn test_reads_upside_down_scan() {
+        let image = image::imageops::rotate180(&scan(code(), &[(7, 2)]));
+        let sheet = read_sheet(&image).unwrap();
+        assert_eq!(sheet.code, code());
+        assert_eq!(marked(&sheet, 7), vec![2]);
+    }
+
+    #[test]
+    fn test_reads_scan_embedded_in_pdf() {
+        use printpdf::lopdf::{dictionary, Document, Object, Stream};
+
+        let image = scan(code(), &[(5, 4)]);
+        let (width, height) = image.dimensions();
+        let mut doc = Document::with_version("1.5");
+        let mut stream = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => width as i64,
+                "Height" => height as i64,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            image.into_raw(),
+        );
+        stream.compress().unwrap();
+        let image_id = doc.add_object(stream);
+        let pages_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Resources" => dictionary! { "XObject" => dictionary! { "Im1" => image_id } },
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+
+        let pages = load_pages(&bytes).unwrap();
+        assert_eq!(pages.len(), 1);
+        let sheet = read_sheet(&pages[0]).unwrap();
+        assert_eq!(sheet.code, code());
+        assert_eq!(marked(&sheet, 5), vec![4]);
+    }
+
+    #[test]
+    fn test_blank_page_rejected() {
+        let image = GrayImage::from_pixel(400, 560, Luma([240]));
+        assert!(read_sheet(&image).is_err());
+    }
+
+    #[test]
+    fn test_marked_answers() {
+        let sheet = read_sheet(&scan(code(), &[(0, 1), (1, 0), (1, 2), (2, 0), (2, 3)])).unwrap();
+        let layout = QuizVariant {
+            question_order: vec![0, 1, 2, 3],
+            option_orders: vec![(0..4).collect(); 4],
+        };
+        let (ans, unclear) = marked_answers(&layout, &[false, false, true, false], &[&sheet]);
+        assert_eq!(
+            ans,
+            vec![
+                Answer::Single(1),
+                Answer::Multiple(vec![0, 2]),
+                Answer::Multiple(vec![0, 3]),
+                Answer::default(),
+            ]
+        );
+        assert_eq!(unclear, vec![2]);
+    }
+}
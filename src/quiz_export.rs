@@ -0,0 +1,610 @@
+//! Quizzes as Moodle XML, GIFT and IMS QTI 2.1 packages for use in an LMS,
+//! or as a CSV table.
+//!
+//! Each question becomes a multiple choice question with the quiz's answer
+//! key, weights and negative marking expressed as answer fractions. LMS
+//! multiple-answer questions always give partial credit, so multi-select
+//! questions are exported that way whatever `Scoring.partial_credit` says,
+//! except in QTI which can express both.
+
+use std::io::{Cursor, Write};
+
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::model::QuizTable;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuizFormat {
+    MoodleXml,
+    Gift,
+    Qti,
+    Csv,
+}
+
+/// Leading columns of quiz CSV files. Every other column holds an option.
+pub const CSV_COLUMNS: [&str; 3] = ["question", "answer", "feedback"];
+
+impl QuizFormat {
+    pub fn parse(format: &str) -> Option<QuizFormat> {
+        match format.trim_start_matches('.').to_lowercase().as_str() {
+            "moodle" | "moodle_xml" | "xml" => Some(QuizFormat::MoodleXml),
+            "gift" => Some(QuizFormat::Gift),
+            "qti" | "qti21" | "zip" => Some(QuizFormat::Qti),
+            "csv" => Some(QuizFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            QuizFormat::MoodleXml => "xml",
+            QuizFormat::Gift => "gift",
+            QuizFormat::Qti => "zip",
+            QuizFormat::Csv => "csv",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            QuizFormat::MoodleXml => "application/xml",
+            QuizFormat::Gift => "text/plain; charset=utf-8",
+            QuizFormat::Qti => "application/zip",
+            QuizFormat::Csv => "text/csv",
+        }
+    }
+}
+
+pub fn export_quiz(quiz: &QuizTable, format: QuizFormat) -> Result<Vec<u8>, String> {
+    match format {
+        QuizFormat::MoodleXml => Ok(moodle_xml(quiz).into_bytes()),
+        QuizFormat::Gift => Ok(gift(quiz).into_bytes()),
+        QuizFormat::Qti => qti_package(quiz),
+        QuizFormat::Csv => csv_table(quiz),
+    }
+}
+
+/// A question with its answer key resolved from `answers`/`multi_select`.
+struct Item<'a> {
+    name: String,
+    text: &'a str,
+    options: &'a [String],
+    correct: Vec<usize>,
+    multi: bool,
+    weight: f64,
+    feedback: Option<&'a str>,
+}
+
+/// The answer fractions, in percent, that Moodle accepts. Any other value is
+/// refused on import, and each may also be negative.
+const MOODLE_FRACTIONS: [f64; 21] = [
+    100.0, 90.0, 83.33333, 80.0, 75.0, 70.0, 66.66667, 60.0, 50.0, 40.0, 33.33333, 30.0, 25.0,
+    20.0, 16.66667, 14.28571, 12.5, 11.11111, 10.0, 5.0, 0.0,
+];
+
+/// The allowed fraction nearest to `percent`, keeping its sign.
+fn moodle_fraction(percent: f64) -> f64 {
+    let nearest = MOODLE_FRACTIONS
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            (a - percent.abs())
+                .abs()
+                .total_cmp(&(b - percent.abs()).abs())
+        })
+        .unwrap_or(0.0);
+    nearest.copysign(percent)
+}
+
+impl Item<'_> {
+    /// Share of the question's marks for choosing `option`, in percent and
+    /// rounded to a value Moodle accepts.
+    fn fraction(&self, option: usize, quiz: &QuizTable) -> f64 {
+        let correct = self.correct.contains(&option);
+        let fraction = if self.multi {
+            let share = 100.0 / self.correct.len() as f64;
+            if correct {
+                share
+            } else {
+                -share
+            }
+        } else if correct {
+            100.0
+        } else {
+            -quiz.scoring.negative_marking * 100.0
+        };
+        moodle_fraction(fraction)
+    }
+}
+
+fn items(quiz: &QuizTable) -> Vec<Item<'_>> {
+    quiz.questions
+        .iter()
+        .enumerate()
+        .map(|(q, question)| {
+            let (correct, multi) = match quiz.multi_select.get(&q.to_string()) {
+                Some(correct) => (correct.iter().map(|o| *o as usize).collect(), true),
+                None => (vec![quiz.answers[q] as usize], false),
+            };
+            Item {
+                name: format!("Q{}", q + 1),
+                text: &question[0],
+                options: &question[1..],
+                correct,
+                multi,
+                weight: quiz.scoring.weights.get(q).copied().unwrap_or(1.0),
+                feedback: quiz
+                    .feedback
+                    .get(q)
+                    .map(String::as_str)
+                    .filter(|f| !f.is_empty()),
+            }
+        })
+        .collect()
+}
+
+/// Numbers without trailing zeros, to at most 5 decimals as Moodle expects.
+fn number(value: f64) -> String {
+    let text = format!("{:.5}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    match text {
+        "-0" => "0".to_string(),
+        _ => text.to_string(),
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Text for an HTML field inside XML: escaped once for HTML, so stored text
+/// is never taken as markup, and again for XML.
+fn html_field(text: &str) -> String {
+    escape_xml(&escape_xml(text).replace('\n', "<br>"))
+}
+
+fn title(quiz: &QuizTable) -> String {
+    format!("Quiz {}", quiz._id)
+}
+
+fn moodle_xml(quiz: &QuizTable) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<quiz>\n");
+    xml.push_str(&format!(
+        "  <question type=\"category\">\n    <category><text>$course$/top/{}</text></category>\n  </question>\n",
+        escape_xml(&title(quiz))
+    ));
+
+    for item in items(quiz) {
+        xml.push_str("  <question type=\"multichoice\">\n");
+        xml.push_str(&format!("    <name><text>{}</text></name>\n", item.name));
+        xml.push_str(&format!(
+            "    <questiontext format=\"html\"><text>{}</text></questiontext>\n",
+            html_field(item.text)
+        ));
+        xml.push_str(&format!(
+            "    <generalfeedback format=\"html\"><text>{}</text></generalfeedback>\n",
+            html_field(item.feedback.unwrap_or(""))
+        ));
+        xml.push_str(&format!(
+            "    <defaultgrade>{}</defaultgrade>\n",
+            number(item.weight)
+        ));
+        xml.push_str("    <penalty>0</penalty>\n    <hidden>0</hidden>\n");
+        xml.push_str(&format!("    <single>{}</single>\n", !item.multi));
+        xml.push_str(&format!(
+            "    <shuffleanswers>{}</shuffleanswers>\n",
+            quiz.shuffle_options
+        ));
+        xml.push_str("    <answernumbering>abc</answernumbering>\n");
+        for (o, option) in item.options.iter().enumerate() {
+            xml.push_str(&format!(
+                "    <answer fraction=\"{}\" format=\"html\"><text>{}</text><feedback format=\"html\"><text></text></feedback></answer>\n",
+                number(item.fraction(o, quiz)),
+                html_field(option)
+            ));
+        }
+        xml.push_str("  </question>\n");
+    }
+
+    xml.push_str("</quiz>\n");
+    xml
+}
+
+fn escape_gift(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '~' | '=' | '#' | '{' | '}' | ':' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn gift(quiz: &QuizTable) -> String {
+    let mut text = format!("$CATEGORY: {}\n", escape_gift(&title(quiz)));
+
+    for item in items(quiz) {
+        text.push_str(&format!(
+            "\n::{}::{} {{\n",
+            item.name,
+            escape_gift(item.text)
+        ));
+        for (o, option) in item.options.iter().enumerate() {
+            let fraction = item.fraction(o, quiz);
+            let mark = if !item.multi && fraction == 100.0 {
+                "=".to_string()
+            } else if fraction == 0.0 {
+                "~".to_string()
+            } else {
+                format!("~%{}%", number(fraction))
+            };
+            text.push_str(&format!("\t{}{}\n", mark, escape_gift(option)));
+        }
+        if let Some(feedback) = item.feedback {
+            text.push_str(&format!("\t####{}\n", escape_gift(feedback)));
+        }
+        text.push_str("}\n");
+    }
+
+    text
+}
+
+/// `A` for the first option, `B` for the second and so on, then numbers
+/// from 27 once the alphabet runs out.
+pub fn option_label(option: usize) -> String {
+    match u8::try_from(option) {
+        Ok(o) if o < 26 => char::from(b'A' + o).to_string(),
+        _ => (option + 1).to_string(),
+    }
+}
+
+/// Correct options as letters, `A` being the first option, joined with `;`.
+fn csv_table(quiz: &QuizTable) -> Result<Vec<u8>, String> {
+    let items = items(quiz);
+    let options = items
+        .iter()
+        .map(|item| item.options.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let mut header: Vec<String> = CSV_COLUMNS.iter().map(|c| c.to_string()).collect();
+    header.extend((0..options).map(|o| format!("option_{}", option_label(o).to_lowercase())));
+    writer
+        .write_record(&header)
+        .map_err(|err| err.to_string())?;
+
+    for item in &items {
+        let answer: Vec<String> = item.correct.iter().map(|o| option_label(*o)).collect();
+        let mut record = vec![
+            item.text.to_string(),
+            answer.join(";"),
+            item.feedback.unwrap_or("").to_string(),
+        ];
+        record.extend(item.options.iter().cloned());
+        record.resize(header.len(), String::new());
+        writer
+            .write_record(&record)
+            .map_err(|err| err.to_string())?;
+    }
+
+    writer.into_inner().map_err(|err| err.to_string())
+}
+
+const QTI_NAMESPACE: &str = "xmlns=\"http://www.imsglobal.org/xsd/imsqti_v2p1\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://www.imsglobal.org/xsd/imsqti_v2p1 http://www.imsglobal.org/xsd/qti/qtiv2p1/imsqti_v2p1.xsd\"";
+
+fn set_score(value: &str) -> String {
+    format!(
+        "<setOutcomeValue identifier=\"SCORE\">{}</setOutcomeValue>",
+        value
+    )
+}
+
+fn float(value: f64) -> String {
+    format!(
+        "<baseValue baseType=\"float\">{}</baseValue>",
+        number(value)
+    )
+}
+
+fn qti_item(item: &Item, quiz: &QuizTable) -> String {
+    let scoring = &quiz.scoring;
+    let partial = item.multi && scoring.partial_credit;
+    let cardinality = if item.multi { "multiple" } else { "single" };
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<assessmentItem {} identifier=\"{}\" title=\"{}\" adaptive=\"false\" timeDependent=\"false\">\n",
+        QTI_NAMESPACE, item.name, item.name
+    );
+
+    xml.push_str(&format!(
+        "  <responseDeclaration identifier=\"RESPONSE\" cardinality=\"{}\" baseType=\"identifier\">\n    <correctResponse>\n",
+        cardinality
+    ));
+    for o in &item.correct {
+        xml.push_str(&format!("      <value>C{}</value>\n", o));
+    }
+    xml.push_str("    </correctResponse>\n");
+    if partial {
+        xml.push_str("    <mapping lowerBound=\"0\" defaultValue=\"0\">\n");
+        for o in 0..item.options.len() {
+            xml.push_str(&format!(
+                "      <mapEntry mapKey=\"C{}\" mappedValue=\"{}\"/>\n",
+                o,
+                number(item.fraction(o, quiz) / 100.0 * item.weight)
+            ));
+        }
+        xml.push_str("    </mapping>\n");
+    }
+    xml.push_str("  </responseDeclaration>\n");
+
+    xml.push_str("  <outcomeDeclaration identifier=\"SCORE\" cardinality=\"single\" baseType=\"float\">\n    <defaultValue><value>0</value></defaultValue>\n  </outcomeDeclaration>\n");
+    xml.push_str(&format!(
+        "  <outcomeDeclaration identifier=\"MAXSCORE\" cardinality=\"single\" baseType=\"float\">\n    <defaultValue><value>{}</value></defaultValue>\n  </outcomeDeclaration>\n",
+        number(item.weight)
+    ));
+    if item.feedback.is_some() {
+        xml.push_str("  <outcomeDeclaration identifier=\"FEEDBACK\" cardinality=\"single\" baseType=\"identifier\"/>\n");
+    }
+
+    xml.push_str(&format!(
+        "  <itemBody>\n    <choiceInteraction responseIdentifier=\"RESPONSE\" shuffle=\"{}\" maxChoices=\"{}\">\n      <prompt>{}</prompt>\n",
+        quiz.shuffle_options,
+        if item.multi { 0 } else { 1 },
+        escape_xml(item.text)
+    ));
+    for (o, option) in item.options.iter().enumerate() {
+        xml.push_str(&format!(
+            "      <simpleChoice identifier=\"C{}\">{}</simpleChoice>\n",
+            o,
+            escape_xml(option)
+        ));
+    }
+    xml.push_str("    </choiceInteraction>\n  </itemBody>\n");
+
+    // Mirrors `grading::score`: unanswered, fully correct, partly correct
+    // (multi-select with partial credit) or wrong.
+    xml.push_str("  <responseProcessing>\n    <responseCondition>\n");
+    xml.push_str(&format!(
+        "      <responseIf><isNull><variable identifier=\"RESPONSE\"/></isNull>{}</responseIf>\n",
+        set_score(&float(scoring.unanswered))
+    ));
+    if partial {
+        xml.push_str(&format!(
+            "      <responseElse>{}</responseElse>\n",
+            set_score("<mapResponse identifier=\"RESPONSE\"/>")
+        ));
+    } else {
+        let wrong = -scoring.negative_marking * item.weight;
+        xml.push_str(&format!(
+            "      <responseElseIf><match><variable identifier=\"RESPONSE\"/><correct identifier=\"RESPONSE\"/></match>{}</responseElseIf>\n",
+            set_score(&float(item.weight))
+        ));
+        xml.push_str(&format!(
+            "      <responseElse>{}</responseElse>\n",
+            set_score(&float(wrong))
+        ));
+    }
+    xml.push_str("    </responseCondition>\n");
+    if item.feedback.is_some() {
+        xml.push_str("    <setOutcomeValue identifier=\"FEEDBACK\"><baseValue baseType=\"identifier\">GENERAL</baseValue></setOutcomeValue>\n");
+    }
+    xml.push_str("  </responseProcessing>\n");
+
+    if let Some(feedback) = item.feedback {
+        xml.push_str(&format!(
+            "  <modalFeedback outcomeIdentifier=\"FEEDBACK\" identifier=\"GENERAL\" showHide=\"show\">{}</modalFeedback>\n",
+            escape_xml(feedback)
+        ));
+    }
+
+    xml.push_str("</assessmentItem>\n");
+    xml
+}
+
+fn qti_test(quiz: &QuizTable, items: &[Item]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<assessmentTest {} identifier=\"quiz-{}\" title=\"{}\">\n",
+        QTI_NAMESPACE,
+        escape_xml(&quiz._id),
+        escape_xml(&title(quiz))
+    );
+    xml.push_str(
+        "  <outcomeDeclaration identifier=\"SCORE\" cardinality=\"single\" baseType=\"float\"/>\n",
+    );
+    xml.push_str("  <testPart identifier=\"part\" navigationMode=\"nonlinear\" submissionMode=\"simultaneous\">\n");
+    xml.push_str(
+        "    <assessmentSection identifier=\"section\" title=\"Questions\" visible=\"true\">\n",
+    );
+    if let Some(n) = quiz.draw_count.filter(|n| *n < items.len()) {
+        xml.push_str(&format!("      <selection select=\"{}\"/>\n", n));
+    }
+    if quiz.shuffle_questions {
+        xml.push_str("      <ordering shuffle=\"true\"/>\n");
+    }
+    for item in items {
+        xml.push_str(&format!(
+            "      <assessmentItemRef identifier=\"{}\" href=\"items/{}.xml\"/>\n",
+            item.name, item.name
+        ));
+    }
+    xml.push_str("    </assessmentSection>\n  </testPart>\n");
+    xml.push_str("  <outcomeProcessing>\n    <setOutcomeValue identifier=\"SCORE\"><sum><testVariables variableIdentifier=\"SCORE\"/></sum></setOutcomeValue>\n  </outcomeProcessing>\n");
+    xml.push_str("</assessmentTest>\n");
+    xml
+}
+
+fn qti_manifest(quiz: &QuizTable, items: &[Item]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<manifest xmlns=\"http://www.imsglobal.org/xsd/imscp_v1p1\" identifier=\"manifest-{}\">\n",
+        escape_xml(&quiz._id)
+    );
+    xml.push_str("  <metadata>\n    <schema>QTIv2.1 Package</schema>\n    <schemaversion>1.0.0</schemaversion>\n  </metadata>\n");
+    xml.push_str("  <organizations/>\n  <resources>\n");
+    xml.push_str("    <resource identifier=\"test\" type=\"imsqti_test_xmlv2p1\" href=\"test.xml\">\n      <file href=\"test.xml\"/>\n");
+    for item in items {
+        xml.push_str(&format!(
+            "      <dependency identifierref=\"{}\"/>\n",
+            item.name
+        ));
+    }
+    xml.push_str("    </resource>\n");
+    for item in items {
+        xml.push_str(&format!(
+            "    <resource identifier=\"{}\" type=\"imsqti_item_xmlv2p1\" href=\"items/{}.xml\">\n      <file href=\"items/{}.xml\"/>\n    </resource>\n",
+            item.name, item.name, item.name
+        ));
+    }
+    xml.push_str("  </resources>\n</manifest>\n");
+    xml
+}
+
+fn qti_package(quiz: &QuizTable) -> Result<Vec<u8>, String> {
+    let items = items(quiz);
+    let mut files = vec![
+        ("imsmanifest.xml".to_string(), qti_manifest(quiz, &items)),
+        ("test.xml".to_string(), qti_test(quiz, &items)),
+    ];
+    for item in &items {
+        files.push((format!("items/{}.xml", item.name), qti_item(item, quiz)));
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+    for (name, xml) in files {
+        zip.start_file(name, options)
+            .map_err(|err| err.to_string())?;
+        zip.write_all(xml.as_bytes())
+            .map_err(|err| err.to_string())?;
+    }
+
+    Ok(zip.finish().map_err(|err| err.to_string())?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Scoring;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    fn quiz() -> QuizTable {
+        QuizTable {
+            _id: "quiz".to_string(),
+            faculty_id: "faculty".to_string(),
+            questions: vec![
+                vec![
+                    "What is 2 + 2?".to_string(),
+                    "3".to_string(),
+                    "4".to_string(),
+                    "5".to_string(),
+                ],
+                vec![
+                    "Which are <b>prime</b>?".to_string(),
+                    "2".to_string(),
+                    "4".to_string(),
+                    "5".to_string(),
+                    "9".to_string(),
+                ],
+            ],
+            answers: vec![1, 0],
+            shuffle_options: true,
+            multi_select: HashMap::from([("1".to_string(), vec![0, 2])]),
+            scoring: Scoring {
+                weights: vec![2.0],
+                negative_marking: 0.25,
+                ..Default::default()
+            },
+            feedback: vec!["Count: 1, 2, 3, 4.".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gift() {
+        let text = gift(&quiz());
+        assert!(text.starts_with("$CATEGORY: Quiz quiz\n"));
+        assert!(text.contains(
+            "::Q1::What is 2 + 2? {\n\t~%-25%3\n\t=4\n\t~%-25%5\n\t####Count\\: 1, 2, 3, 4.\n}\n"
+        ));
+        assert!(text.contains("\t~%50%2\n\t~%-50%4\n\t~%50%5\n"));
+    }
+
+    #[test]
+    fn test_moodle_xml() {
+        let xml = moodle_xml(&quiz());
+        assert!(xml.contains("<defaultgrade>2</defaultgrade>"));
+        assert!(xml.contains("<single>false</single>"));
+        assert!(xml.contains("<text>Which are &amp;lt;b&amp;gt;prime&amp;lt;/b&amp;gt;?</text>"));
+        assert!(xml.contains("<answer fraction=\"100\" format=\"html\"><text>4</text>"));
+        assert!(xml.contains("<answer fraction=\"-25\" format=\"html\"><text>3</text>"));
+    }
+
+    #[test]
+    fn test_moodle_fractions_are_allowed() {
+        for correct in [3, 6] {
+            let mut quiz = quiz();
+            quiz.questions[1] = (0..8).map(|o| o.to_string()).collect();
+            quiz.multi_select = HashMap::from([("1".to_string(), (0..correct).collect())]);
+            quiz.scoring.negative_marking = 0.15;
+
+            let item = &items(&quiz)[1];
+            let fractions: Vec<f64> = (0..item.options.len())
+                .map(|o| item.fraction(o, &quiz))
+                .collect();
+            assert!(fractions
+                .iter()
+                .all(|f| MOODLE_FRACTIONS.contains(&f.abs())));
+            let total: f64 = fractions.iter().filter(|f| **f > 0.0).sum();
+            assert!(
+                (total - 100.0).abs() < 0.001,
+                "{} correct: {}",
+                correct,
+                total
+            );
+        }
+
+        let quiz = QuizTable {
+            scoring: Scoring {
+                negative_marking: 0.15,
+                ..Default::default()
+            },
+            ..quiz()
+        };
+        assert_eq!(items(&quiz)[0].fraction(0, &quiz), -14.28571);
+    }
+
+    #[test]
+    fn test_qti_package() {
+        let bytes = export_quiz(&quiz(), QuizFormat::Qti).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut read = |name: &str| {
+            let mut text = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+
+        let manifest = read("imsmanifest.xml");
+        assert!(manifest.contains("href=\"items/Q2.xml\""));
+        assert!(read("test.xml").contains("<assessmentItemRef identifier=\"Q1\""));
+
+        let item = read("items/Q2.xml");
+        assert!(item.contains("cardinality=\"multiple\""));
+        assert!(item.contains("<value>C0</value>\n      <value>C2</value>"));
+        assert!(item.contains("maxChoices=\"0\""));
+        assert!(read("items/Q1.xml").contains("<modalFeedback"));
+    }
+}
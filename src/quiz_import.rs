@@ -0,0 +1,751 @@
+//! Reads multiple choice questions from GIFT, Moodle XML, IMS QTI 2.1 and
+//! CSV files. A file that cannot be read at all is an error; a question that
+//! cannot be used is reported as an `ItemError` and the rest still imported.
+
+use std::io::{Cursor, Read};
+
+use zip::ZipArchive;
+
+use crate::deck_io::strip_html;
+use crate::model::{ItemError, MAX_QUESTION_OPTIONS};
+use crate::quiz_export::{QuizFormat, CSV_COLUMNS};
+
+/// A question read from a file, numbered by its position there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedQuestion {
+    pub item: usize,
+    pub question: Vec<String>,
+    pub answers: Vec<i32>,
+    pub feedback: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedQuestions {
+    pub questions: Vec<ImportedQuestion>,
+    pub errors: Vec<ItemError>,
+}
+
+impl ParsedQuestions {
+    fn push(&mut self, item: usize, parsed: Result<Question, String>) {
+        match parsed.and_then(Question::validate) {
+            Ok(q) => self.questions.push(ImportedQuestion {
+                item,
+                question: std::iter::once(q.text).chain(q.options).collect(),
+                answers: q.answers.into_iter().map(|a| a as i32).collect(),
+                feedback: q.feedback,
+            }),
+            Err(message) => self.errors.push(ItemError { item, message }),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Question {
+    text: String,
+    options: Vec<String>,
+    answers: Vec<usize>,
+    feedback: Option<String>,
+}
+
+impl Question {
+    fn validate(mut self) -> Result<Question, String> {
+        self.text = self.text.trim().to_string();
+        self.answers.sort_unstable();
+        self.answers.dedup();
+        self.feedback = self.feedback.filter(|f| !f.trim().is_empty());
+
+        if self.text.is_empty() {
+            return Err("Question has no text".to_string());
+        }
+        if self.options.len() < 2 {
+            return Err("Question needs at least two options".to_string());
+        }
+        if self.options.len() > MAX_QUESTION_OPTIONS {
+            return Err(format!(
+                "Question has more than {} options",
+                MAX_QUESTION_OPTIONS
+            ));
+        }
+        if self.answers.is_empty() {
+            return Err("Question has no correct option".to_string());
+        }
+        if self.answers.iter().any(|a| *a >= self.options.len()) {
+            return Err("Correct option is out of range".to_string());
+        }
+        Ok(self)
+    }
+}
+
+pub fn import_questions(bytes: &[u8], format: QuizFormat) -> Result<ParsedQuestions, String> {
+    match format {
+        QuizFormat::Gift => Ok(parse_gift(&text(bytes)?)),
+        QuizFormat::MoodleXml => parse_moodle_xml(&text(bytes)?),
+        QuizFormat::Qti => parse_qti(bytes),
+        QuizFormat::Csv => parse_csv(bytes),
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<String, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "File is not UTF-8 text".to_string())?;
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Key under which two questions count as the same: the text and the set of
+/// options, ignoring case, spacing and option order.
+pub fn question_key(question: &[String]) -> String {
+    let normalise = |s: &String| {
+        s.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    let mut options: Vec<String> = question.iter().skip(1).map(normalise).collect();
+    options.sort();
+    let text = question.first().map(normalise).unwrap_or_default();
+    format!("{}\x1f{}", text, options.join("\x1f"))
+}
+
+/// A GIFT character and whether it was escaped with `\`.
+type GiftChar = (char, bool);
+
+fn gift_chars(text: &str) -> Vec<GiftChar> {
+    let mut chars = Vec::with_capacity(text.len());
+    let mut iter = text.chars();
+    while let Some(c) = iter.next() {
+        match (c, iter.clone().next()) {
+            ('\\', Some('n')) => {
+                iter.next();
+                chars.push(('\n', true));
+            }
+            ('\\', Some(next)) => {
+                iter.next();
+                chars.push((next, true));
+            }
+            _ => chars.push((c, false)),
+        }
+    }
+    chars
+}
+
+fn gift_text(chars: &[GiftChar]) -> String {
+    let text: String = chars.iter().map(|(c, _)| c).collect();
+    let text = text.trim();
+    // An optional `[html]`, `[moodle]`, `[plain]` or `[markdown]` marker
+    // gives the text format.
+    for marker in ["[html]", "[moodle]", "[plain]", "[markdown]"] {
+        if let Some(rest) = text.strip_prefix(marker) {
+            return match marker {
+                "[html]" | "[moodle]" => strip_html(rest),
+                _ => rest.trim().to_string(),
+            };
+        }
+    }
+    text.to_string()
+}
+
+fn find_unescaped(chars: &[GiftChar], from: usize, target: char) -> Option<usize> {
+    chars[from..]
+        .iter()
+        .position(|(c, escaped)| *c == target && !escaped)
+        .map(|i| from + i)
+}
+
+fn starts_with_unescaped(chars: &[GiftChar], at: usize, pattern: &str) -> bool {
+    pattern
+        .chars()
+        .enumerate()
+        .all(|(i, p)| chars.get(at + i) == Some(&(p, false)))
+}
+
+fn parse_gift(text: &str) -> ParsedQuestions {
+    let mut parsed = ParsedQuestions::default();
+
+    // Questions are separated by blank lines; comments and category
+    // commands take whole lines.
+    let mut blocks: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("//") || trimmed.starts_with("$CATEGORY:") {
+            continue;
+        }
+        if trimmed.is_empty() {
+            if !current.trim().is_empty() {
+                blocks.push(std::mem::take(&mut current));
+            }
+            current.clear();
+        } else {
+            current.push_str(line);
+            current.push('\n');
+        }
+    }
+    if !current.trim().is_empty() {
+        blocks.push(current);
+    }
+
+    for (i, block) in blocks.iter().enumerate() {
+        parsed.push(i + 1, parse_gift_question(block));
+    }
+    parsed
+}
+
+fn parse_gift_question(block: &str) -> Result<Question, String> {
+    let mut chars = gift_chars(block.trim());
+
+    // `::title::` is optional and not kept.
+    if starts_with_unescaped(&chars, 0, "::") {
+        let end = (2..chars.len())
+            .find(|i| starts_with_unescaped(&chars, *i, "::"))
+            .ok_or("Unclosed question title")?;
+        chars.drain(..end + 2);
+    }
+
+    let open = find_unescaped(&chars, 0, '{').ok_or("Question has no answer block")?;
+    let close = find_unescaped(&chars, open, '}').ok_or("Unclosed answer block")?;
+    let before = gift_text(&chars[..open]);
+    let after = gift_text(&chars[close + 1..]);
+    let block = &chars[open + 1..close];
+
+    // Text after the answers makes a missing-word question.
+    let text = if after.is_empty() {
+        before
+    } else {
+        format!("{} _____ {}", before, after)
+    };
+
+    if block.iter().find(|(c, _)| !c.is_whitespace()) == Some(&('#', false)) {
+        return Err("Numerical questions are not supported".to_string());
+    }
+    let answers = gift_text(block);
+    let answers = answers.split('#').next().unwrap_or("").trim();
+    if answers.is_empty() {
+        return Err("Essay questions are not supported".to_string());
+    }
+    match answers.to_uppercase().as_str() {
+        "T" | "TRUE" | "F" | "FALSE" => {
+            return Ok(Question {
+                text,
+                options: vec!["True".to_string(), "False".to_string()],
+                answers: vec![if answers.starts_with(['T', 't']) {
+                    0
+                } else {
+                    1
+                }],
+                feedback: gift_general_feedback(block),
+            })
+        }
+        _ => {}
+    }
+
+    let mut question = Question {
+        text,
+        feedback: gift_general_feedback(block),
+        ..Default::default()
+    };
+    let mut weights = Vec::new();
+    let mut any_wrong = false;
+    let mut i = 0;
+    while i < block.len() {
+        if starts_with_unescaped(block, i, "####") {
+            break;
+        }
+        let marker = match block[i] {
+            ('=', false) => '=',
+            ('~', false) => '~',
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let start = i + 1;
+        let mut end = start;
+        while end < block.len()
+            && !matches!(block[end], ('=', false) | ('~', false))
+            && !starts_with_unescaped(block, end, "####")
+        {
+            end += 1;
+        }
+        i = end;
+
+        // Per-answer feedback after `#` is dropped.
+        let answer = &block[start..end];
+        let answer = match find_unescaped(answer, 0, '#') {
+            Some(hash) => &answer[..hash],
+            None => answer,
+        };
+        if (0..answer.len()).any(|j| starts_with_unescaped(answer, j, "->")) {
+            return Err("Matching questions are not supported".to_string());
+        }
+
+        let mut answer = gift_text(answer);
+        let mut weight = None;
+        if let Some(rest) = answer.strip_prefix('%') {
+            let (w, rest) = rest.split_once('%').ok_or("Unclosed answer weight")?;
+            weight = Some(
+                w.trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid answer weight {:?}", w))?,
+            );
+            answer = rest.trim().to_string();
+        }
+
+        weights.push(weight.unwrap_or(if marker == '=' { 100.0 } else { 0.0 }));
+        any_wrong |= marker == '~';
+        question.options.push(answer);
+    }
+
+    if !any_wrong {
+        return Err("Short answer questions are not supported".to_string());
+    }
+
+    // With a full-marks answer the others are at most partly right. Without
+    // one, the marks are split between the correct answers of a
+    // multiple-answer question.
+    let full_marks = weights.iter().any(|w| *w >= 100.0);
+    question.answers = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| if full_marks { **w >= 100.0 } else { **w > 0.0 })
+        .map(|(o, _)| o)
+        .collect();
+    Ok(question)
+}
+
+fn gift_general_feedback(block: &[GiftChar]) -> Option<String> {
+    (0..block.len())
+        .find(|i| starts_with_unescaped(block, *i, "####"))
+        .map(|i| gift_text(&block[i + 4..]))
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+/// All text under `node`, skipping subtrees of elements named in `skip`.
+fn node_text(node: roxmltree::Node, skip: &[&str]) -> String {
+    let mut text = String::new();
+    for n in node.descendants() {
+        if n.ancestors()
+            .take_while(|a| *a != node)
+            .any(|a| skip.contains(&a.tag_name().name()))
+        {
+            continue;
+        }
+        if let Some(t) = n.text().filter(|_| n.is_text()) {
+            text.push_str(t);
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The `<text>` of a Moodle field, which may itself hold HTML.
+fn moodle_text(field: Option<roxmltree::Node>) -> String {
+    let Some(field) = field else {
+        return String::new();
+    };
+    let text = child(field, "text").and_then(|t| t.text()).unwrap_or("");
+    match field.attribute("format") {
+        Some("plain_text") | Some("markdown") => text.trim().to_string(),
+        _ => strip_html(text),
+    }
+}
+
+fn parse_moodle_xml(xml: &str) -> Result<ParsedQuestions, String> {
+    let document = roxmltree::Document::parse(xml).map_err(|err| err.to_string())?;
+    let root = document.root_element();
+    if !root.has_tag_name("quiz") {
+        return Err("Not a Moodle XML quiz".to_string());
+    }
+
+    let mut parsed = ParsedQuestions::default();
+    let questions = root
+        .children()
+        .filter(|n| n.has_tag_name("question") && n.attribute("type") != Some("category"));
+    for (i, question) in questions.enumerate() {
+        parsed.push(i + 1, parse_moodle_question(question));
+    }
+    Ok(parsed)
+}
+
+fn parse_moodle_question(node: roxmltree::Node) -> Result<Question, String> {
+    let kind = node.attribute("type").unwrap_or("");
+    if kind != "multichoice" && kind != "truefalse" {
+        return Err(format!("{} questions are not supported", kind));
+    }
+
+    let mut question = Question {
+        text: moodle_text(child(node, "questiontext")),
+        feedback: Some(moodle_text(child(node, "generalfeedback"))),
+        ..Default::default()
+    };
+
+    let mut fractions = Vec::new();
+    for answer in node.children().filter(|n| n.has_tag_name("answer")) {
+        let fraction = answer
+            .attribute("fraction")
+            .unwrap_or("0")
+            .parse::<f64>()
+            .map_err(|_| "Invalid answer fraction".to_string())?;
+        fractions.push(fraction);
+        question.options.push(moodle_text(Some(answer)));
+    }
+
+    // Single-answer questions may give part marks to wrong options; only the
+    // best ones are correct.
+    let single = kind == "truefalse"
+        || child(node, "single").and_then(|s| s.text()).map(str::trim) != Some("false");
+    let best = fractions.iter().copied().fold(0.0, f64::max);
+    question.answers = fractions
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| {
+            if single {
+                **f == best && best > 0.0
+            } else {
+                **f > 0.0
+            }
+        })
+        .map(|(o, _)| o)
+        .collect();
+
+    if kind == "truefalse" {
+        for option in &mut question.options {
+            *option = match option.to_lowercase().as_str() {
+                "true" => "True".to_string(),
+                "false" => "False".to_string(),
+                _ => std::mem::take(option),
+            };
+        }
+    }
+    Ok(question)
+}
+
+/// A QTI package is a zip with a manifest listing its items; a single item
+/// file is accepted too.
+fn parse_qti(bytes: &[u8]) -> Result<ParsedQuestions, String> {
+    if !bytes.starts_with(b"PK") {
+        let mut parsed = ParsedQuestions::default();
+        parsed.push(1, parse_qti_item(&text(bytes)?));
+        return Ok(parsed);
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|err| err.to_string())?;
+    let mut read = |name: &str| -> Result<String, String> {
+        let mut text = String::new();
+        archive
+            .by_name(name)
+            .map_err(|err| format!("{}: {}", name, err))?
+            .read_to_string(&mut text)
+            .map_err(|err| format!("{}: {}", name, err))?;
+        Ok(text)
+    };
+
+    let manifest = read("imsmanifest.xml")?;
+    let manifest = roxmltree::Document::parse(&manifest).map_err(|err| err.to_string())?;
+    let hrefs: Vec<String> = manifest
+        .descendants()
+        .filter(|n| n.has_tag_name("resource"))
+        .filter(|n| {
+            n.attribute("type")
+                .is_some_and(|t| t.starts_with("imsqti_item_xmlv2p"))
+        })
+        .filter_map(|n| n.attribute("href").map(str::to_string))
+        .collect();
+    if hrefs.is_empty() {
+        return Err("Manifest lists no QTI 2.1 items".to_string());
+    }
+
+    let mut parsed = ParsedQuestions::default();
+    for (i, href) in hrefs.iter().enumerate() {
+        parsed.push(i + 1, read(href).and_then(|xml| parse_qti_item(&xml)));
+    }
+    Ok(parsed)
+}
+
+fn parse_qti_item(xml: &str) -> Result<Question, String> {
+    let document = roxmltree::Document::parse(xml).map_err(|err| err.to_string())?;
+    let item = document.root_element();
+    if !item.has_tag_name("assessmentItem") {
+        return Err("Not a QTI assessment item".to_string());
+    }
+
+    let interaction = item
+        .descendants()
+        .find(|n| n.has_tag_name("choiceInteraction"))
+        .ok_or("Only choice interactions are supported")?;
+    let response = interaction.attribute("responseIdentifier").unwrap_or("");
+
+    // The question is the interaction's prompt, or else the item body's text
+    // around the interaction.
+    let text = match child(interaction, "prompt") {
+        Some(prompt) => node_text(prompt, &[]),
+        None => child(item, "itemBody")
+            .map(|body| node_text(body, &["choiceInteraction"]))
+            .unwrap_or_default(),
+    };
+
+    let choices: Vec<(&str, String)> = interaction
+        .descendants()
+        .filter(|n| n.has_tag_name("simpleChoice"))
+        .map(|n| (n.attribute("identifier").unwrap_or(""), node_text(n, &[])))
+        .collect();
+
+    let declaration = item
+        .children()
+        .find(|n| {
+            n.has_tag_name("responseDeclaration") && n.attribute("identifier") == Some(response)
+        })
+        .ok_or("Item has no response declaration")?;
+    let mut correct: Vec<&str> = child(declaration, "correctResponse")
+        .map(|c| {
+            c.children()
+                .filter(|v| v.has_tag_name("value"))
+                .filter_map(|v| v.text().map(str::trim))
+                .collect()
+        })
+        .unwrap_or_default();
+    if correct.is_empty() {
+        // Without a correct response, options mapped to a positive score are
+        // taken as correct.
+        correct = declaration
+            .descendants()
+            .filter(|n| n.has_tag_name("mapEntry"))
+            .filter(|n| {
+                n.attribute("mappedValue")
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .is_some_and(|v| v > 0.0)
+            })
+            .filter_map(|n| n.attribute("mapKey"))
+            .collect();
+    }
+
+    Ok(Question {
+        text,
+        answers: choices
+            .iter()
+            .enumerate()
+            .filter(|(_, (id, _))| correct.contains(id))
+            .map(|(o, _)| o)
+            .collect(),
+        options: choices.into_iter().map(|(_, text)| text).collect(),
+        feedback: item
+            .children()
+            .find(|n| n.has_tag_name("modalFeedback"))
+            .map(|n| node_text(n, &[])),
+    })
+}
+
+/// Needs a header row naming the `question` and `answer` columns; any column
+/// other than those and `feedback` holds an option. Answers are option
+/// letters (`A`), 1-based numbers or option texts, several joined with `;`.
+fn parse_csv(bytes: &[u8]) -> Result<ParsedQuestions, String> {
+    let delimiter = match text(bytes)?.lines().next() {
+        Some(header) if header.contains('\t') => b'\t',
+        _ => b',',
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(bytes.strip_prefix("\u{feff}".as_bytes()).unwrap_or(bytes));
+
+    let header: Vec<String> = reader
+        .headers()
+        .map_err(|err| err.to_string())?
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let (Some(question_col), Some(answer_col)) = (column(CSV_COLUMNS[0]), column(CSV_COLUMNS[1]))
+    else {
+        return Err("CSV needs question and answer columns".to_string());
+    };
+    let feedback_col = column(CSV_COLUMNS[2]);
+    let option_cols: Vec<usize> = (0..header.len())
+        .filter(|c| *c != question_col && *c != answer_col && Some(*c) != feedback_col)
+        .collect();
+
+    let mut parsed = ParsedQuestions::default();
+    for (i, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => {
+                parsed.push(i + 1, Err(error.to_string()));
+                continue;
+            }
+        };
+        let field = |c: usize| record.get(c).unwrap_or("").trim().to_string();
+        if record.iter().all(|f| f.trim().is_empty()) {
+            continue;
+        }
+
+        let options: Vec<String> = option_cols
+            .iter()
+            .map(|c| field(*c))
+            .filter(|o| !o.is_empty())
+            .collect();
+        let answers = field(answer_col)
+            .split([';', '|'])
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(|a| csv_answer(a, &options))
+            .collect::<Result<Vec<usize>, String>>();
+
+        parsed.push(
+            i + 1,
+            answers.map(|answers| Question {
+                text: field(question_col),
+                options,
+                answers,
+                feedback: feedback_col.map(field),
+            }),
+        );
+    }
+    Ok(parsed)
+}
+
+fn csv_answer(answer: &str, options: &[String]) -> Result<usize, String> {
+    if let Some(o) = options.iter().position(|o| o == answer) {
+        return Ok(o);
+    }
+    if let Ok(n) = answer.parse::<usize>() {
+        return n
+            .checked_sub(1)
+            .ok_or_else(|| "Answer numbers start at 1".to_string());
+    }
+    match answer.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Ok((c.to_ascii_uppercase() - b'A') as usize),
+        _ => Err(format!("Answer {:?} matches no option", answer)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{QuizTable, Scoring};
+    use crate::quiz_export::export_quiz;
+    use std::collections::HashMap;
+
+    fn quiz() -> QuizTable {
+        QuizTable {
+            _id: "quiz".to_string(),
+            faculty_id: "faculty".to_string(),
+            questions: vec![
+                vec![
+                    "What is {2 + 2}?".to_string(),
+                    "3".to_string(),
+                    "4".to_string(),
+                    "5 = five".to_string(),
+                ],
+                vec![
+                    "Which are <b>prime</b>?".to_string(),
+                    "2".to_string(),
+                    "4".to_string(),
+                    "5".to_string(),
+                ],
+            ],
+            answers: vec![1, 0],
+            multi_select: HashMap::from([("1".to_string(), vec![0, 2])]),
+            scoring: Scoring {
+                negative_marking: 0.25,
+                ..Default::default()
+            },
+            feedback: vec!["Count: 1, 2, 3, 4.".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn assert_round_trip(format: QuizFormat) {
+        let quiz = quiz();
+        let bytes = export_quiz(&quiz, format).unwrap();
+        let parsed = import_questions(&bytes, format).unwrap();
+
+        assert_eq!(parsed.errors, Vec::new(), "{:?}", format);
+        assert_eq!(parsed.questions.len(), 2);
+        assert_eq!(parsed.questions[0].question, quiz.questions[0]);
+        assert_eq!(parsed.questions[0].answers, vec![1]);
+        assert_eq!(
+            parsed.questions[0].feedback.as_deref(),
+            Some("Count: 1, 2, 3, 4.")
+        );
+        assert_eq!(parsed.questions[1].question, quiz.questions[1]);
+        assert_eq!(parsed.questions[1].answers, vec![0, 2]);
+        assert_eq!(parsed.questions[1].feedback, None);
+    }
+
+    #[test]
+    fn test_gift_round_trip() {
+        assert_round_trip(QuizFormat::Gift);
+    }
+
+    #[test]
+    fn test_moodle_xml_round_trip() {
+        assert_round_trip(QuizFormat::MoodleXml);
+    }
+
+    #[test]
+    fn test_qti_round_trip() {
+        assert_round_trip(QuizFormat::Qti);
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        assert_round_trip(QuizFormat::Csv);
+    }
+
+    #[test]
+    fn test_gift_reports_unsupported_items() {
+        let gift = "// comment\n::T1:: The sky is blue. {T}\n\nWrite an essay. {}\n\n\
+                    Match. { =a -> 1 =b -> 2 }\n\nWho? { =Ann ~Bob ~Cy }";
+        let parsed = parse_gift(gift);
+
+        let items: Vec<usize> = parsed.questions.iter().map(|q| q.item).collect();
+        assert_eq!(items, vec![1, 4]);
+        assert_eq!(
+            parsed.questions[0].question,
+            vec!["The sky is blue.", "True", "False"]
+        );
+        assert_eq!(parsed.questions[1].answers, vec![0]);
+
+        let errors: Vec<usize> = parsed.errors.iter().map(|e| e.item).collect();
+        assert_eq!(errors, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_gift_partial_credit_is_not_correct() {
+        let gift = "Capital? { =Paris ~%50%Lyon ~Rome }\n\n\
+                    Primes? { ~%50%2 ~%50%3 ~%-100%4 }";
+        let parsed = parse_gift(gift);
+        assert_eq!(parsed.questions[0].answers, vec![0]);
+        assert_eq!(parsed.questions[1].answers, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_too_many_options() {
+        let options: Vec<String> = (0..30).map(|o| o.to_string()).collect();
+        let csv = format!("question,answer\nPick,1,{}\n", options.join(","));
+        let parsed = parse_csv(csv.as_bytes()).unwrap();
+        assert!(parsed.questions.is_empty());
+        assert_eq!(parsed.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_csv_answers() {
+        let csv = "question,a,b,c,answer\nPick one,x,y,z,B\nPick two,x,y,z,1;z\nBad,x,y,z,Q\n";
+        let parsed = parse_csv(csv.as_bytes()).unwrap();
+        assert_eq!(parsed.questions[0].answers, vec![1]);
+        assert_eq!(parsed.questions[1].answers, vec![0, 2]);
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(parsed.errors[0].item, 3);
+    }
+
+    #[test]
+    fn test_question_key_ignores_case_spacing_and_order() {
+        let a: Vec<String> = ["What  is 2+2?", "4", "3"].map(String::from).to_vec();
+        let b: Vec<String> = ["what is 2+2?", "3", "4"].map(String::from).to_vec();
+        assert_eq!(question_key(&a), question_key(&b));
+    }
+}
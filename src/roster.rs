@@ -0,0 +1,205 @@
+//! Class rosters uploaded as CSV with a header row naming the columns
+//! `username`, `display name`, `email` and `class`. Only `username` is
+//! required. A `class` naming one of the uploader's classes also enrolls the
+//! student in it.
+
+use std::collections::HashSet;
+
+use crate::model::{Class, ItemError};
+use crate::validation::{is_email, username_errors};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RosterRow {
+    /// Position in the file, counting from 1 after the header.
+    pub row: usize,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    pub class: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedRoster {
+    pub rows: Vec<RosterRow>,
+    pub errors: Vec<ItemError>,
+}
+
+fn column(header: &str) -> Option<usize> {
+    let name: String = header
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect();
+    match name.as_str() {
+        "username" | "user" | "login" => Some(0),
+        "displayname" | "name" | "fullname" => Some(1),
+        "email" | "emailaddress" | "mail" => Some(2),
+        "class" | "cohort" | "section" => Some(3),
+        _ => None,
+    }
+}
+
+pub fn parse_roster(bytes: &[u8]) -> Result<ParsedRoster, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(bytes);
+
+    let mut columns = [None; 4];
+    for (i, header) in reader
+        .headers()
+        .map_err(|err| err.to_string())?
+        .iter()
+        .enumerate()
+    {
+        if let Some(c) = column(header) {
+            columns[c].get_or_insert(i);
+        }
+    }
+    if columns[0].is_none() {
+        return Err("The roster needs a username column".to_string());
+    }
+
+    let mut parsed = ParsedRoster::default();
+    let mut seen = HashSet::new();
+    for (i, record) in reader.records().enumerate() {
+        let row = i + 1;
+        let record = match record {
+            Ok(s) => s,
+            Err(err) => {
+                parsed.errors.push(ItemError {
+                    item: row,
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let field = |c: usize| {
+            columns[c]
+                .and_then(|i| record.get(i))
+                .filter(|f| !f.is_empty())
+                .map(str::to_string)
+        };
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+
+        let username = field(0).unwrap_or_default();
+        let email = field(2);
+        let mut problems: Vec<String> = username_errors(&username)
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        if email.as_deref().is_some_and(|e| !is_email(e)) {
+            problems.push("Email address is not valid".to_string());
+        }
+        if problems.is_empty() && !seen.insert(username.to_lowercase()) {
+            problems.push("Username appears earlier in the roster".to_string());
+        }
+        if !problems.is_empty() {
+            parsed.errors.push(ItemError {
+                item: row,
+                message: problems.join("; "),
+            });
+            continue;
+        }
+
+        parsed.rows.push(RosterRow {
+            row,
+            username,
+            display_name: field(1),
+            email,
+            class: field(3),
+        });
+    }
+    Ok(parsed)
+}
+
+/// Whether a roster's class label names `class`: either its id, or its
+/// course and section such as `CS101 A`.
+pub fn class_matches(class: &Class, label: &str) -> bool {
+    let label = label.trim();
+    let name = format!("{} {}", class.course, class.section);
+    label == class._id || label.eq_ignore_ascii_case(name.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_columns_in_any_order() {
+        let csv = "Email,Username,Display Name,Class\n\
+                   ada@example.edu,ada,Ada Lovelace,CS101\n\
+                   ,alan,,\n";
+        let parsed = parse_roster(csv.as_bytes()).unwrap();
+        assert!(parsed.errors.is_empty());
+        assert_eq!(
+            parsed.rows,
+            vec![
+                RosterRow {
+                    row: 1,
+                    username: "ada".to_string(),
+                    display_name: Some("Ada Lovelace".to_string()),
+                    email: Some("ada@example.edu".to_string()),
+                    class: Some("CS101".to_string()),
+                },
+                RosterRow {
+                    row: 2,
+                    username: "alan".to_string(),
+                    display_name: None,
+                    email: None,
+                    class: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_bad_rows() {
+        let csv = "username,email\nok1,ok@example.edu\nx,\nok2,not-an-email\nOK1,\n\n";
+        let parsed = parse_roster(csv.as_bytes()).unwrap();
+        assert_eq!(parsed.rows.len(), 1);
+        let rows: Vec<usize> = parsed.errors.iter().map(|e| e.item).collect();
+        assert_eq!(rows, vec![2, 3, 4]);
+        assert_eq!(
+            parsed.errors[2].message,
+            "Username appears earlier in the roster"
+        );
+    }
+
+    #[test]
+    fn test_requires_username_column() {
+        assert!(parse_roster(b"name,email\nAda,ada@example.edu\n").is_err());
+    }
+
+    #[test]
+    fn test_bad_rows_do_not_stop_the_import() {
+        let mut csv = b"username,class\nok1,CS101\n".to_vec();
+        csv.extend_from_slice(b"bad\xff,CS101\nok2,CS101\n");
+        let parsed = parse_roster(&csv).unwrap();
+        let usernames: Vec<&str> = parsed.rows.iter().map(|r| r.username.as_str()).collect();
+        assert_eq!(usernames, vec!["ok1", "ok2"]);
+        assert_eq!(parsed.errors.len(), 1);
+        assert_eq!(parsed.errors[0].item, 2);
+    }
+
+    #[test]
+    fn test_class_labels() {
+        let class = Class {
+            _id: "class-1".to_string(),
+            faculty_id: "faculty".to_string(),
+            course: "CS101".to_string(),
+            section: "A".to_string(),
+            term: "Fall".to_string(),
+            students: Vec::new(),
+            quizzes: Vec::new(),
+            decks: Vec::new(),
+            created_at: chrono::Utc::now(),
+        };
+        assert!(class_matches(&class, "class-1"));
+        assert!(class_matches(&class, "cs101 a"));
+        assert!(!class_matches(&class, "CS101"));
+    }
+}
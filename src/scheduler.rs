@@ -0,0 +1,91 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::model::ReviewState;
+
+/// Lowest ease a card can fall to, as in SM-2.
+const MIN_EASE: f64 = 1.3;
+pub const START_EASE: f64 = 2.5;
+
+/// Schedules the next review of a card after it was recalled with `grade`,
+/// from 0 (blackout) to 5 (perfect), following the SM-2 algorithm.
+pub fn review(state: &mut ReviewState, grade: u8, now: DateTime<Utc>) {
+    let grade = grade.min(5) as f64;
+
+    if grade >= 3.0 {
+        state.interval_days = match state.repetitions {
+            0 => 1,
+            1 => 6,
+            _ => (state.interval_days as f64 * state.ease).round() as i64,
+        };
+        state.repetitions += 1;
+    } else {
+        if state.repetitions > 0 {
+            state.lapses += 1;
+        }
+        state.repetitions = 0;
+        state.interval_days = 1;
+    }
+
+    let miss = 5.0 - grade;
+    state.ease = (state.ease + 0.1 - miss * (0.08 + miss * 0.02)).max(MIN_EASE);
+    state.due = bson::DateTime::from_chrono(now + Duration::days(state.interval_days));
+    state.last_reviewed = Some(bson::DateTime::from_chrono(now));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_state() -> ReviewState {
+        ReviewState {
+            _id: "student:card".to_string(),
+            student_id: "student".to_string(),
+            card_id: "card".to_string(),
+            ease: START_EASE,
+            interval_days: 0,
+            repetitions: 0,
+            lapses: 0,
+            due: bson::DateTime::now(),
+            last_reviewed: None,
+        }
+    }
+
+    #[test]
+    fn test_intervals_grow_on_recall() {
+        let now = Utc::now();
+        let mut state = new_state();
+
+        review(&mut state, 4, now);
+        assert_eq!(state.interval_days, 1);
+        review(&mut state, 4, now);
+        assert_eq!(state.interval_days, 6);
+        review(&mut state, 4, now);
+        assert_eq!(state.interval_days, 15);
+        assert_eq!(
+            state.due.to_chrono().timestamp(),
+            (now + Duration::days(15)).timestamp()
+        );
+    }
+
+    #[test]
+    fn test_lapse_resets_interval() {
+        let now = Utc::now();
+        let mut state = new_state();
+
+        review(&mut state, 5, now);
+        review(&mut state, 5, now);
+        review(&mut state, 1, now);
+        assert_eq!(state.repetitions, 0);
+        assert_eq!(state.interval_days, 1);
+        assert_eq!(state.lapses, 1);
+    }
+
+    #[test]
+    fn test_ease_has_floor() {
+        let mut state = new_state();
+        for _ in 0..10 {
+            review(&mut state, 0, Utc::now());
+        }
+        assert_eq!(state.ease, MIN_EASE);
+    }
+}
@@ -0,0 +1,178 @@
+//! Slows down password guessing on `/login` and everywhere else a password
+//! is checked. Each username and each client address gets a few free
+//! failures, then must wait twice as long after every further one, and is
+//! locked out for a while once it fails too often.
+
+use bson::{doc, Document};
+use chrono::{DateTime, Duration, Utc};
+
+use crate::model::LoginFailures;
+
+const FREE_FAILURES: u32 = 3;
+const BASE_DELAY_SECS: i64 = 1;
+const MAX_DELAY_SECS: i64 = 300;
+/// Failures older than this are forgotten.
+const FAILURE_WINDOW_MINUTES: i64 = 60;
+const LOCKOUT_MINUTES: i64 = 30;
+
+/// Failures before a username is locked.
+pub const USER_LOCKOUT_THRESHOLD: u32 = 10;
+/// Failures before a client address is locked; higher than for usernames
+/// since many users can share an address.
+pub const IP_LOCKOUT_THRESHOLD: u32 = 50;
+
+/// Usernames are unique regardless of case, so every casing of one shares
+/// its failures.
+pub fn user_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Counts password reset requests for `key` apart from its failed sign-ins.
+pub fn reset_key(key: &str) -> String {
+    format!("reset:{}", key)
+}
+
+impl LoginFailures {
+    /// How long until another sign-in may be tried, if it must wait.
+    pub fn wait(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let allowed_at = match self.locked_until {
+            Some(until) if until.to_chrono() > now => until.to_chrono(),
+            Some(_) => return None,
+            None if self.failures <= FREE_FAILURES => return None,
+            None => {
+                let doublings = (self.failures - FREE_FAILURES - 1).min(16);
+                let delay = (BASE_DELAY_SECS << doublings).min(MAX_DELAY_SECS);
+                self.last_failure.to_chrono() + Duration::seconds(delay)
+            }
+        };
+        (allowed_at > now).then(|| allowed_at - now)
+    }
+
+    /// Whether the record has reached `threshold` and is not locked yet.
+    pub fn should_lock(&self, threshold: u32) -> bool {
+        self.locked_until.is_none() && self.failures >= threshold
+    }
+}
+
+/// Matches the record for `key` if its failures should be forgotten before
+/// counting another: its lockout is over, or it was never locked and its
+/// last failure is older than the window.
+pub fn forget_filter(key: &str, now: DateTime<Utc>) -> Document {
+    let now_bson = bson::DateTime::from_chrono(now);
+    let stale = bson::DateTime::from_chrono(now - Duration::minutes(FAILURE_WINDOW_MINUTES));
+    doc! {
+        "_id": key,
+        "$or": [
+            { "locked_until": { "$lte": now_bson } },
+            { "locked_until": null, "last_failure": { "$lt": stale } },
+        ],
+    }
+}
+
+/// Counts one failure, creating the record if there is none.
+pub fn failure_update(now: DateTime<Utc>) -> Document {
+    let forget_at = now + Duration::minutes(FAILURE_WINDOW_MINUTES);
+    doc! {
+        "$inc": { "failures": 1 },
+        "$set": { "last_failure": bson::DateTime::from_chrono(now) },
+        "$max": { "expires_at": bson::DateTime::from_chrono(forget_at) },
+    }
+}
+
+/// Locks the record for `key` unless another failure already has.
+pub fn lock_filter(key: &str) -> Document {
+    doc! { "_id": key, "locked_until": null }
+}
+
+pub fn lock_update(now: DateTime<Utc>) -> (Document, bson::DateTime) {
+    let until = bson::DateTime::from_chrono(now + Duration::minutes(LOCKOUT_MINUTES));
+    let update = doc! {
+        "$set": { "locked_until": until },
+        "$max": { "expires_at": until },
+    };
+    (update, until)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Now, at the millisecond precision records are stored with.
+    fn now() -> DateTime<Utc> {
+        bson::DateTime::now().to_chrono()
+    }
+
+    fn failed(times: u32, now: DateTime<Utc>) -> LoginFailures {
+        LoginFailures {
+            _id: user_key("ada"),
+            failures: times,
+            last_failure: bson::DateTime::from_chrono(now),
+            locked_until: None,
+            expires_at: bson::DateTime::from_chrono(now),
+        }
+    }
+
+    #[test]
+    fn test_free_failures_do_not_wait() {
+        let now = now();
+        assert_eq!(failed(FREE_FAILURES, now).wait(now), None);
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let now = now();
+        assert_eq!(failed(4, now).wait(now), Some(Duration::seconds(1)));
+        assert_eq!(failed(5, now).wait(now), Some(Duration::seconds(2)));
+        assert_eq!(failed(7, now).wait(now), Some(Duration::seconds(8)));
+        assert_eq!(failed(6, now).wait(now + Duration::seconds(4)), None);
+    }
+
+    #[test]
+    fn test_lockout_after_threshold() {
+        let now = now();
+        assert!(!failed(USER_LOCKOUT_THRESHOLD - 1, now).should_lock(USER_LOCKOUT_THRESHOLD));
+
+        let mut record = failed(USER_LOCKOUT_THRESHOLD, now);
+        assert!(record.should_lock(USER_LOCKOUT_THRESHOLD));
+        let (_, until) = lock_update(now);
+        record.locked_until = Some(until);
+        // A locked record is not locked again by later failures.
+        assert!(!record.should_lock(USER_LOCKOUT_THRESHOLD));
+        assert_eq!(record.wait(now), Some(Duration::minutes(LOCKOUT_MINUTES)));
+
+        let later = now + Duration::minutes(LOCKOUT_MINUTES + 1);
+        assert_eq!(record.wait(later), None);
+    }
+
+    #[test]
+    fn test_every_casing_shares_a_counter() {
+        let now = now();
+        assert_eq!(user_key("Ada"), user_key("ada"));
+        assert_eq!(user_key("ADA"), user_key("ada"));
+        assert_eq!(
+            forget_filter(&user_key("ADA"), now),
+            forget_filter(&user_key("ada"), now)
+        );
+        assert_eq!(reset_key(&user_key("Ada")), reset_key(&user_key("ada")));
+    }
+
+    #[test]
+    fn test_old_failures_are_forgotten() {
+        let now = now();
+        let filter = forget_filter(&user_key("ada"), now);
+        let stale = &filter.get_array("$or").unwrap()[1];
+        let cutoff = stale
+            .as_document()
+            .and_then(|d| d.get_document("last_failure").ok())
+            .and_then(|d| d.get_datetime("$lt").ok())
+            .unwrap();
+        assert_eq!(
+            cutoff.to_chrono(),
+            now - Duration::minutes(FAILURE_WINDOW_MINUTES)
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! One-time tokens sent to users by email. Only a SHA-256 hash of each token
+//! is stored, so a leaked database cannot be used to redeem them.
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+const TOKEN_LENGTH: usize = 43;
+
+/// A fresh token and the hash to store for it.
+pub fn new_token() -> (String, String) {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.trim().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_unique_and_hashed() {
+        let (a, a_hash) = new_token();
+        let (b, _) = new_token();
+        assert_ne!(a, b);
+        assert_eq!(a.len(), TOKEN_LENGTH);
+        assert_ne!(a, a_hash);
+        assert_eq!(hash_token(&a), a_hash);
+        assert_eq!(hash_token(&format!(" {}\n", a)), a_hash);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::model::FieldError;
+
+const USERNAME_LENGTH: std::ops::RangeInclusive<usize> = 3..=32;
+const PASSWORD_LENGTH: std::ops::RangeInclusive<usize> = 8..=128;
+
+/// Usernames are 3 to 32 letters, digits, `.`, `_` or `-`, starting with a
+/// letter or digit.
+pub fn username_errors(username: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let mut error = |message: &str| {
+        errors.push(FieldError {
+            field: "username".to_string(),
+            message: message.to_string(),
+        })
+    };
+
+    if !USERNAME_LENGTH.contains(&username.chars().count()) {
+        error("Username must be 3 to 32 characters long");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        error("Username may only contain letters, digits, '.', '_' and '-'");
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        error("Username must start with a letter or digit");
+    }
+    errors
+}
+
+/// Passwords are 8 to 128 characters with at least one letter and one
+/// digit, and must not contain the username.
+pub fn password_errors(password: &str, username: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    let mut error = |message: &str| {
+        errors.push(FieldError {
+            field: "password".to_string(),
+            message: message.to_string(),
+        })
+    };
+
+    if !PASSWORD_LENGTH.contains(&password.chars().count()) {
+        error("Password must be 8 to 128 characters long");
+    }
+    if !password.chars().any(char::is_alphabetic) || !password.chars().any(|c| c.is_ascii_digit()) {
+        error("Password must contain a letter and a digit");
+    }
+    if !username.is_empty() && password.to_lowercase().contains(&username.to_lowercase()) {
+        error("Password must not contain the username");
+    }
+    errors
+}
+
+pub fn is_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+pub fn email_errors(email: Option<&str>) -> Vec<FieldError> {
+    match email {
+        Some(email) if !is_email(email) => vec![FieldError {
+            field: "email".to_string(),
+            message: "Email address is not valid".to_string(),
+        }],
+        _ => Vec::new(),
+    }
+}
+
+/// Every problem with a new account's credentials.
+pub fn credential_errors(username: &str, password: &str) -> Vec<FieldError> {
+    let mut errors = username_errors(username);
+    errors.extend(password_errors(password, username));
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(errors: Vec<FieldError>) -> Vec<String> {
+        errors.into_iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn test_valid_credentials() {
+        assert!(credential_errors("jane.doe-2", "correct horse 9").is_empty());
+    }
+
+    #[test]
+    fn test_username_rules() {
+        assert_eq!(username_errors("ab").len(), 1);
+        assert_eq!(username_errors(&"a".repeat(33)).len(), 1);
+        assert_eq!(
+            messages(username_errors("_jane doe")),
+            vec![
+                "Username may only contain letters, digits, '.', '_' and '-'",
+                "Username must start with a letter or digit",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_password_rules() {
+        assert_eq!(
+            messages(password_errors("short1", "jane")),
+            vec!["Password must be 8 to 128 characters long"]
+        );
+        assert_eq!(
+            messages(password_errors("onlyletters", "jane")),
+            vec!["Password must contain a letter and a digit"]
+        );
+        assert_eq!(
+            messages(password_errors("Jane12345", "jane")),
+            vec!["Password must not contain the username"]
+        );
+    }
+
+    #[test]
+    fn test_email_shape() {
+        assert!(is_email("ada@example.edu"));
+        assert!(!is_email("ada@example"));
+        assert!(!is_email("@example.edu"));
+        assert!(!is_email("ada lovelace@example.edu"));
+    }
+
+    #[test]
+    fn test_errors_name_their_field() {
+        let errors = credential_errors("x", "y");
+        assert!(errors.iter().any(|e| e.field == "username"));
+        assert!(errors.iter().any(|e| e.field == "password"));
+    }
+}
@@ -0,0 +1,193 @@
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use sha1::{Digest, Sha1};
+
+use crate::model::{Answer, QuizTable};
+
+/// Stands in for an option the question does not have, so an answer that
+/// picks one is marked wrong rather than unanswered.
+const NO_SUCH_OPTION: i8 = i8::MAX;
+
+/// The order a single attempt sees a quiz in. `question_order[i]` is the
+/// original index of the i-th displayed question and `option_orders[i][j]`
+/// the original index of its j-th displayed option.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuizVariant {
+    pub question_order: Vec<usize>,
+    pub option_orders: Vec<Vec<usize>>,
+}
+
+/// Builds the variant for `seed`. The same quiz and seed always give the
+/// same variant, so only the seed has to be kept with the attempt.
+pub fn build_variant(quiz: &QuizTable, seed: u32) -> QuizVariant {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed as u64);
+    let pool = quiz.questions.len();
+
+    let mut question_order: Vec<usize> = (0..pool).collect();
+    if let Some(n) = quiz.draw_count.filter(|n| *n < pool) {
+        question_order.shuffle(&mut rng);
+        question_order.truncate(n);
+        if !quiz.shuffle_questions {
+            question_order.sort_unstable();
+        }
+    } else if quiz.shuffle_questions {
+        question_order.shuffle(&mut rng);
+    }
+
+    let option_orders = question_order
+        .iter()
+        .map(|q| {
+            let mut options: Vec<usize> = (0..quiz.questions[*q].len().saturating_sub(1)).collect();
+            if quiz.shuffle_options {
+                options.shuffle(&mut rng);
+            }
+            options
+        })
+        .collect();
+
+    QuizVariant {
+        question_order,
+        option_orders,
+    }
+}
+
+/// Labels of the variants printed for paper exams.
+pub const PAPER_VARIANTS: [char; 4] = ['A', 'B', 'C', 'D'];
+
+/// Seed of printed variant `variant` of a quiz. Derived from the quiz id so a
+/// scanned sheet can be matched to its variant without anything stored.
+pub fn paper_seed(quiz_id: &str, variant: usize) -> u32 {
+    let digest = Sha1::digest(format!("{}:{}", quiz_id, variant).as_bytes());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+impl QuizVariant {
+    /// Questions in display order, each as `[question, options...]`.
+    pub fn render(&self, quiz: &QuizTable) -> Vec<Vec<String>> {
+        self.question_order
+            .iter()
+            .zip(&self.option_orders)
+            .map(|(q, options)| {
+                let question = &quiz.questions[*q];
+                let mut shown = vec![question[0].clone()];
+                shown.extend(options.iter().map(|o| question[o + 1].clone()));
+                shown
+            })
+            .collect()
+    }
+
+    /// Maps answers given against this variant back to the original question
+    /// and option order. Questions not drawn or not answered are `-1`, and
+    /// options the question does not have become [`NO_SUCH_OPTION`].
+    pub fn to_original(&self, ans: &[Answer], pool: usize) -> Vec<Answer> {
+        let mut original = vec![Answer::default(); pool];
+        for (i, a) in ans.iter().enumerate().take(self.question_order.len()) {
+            let options = &self.option_orders[i];
+            let remap = |a: &i8| {
+                options
+                    .get(*a as usize)
+                    .map_or(NO_SUCH_OPTION, |o| *o as i8)
+            };
+            original[self.question_order[i]] = match a {
+                Answer::Single(a) if *a >= 0 => Answer::Single(remap(a)),
+                Answer::Single(_) => Answer::default(),
+                Answer::Multiple(a) => {
+                    Answer::Multiple(a.iter().filter(|a| **a >= 0).map(remap).collect())
+                }
+            };
+        }
+        original
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiz(shuffle: bool, draw_count: Option<usize>) -> QuizTable {
+        let questions = (0..6)
+            .map(|q| {
+                let mut question = vec![format!("question {q}")];
+                question.extend((0..4).map(|o| format!("q{q} option {o}")));
+                question
+            })
+            .collect();
+
+        QuizTable {
+            _id: "quiz".to_string(),
+            faculty_id: "faculty".to_string(),
+            questions,
+            answers: vec![0, 1, 2, 3, 0, 1],
+            shuffle_questions: shuffle,
+            shuffle_options: shuffle,
+            draw_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_variant() {
+        let quiz = quiz(true, None);
+        assert_eq!(build_variant(&quiz, 42), build_variant(&quiz, 42));
+    }
+
+    #[test]
+    fn test_unshuffled_keeps_order() {
+        let quiz = quiz(false, None);
+        let variant = build_variant(&quiz, 7);
+        assert_eq!(variant.render(&quiz), quiz.questions);
+    }
+
+    #[test]
+    fn test_draw_count_from_pool() {
+        let quiz = quiz(false, Some(3));
+        let variant = build_variant(&quiz, 7);
+        assert_eq!(variant.question_order.len(), 3);
+        assert!(variant.question_order.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_answers_remapped_to_original() {
+        let quiz = quiz(true, Some(4));
+        let variant = build_variant(&quiz, 1234);
+        let rendered = variant.render(&quiz);
+
+        // Answer every displayed question with its correct option.
+        let ans: Vec<Answer> = variant
+            .question_order
+            .iter()
+            .enumerate()
+            .map(|(i, q)| {
+                let correct = &quiz.questions[*q][quiz.answers[*q] as usize + 1];
+                Answer::Single(rendered[i][1..].iter().position(|o| o == correct).unwrap() as i8)
+            })
+            .collect();
+
+        let original = variant.to_original(&ans, quiz.questions.len());
+        for (q, a) in original.iter().enumerate() {
+            if variant.question_order.contains(&q) {
+                assert_eq!(*a, Answer::Single(quiz.answers[q] as i8));
+            } else {
+                assert_eq!(*a, Answer::default());
+            }
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_answer_is_wrong() {
+        let quiz = quiz(false, None);
+        let variant = build_variant(&quiz, 7);
+
+        let original = variant.to_original(&[Answer::Single(9)], quiz.questions.len());
+        assert_eq!(original[0], Answer::Single(NO_SUCH_OPTION));
+        assert_eq!(crate::grading::score(&quiz, &[0], &original), 0.0);
+
+        let scoring = crate::model::Scoring {
+            negative_marking: 1.0,
+            ..Default::default()
+        };
+        let quiz = QuizTable { scoring, ..quiz };
+        assert_eq!(crate::grading::score(&quiz, &[0], &original), -1.0);
+    }
+}